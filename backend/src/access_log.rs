@@ -0,0 +1,163 @@
+use crate::logging::RotatingFile;
+use crate::proxy;
+use axum::body::{boxed, Bytes, HttpBody};
+use axum::http::header::{self, HeaderName};
+use axum::http::{HeaderMap, Request};
+use axum::middleware::Next;
+use axum::response::Response;
+use chrono::{DateTime, Local};
+use once_cell::sync::OnceCell;
+use serde_json::json;
+use std::io::Write;
+use std::time::Instant;
+
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+pub enum AccessLogFormat {
+    /// Common Log Format
+    Common,
+    /// Combined Log Format, followed by the duration in seconds
+    Combined,
+    Json,
+}
+
+pub struct AccessLog {
+    pub format: AccessLogFormat,
+    /// Written to stdout if not given
+    pub file: Option<RotatingFile>,
+}
+
+static ACCESS_LOG: OnceCell<AccessLog> = OnceCell::new();
+
+pub fn init(access_log: AccessLog) {
+    ACCESS_LOG.set(access_log).ok();
+}
+
+impl AccessLog {
+    fn write(&self, line: &str) {
+        let result = match &self.file {
+            Some(file) => file.append(line.as_bytes()),
+            None => std::io::stdout().lock().write_all(line.as_bytes()),
+        };
+        if let Err(e) = result {
+            log::error!("failed to write access log: {}", e);
+        }
+    }
+}
+
+/// A request being served, written to the access log when dropped, that is
+/// once its response body has been sent or the client went away.
+struct Entry {
+    access_log: &'static AccessLog,
+    start: Instant,
+    time: DateTime<Local>,
+    ip: Option<String>,
+    user: Option<String>,
+    method: String,
+    uri: String,
+    version: String,
+    referer: Option<String>,
+    user_agent: Option<String>,
+    status: u16,
+    bytes: u64,
+}
+
+impl Entry {
+    fn sent(&mut self, len: usize) {
+        self.bytes += len as u64;
+    }
+
+    fn format(&self) -> String {
+        let duration = self.start.elapsed().as_secs_f64();
+        let dash = |value: &Option<String>| value.clone().unwrap_or_else(|| "-".to_string());
+        let request = format!("{} {} {}", self.method, self.uri, self.version);
+        let bytes = match self.bytes {
+            0 => "-".to_string(),
+            bytes => bytes.to_string(),
+        };
+        let common = format!(
+            "{} - {} [{}] \"{}\" {} {}",
+            dash(&self.ip),
+            dash(&self.user),
+            self.time.format("%d/%b/%Y:%H:%M:%S %z"),
+            escape(&request),
+            self.status,
+            bytes
+        );
+        match self.access_log.format {
+            AccessLogFormat::Common => format!("{}\n", common),
+            AccessLogFormat::Combined => format!(
+                "{} \"{}\" \"{}\" {:.3}\n",
+                common,
+                escape(&dash(&self.referer)),
+                escape(&dash(&self.user_agent)),
+                duration
+            ),
+            AccessLogFormat::Json => format!(
+                "{}\n",
+                json!({
+                    "time": self.time.to_rfc3339(),
+                    "ip": self.ip,
+                    "user": self.user,
+                    "method": self.method,
+                    "uri": self.uri,
+                    "version": self.version,
+                    "status": self.status,
+                    "bytes": self.bytes,
+                    "duration": duration,
+                    "referer": self.referer,
+                    "user_agent": self.user_agent,
+                })
+            ),
+        }
+    }
+}
+
+impl Drop for Entry {
+    fn drop(&mut self) {
+        self.access_log.write(&self.format());
+    }
+}
+
+/// Writes a line to the access log for every request, if it is enabled.
+pub async fn log<B>(req: Request<B>, next: Next<B>) -> Response {
+    let access_log = match ACCESS_LOG.get() {
+        Some(access_log) => access_log,
+        None => return next.run(req).await,
+    };
+    let headers = req.headers();
+    let mut entry = Entry {
+        access_log,
+        start: Instant::now(),
+        time: Local::now(),
+        ip: proxy::client_ip(req.extensions(), headers).map(|ip| ip.to_string()),
+        user: proxy::user(req.extensions(), headers),
+        method: req.method().to_string(),
+        uri: req.uri().to_string(),
+        version: format!("{:?}", req.version()),
+        referer: header_value(headers, header::REFERER),
+        user_agent: header_value(headers, header::USER_AGENT),
+        status: 0,
+        bytes: 0,
+    };
+
+    let response = next.run(req).await;
+    entry.status = response.status().as_u16();
+    response.map(|body| {
+        boxed(body.map_data(move |data: Bytes| {
+            entry.sent(data.len());
+            data
+        }))
+    })
+}
+
+fn header_value(headers: &HeaderMap, name: HeaderName) -> Option<String> {
+    headers
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.to_string())
+}
+
+/// Escapes a quoted field.
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
@@ -0,0 +1,605 @@
+use crate::{audit, limits, resolve_path, AppError};
+use axum::body::{Bytes, StreamBody};
+use axum::extract::Path;
+use axum::http::header;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use chrono::{Local, TimeZone};
+use common::{is_archive, DirDesc, DirEntry, ExtractStatus, FileType, JsonResponse};
+use futures::SinkExt;
+use once_cell::sync::{Lazy, OnceCell};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{BufReader, Read, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Component, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Extract jobs by the full path of the archive, finished jobs are kept until
+/// their outcome is queried, or for `FINISHED_JOB_TTL` if it never is.
+static JOBS: Lazy<Mutex<HashMap<PathBuf, Job>>> = Lazy::new(Default::default);
+
+const FINISHED_JOB_TTL: Duration = Duration::from_secs(60 * 60);
+
+struct Job {
+    status: ExtractStatus,
+    finished: Option<Instant>,
+}
+
+impl Job {
+    fn is_expired(&self) -> bool {
+        matches!(self.finished, Some(finished) if finished.elapsed() > FINISHED_JOB_TTL)
+    }
+}
+
+/// Limit on the uncompressed size of an archive being extracted, against zip
+/// bombs.
+static MAX_EXTRACT_SIZE: OnceCell<u64> = OnceCell::new();
+
+pub fn init(max_extract_size: u64) {
+    log::info!("max size of extracted archives: {} bytes", max_extract_size);
+    MAX_EXTRACT_SIZE.set(max_extract_size).ok();
+}
+
+#[derive(Clone, Copy)]
+enum Kind {
+    Zip,
+    Tar,
+    TarGz,
+}
+
+impl Kind {
+    fn of(file_name: &str) -> Option<Kind> {
+        let name = file_name.to_ascii_lowercase();
+        if name.ends_with(".zip") {
+            Some(Kind::Zip)
+        } else if name.ends_with(".tar") {
+            Some(Kind::Tar)
+        } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Some(Kind::TarGz)
+        } else {
+            None
+        }
+    }
+}
+
+struct Member {
+    /// Path in the archive, without leading or trailing slashes
+    name: String,
+    is_dir: bool,
+    size: u64,
+    modified: String,
+}
+
+/// Splits a request path that goes through an archive into the path of the
+/// archive and the path inside it, "dir/a.zip/docs/x.md" becomes
+/// ("dir/a.zip", "docs/x.md").
+pub fn split_path(path: &str) -> Option<(String, String)> {
+    let path = path.trim_matches('/');
+    let mut end = 0;
+    for segment in path.split('/') {
+        end += segment.len();
+        if is_archive(segment)
+            && resolve_path(&path[..end])
+                .map(|full_path| full_path.is_file())
+                .unwrap_or(false)
+        {
+            let inner = path[end..].trim_matches('/');
+            return Some((path[..end].to_string(), inner.to_string()));
+        }
+        end += 1;
+    }
+    None
+}
+
+/// Lists a directory inside an archive, directories that only exist
+/// implicitly as the prefix of member paths are listed too.
+pub async fn list_dir(archive_path: &str, inner: &str) -> Result<DirDesc, AppError> {
+    let full_path = resolve_path(archive_path)?;
+    let kind = Kind::of(archive_path).ok_or_else(|| AppError("not an archive".to_string()))?;
+    let members = tokio::task::spawn_blocking(move || members(&full_path, kind))
+        .await
+        .map_err(|e| AppError(format!("failed to read archive: {}", e)))?
+        .map_err(|e| AppError(format!("failed to read archive: {}", e)))?;
+
+    let prefix = if inner.is_empty() {
+        String::new()
+    } else {
+        format!("{}/", inner)
+    };
+    let mut found = inner.is_empty();
+    let mut children = BTreeMap::new();
+    for member in members {
+        let rest = match member.name.strip_prefix(&prefix) {
+            Some(rest) if !rest.is_empty() => rest,
+            _ => {
+                found |= member.name == inner;
+                continue;
+            }
+        };
+        found = true;
+
+        let entry = match rest.split_once('/') {
+            Some((dir, _)) if !children.contains_key(dir) => DirEntry {
+                file_name: dir.to_string(),
+                file_type: FileType::Directory,
+                file_size: 0,
+                last_accessed: String::new(),
+            },
+            Some(_) => continue,
+            None => DirEntry {
+                file_name: rest.to_string(),
+                file_type: if member.is_dir {
+                    FileType::Directory
+                } else {
+                    FileType::File
+                },
+                file_size: member.size,
+                last_accessed: member.modified,
+            },
+        };
+        children.insert(entry.file_name.clone(), entry);
+    }
+    if !found {
+        return Err(AppError(format!("not found in archive: {}", inner)));
+    }
+
+    Ok(DirDesc {
+        dir_name: format!("/{}/{}", archive_path, inner)
+            .trim_end_matches('/')
+            .to_string(),
+        descendants: children.into_values().collect(),
+        in_archive: true,
+    })
+}
+
+fn members(full_path: &std::path::Path, kind: Kind) -> Result<Vec<Member>, String> {
+    let file = File::open(full_path).map_err(|e| e.to_string())?;
+    let mut members = vec![];
+    match kind {
+        Kind::Zip => {
+            let mut archive = zip::ZipArchive::new(file).map_err(|e| e.to_string())?;
+            for i in 0..archive.len() {
+                let file = archive.by_index_raw(i).map_err(|e| e.to_string())?;
+                let modified = file.last_modified();
+                members.push(Member {
+                    name: normalize(file.name()),
+                    is_dir: file.is_dir(),
+                    size: file.size(),
+                    modified: format!(
+                        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+                        modified.year(),
+                        modified.month(),
+                        modified.day(),
+                        modified.hour(),
+                        modified.minute(),
+                        modified.second()
+                    ),
+                });
+            }
+        }
+        Kind::Tar | Kind::TarGz => {
+            let mut archive = tar_archive(file, kind);
+            for entry in archive.entries().map_err(|e| e.to_string())? {
+                let entry = entry.map_err(|e| e.to_string())?;
+                let header = entry.header();
+                let modified = header
+                    .mtime()
+                    .ok()
+                    .and_then(|mtime| Local.timestamp_opt(mtime as i64, 0).single())
+                    .map(|mtime| mtime.format("%Y-%m-%d %H:%M:%S").to_string())
+                    .unwrap_or_default();
+                members.push(Member {
+                    name: normalize(&entry.path().map_err(|e| e.to_string())?.to_string_lossy()),
+                    is_dir: header.entry_type().is_dir(),
+                    size: header.size().unwrap_or(0),
+                    modified,
+                });
+            }
+        }
+    }
+    members.retain(|member| !member.name.is_empty());
+    Ok(members)
+}
+
+fn tar_archive(file: File, kind: Kind) -> tar::Archive<Box<dyn Read + Send>> {
+    let reader: Box<dyn Read + Send> = match kind {
+        Kind::TarGz => Box::new(flate2::read::GzDecoder::new(BufReader::new(file))),
+        _ => Box::new(BufReader::new(file)),
+    };
+    tar::Archive::new(reader)
+}
+
+/// "./docs/x.md" => "docs/x.md", "docs/" => "docs"
+fn normalize(name: &str) -> String {
+    name.trim_start_matches("./").trim_matches('/').to_string()
+}
+
+/// Downloads a single member of an archive, decompressed on the fly.
+pub async fn member(Path(path): Path<String>) -> Result<Response, AppError> {
+    let (archive_path, inner) =
+        split_path(&path).ok_or_else(|| AppError(format!("not in an archive: {}", path)))?;
+    let full_path = resolve_path(&archive_path)?;
+    let kind = Kind::of(&archive_path).ok_or_else(|| AppError("not an archive".to_string()))?;
+
+    let (found_tx, found_rx) = tokio::sync::oneshot::channel();
+    let (mut body_tx, body_rx) = futures::channel::mpsc::channel::<std::io::Result<Bytes>>(4);
+    let name = inner.clone();
+    tokio::task::spawn_blocking(move || {
+        let mut send = |reader: &mut dyn Read| loop {
+            let mut buf = vec![0; 64 * 1024];
+            let chunk = match reader.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => {
+                    buf.truncate(n);
+                    Ok(Bytes::from(buf))
+                }
+                Err(e) => Err(e),
+            };
+            let failed = chunk.is_err();
+            // the client went away
+            if futures::executor::block_on(body_tx.send(chunk)).is_err() || failed {
+                break;
+            }
+        };
+
+        let file = match File::open(&full_path) {
+            Ok(file) => file,
+            Err(e) => return drop(found_tx.send(Err(e.to_string()))),
+        };
+        match kind {
+            Kind::Zip => {
+                let mut archive = match zip::ZipArchive::new(file) {
+                    Ok(archive) => archive,
+                    Err(e) => return drop(found_tx.send(Err(e.to_string()))),
+                };
+                let index = (0..archive.len()).find(|i| {
+                    archive
+                        .by_index_raw(*i)
+                        .map(|file| !file.is_dir() && normalize(file.name()) == name)
+                        .unwrap_or(false)
+                });
+                let mut member = match index.map(|i| archive.by_index(i)) {
+                    Some(Ok(member)) => member,
+                    Some(Err(e)) => return drop(found_tx.send(Err(e.to_string()))),
+                    None => return drop(found_tx.send(Err("no such file".to_string()))),
+                };
+                let _ = found_tx.send(Ok(member.size()));
+                send(&mut member);
+            }
+            Kind::Tar | Kind::TarGz => {
+                let mut archive = tar_archive(file, kind);
+                let entries = match archive.entries() {
+                    Ok(entries) => entries,
+                    Err(e) => return drop(found_tx.send(Err(e.to_string()))),
+                };
+                for entry in entries {
+                    let mut entry = match entry {
+                        Ok(entry) => entry,
+                        Err(e) => return drop(found_tx.send(Err(e.to_string()))),
+                    };
+                    let matches = entry
+                        .path()
+                        .map(|path| normalize(&path.to_string_lossy()) == name)
+                        .unwrap_or(false);
+                    if matches && entry.header().entry_type().is_file() {
+                        let _ = found_tx.send(Ok(entry.size()));
+                        send(&mut entry);
+                        return;
+                    }
+                }
+                let _ = found_tx.send(Err("no such file".to_string()));
+            }
+        }
+    });
+
+    let size = found_rx
+        .await
+        .map_err(|e| AppError(format!("failed to read archive: {}", e)))?
+        .map_err(|e| AppError(format!("failed to read {} in archive: {}", inner, e)))?;
+    let content_type = mime_guess::from_path(&inner)
+        .first_or_octet_stream()
+        .to_string();
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, content_type),
+            (header::CONTENT_LENGTH, size.to_string()),
+        ],
+        StreamBody::new(body_rx),
+    )
+        .into_response())
+}
+
+/// Starts extracting an archive into a new directory next to it, named after
+/// the archive. The archive is unpacked into a hidden directory first which is
+/// renamed once complete, members with paths escaping the target directory
+/// fail the job, and links are skipped. The job is recorded in the audit log
+/// once it is done.
+pub async fn extract(
+    client: audit::Client,
+    Path(path): Path<String>,
+) -> Result<Json<JsonResponse>, AppError> {
+    let result = start_extract(&path, client.clone()).await;
+    if result.is_err() {
+        client.record(audit::Operation::Extract, &path, None, &result);
+    }
+    result
+}
+
+async fn start_extract(path: &str, client: audit::Client) -> Result<Json<JsonResponse>, AppError> {
+    let full_path = resolve_path(path)?;
+    let file_name = full_path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let kind = match Kind::of(&file_name) {
+        Some(kind) if full_path.is_file() => kind,
+        _ => return Err(AppError(format!("not an archive: {}", path))),
+    };
+
+    let parent = full_path.parent().unwrap().to_path_buf();
+    let lower = file_name.to_ascii_lowercase();
+    let stem_len = [".tar.gz", ".tgz", ".tar", ".zip"]
+        .iter()
+        .find(|ext| lower.ends_with(*ext))
+        .map(|ext| file_name.len() - ext.len())
+        .unwrap_or(file_name.len());
+    let stem = &file_name[..stem_len];
+
+    // extracted files count against the quotas and free space like uploads
+    let guard = limits::UploadGuard::new(&parent, None, client.user())
+        .await
+        .map_err(|e| AppError(e.message().to_string()))?;
+    let mut extraction = Extraction {
+        guard,
+        size: 0,
+        max_size: MAX_EXTRACT_SIZE.get().copied().unwrap_or(u64::MAX),
+    };
+
+    let mut jobs = JOBS.lock().unwrap();
+    jobs.retain(|_, job| !job.is_expired());
+    if matches!(jobs.get(&full_path), Some(job) if job.finished.is_none()) {
+        return Err(AppError(format!("{} is already being extracted", path)));
+    }
+    let target_name = (0..)
+        .map(|i| match i {
+            0 => stem.to_string(),
+            i => format!("{}-{}", stem, i),
+        })
+        .find(|name| {
+            !parent.join(name).exists() && !parent.join(format!(".{}.part", name)).exists()
+        })
+        .unwrap();
+    let request_path = format!("/{}", path.trim_start_matches('/'));
+    let target = format!(
+        "{}/{}",
+        &request_path[..request_path.rfind('/').unwrap_or(0)],
+        target_name
+    );
+    jobs.insert(
+        full_path.clone(),
+        Job {
+            status: ExtractStatus {
+                target: target.clone(),
+                running: true,
+                error: None,
+                entries: 0,
+            },
+            finished: None,
+        },
+    );
+    drop(jobs);
+
+    log::info!(
+        "extracting {:?} into {:?}",
+        full_path,
+        parent.join(&target_name)
+    );
+    tokio::task::spawn_blocking(move || {
+        let partial_dir = parent.join(format!(".{}.part", target_name));
+        let result = std::fs::create_dir(&partial_dir)
+            .map_err(|e| e.to_string())
+            .and_then(|_| unpack(&full_path, kind, &partial_dir, &mut extraction))
+            .and_then(|_| {
+                std::fs::rename(&partial_dir, parent.join(&target_name)).map_err(|e| e.to_string())
+            });
+        if result.is_ok() {
+            limits::record_upload(client.user(), &parent.join(&target_name));
+        }
+        if let Err(e) = &result {
+            log::warn!("failed to extract {:?}: {}", full_path, e);
+            let _ = std::fs::remove_dir_all(&partial_dir);
+        }
+        client.record(audit::Operation::Extract, &request_path, None, &result);
+
+        if let Some(job) = JOBS.lock().unwrap().get_mut(&full_path) {
+            job.status.running = false;
+            job.status.error = result.err();
+            job.finished = Some(Instant::now());
+        }
+    });
+
+    Ok(Json(JsonResponse::Succeeded { msg: Some(target) }))
+}
+
+/// Progress of the last extract job of an archive. The outcome of a finished
+/// job is only reported once, the job is forgotten afterwards.
+pub async fn extract_status(Path(path): Path<String>) -> Result<Json<ExtractStatus>, AppError> {
+    let full_path = resolve_path(&path)?;
+    let mut jobs = JOBS.lock().unwrap();
+    let job = jobs
+        .get(&full_path)
+        .ok_or_else(|| AppError(format!("{} is not being extracted", path)))?;
+    let status = job.status.clone();
+    if job.finished.is_some() {
+        jobs.remove(&full_path);
+    }
+    Ok(Json(status))
+}
+
+/// Maps a member path to a path relative to the target directory, `None` if
+/// it is absolute or goes up with "..", the zip-slip attack. Backslashes of
+/// archives made on Windows separate directories too.
+fn safe_path(name: &str) -> Option<PathBuf> {
+    let name = name.replace('\\', "/");
+    let mut path = PathBuf::new();
+    for component in std::path::Path::new(&name).components() {
+        match component {
+            // a drive, as in C:/Windows
+            Component::Normal(part) if path.as_os_str().is_empty() && is_drive(part) => {
+                return None
+            }
+            Component::Normal(part) => path.push(part),
+            Component::CurDir => {}
+            _ => return None,
+        }
+    }
+    Some(path)
+}
+
+fn is_drive(part: &std::ffi::OsStr) -> bool {
+    let part = part.as_bytes();
+    part.len() == 2 && part[0].is_ascii_alphabetic() && part[1] == b':'
+}
+
+/// Accounts for the files of an archive being extracted.
+struct Extraction {
+    guard: limits::UploadGuard,
+    /// Uncompressed bytes written so far
+    size: u64,
+    max_size: u64,
+}
+
+fn unpack(
+    full_path: &std::path::Path,
+    kind: Kind,
+    target: &std::path::Path,
+    extraction: &mut Extraction,
+) -> Result<(), String> {
+    let progress = || {
+        if let Some(job) = JOBS.lock().unwrap().get_mut(full_path) {
+            job.status.entries += 1;
+        }
+    };
+    let unsafe_path = |name: &str| format!("refusing to extract {}: outside of the target", name);
+
+    let file = File::open(full_path).map_err(|e| e.to_string())?;
+    match kind {
+        Kind::Zip => {
+            let mut archive = zip::ZipArchive::new(file).map_err(|e| e.to_string())?;
+            for i in 0..archive.len() {
+                let mut member = archive.by_index(i).map_err(|e| e.to_string())?;
+                let path = safe_path(member.name()).ok_or_else(|| unsafe_path(member.name()))?;
+                let mode = member.unix_mode();
+                // symbolic links could point anywhere
+                if mode
+                    .map(|mode| mode & 0o170000 == 0o120000)
+                    .unwrap_or(false)
+                {
+                    log::debug!("skipping symbolic link: {}", member.name());
+                    continue;
+                }
+                if member.is_dir() {
+                    std::fs::create_dir_all(target.join(&path)).map_err(|e| e.to_string())?;
+                } else {
+                    write_file(&target.join(&path), &mut member, mode, extraction)?;
+                }
+                progress();
+            }
+        }
+        Kind::Tar | Kind::TarGz => {
+            let mut archive = tar_archive(file, kind);
+            for entry in archive.entries().map_err(|e| e.to_string())? {
+                let mut entry = entry.map_err(|e| e.to_string())?;
+                let name = entry
+                    .path()
+                    .map_err(|e| e.to_string())?
+                    .to_string_lossy()
+                    .to_string();
+                let path = safe_path(&name).ok_or_else(|| unsafe_path(&name))?;
+                let entry_type = entry.header().entry_type();
+                let mode = entry.header().mode().ok();
+                if entry_type.is_dir() {
+                    std::fs::create_dir_all(target.join(&path)).map_err(|e| e.to_string())?;
+                } else if entry_type.is_file() {
+                    write_file(&target.join(&path), &mut entry, mode, extraction)?;
+                } else {
+                    log::debug!("skipping {:?} entry: {}", entry_type, name);
+                    continue;
+                }
+                progress();
+            }
+        }
+    }
+    Ok(())
+}
+
+fn write_file(
+    path: &std::path::Path,
+    reader: &mut dyn Read,
+    mode: Option<u32>,
+    extraction: &mut Extraction,
+) -> Result<(), String> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+    }
+    let mut file = File::create(path).map_err(|e| e.to_string())?;
+    extraction.guard.start_file();
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        let len = match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(len) => len,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.to_string()),
+        };
+        extraction.size += len as u64;
+        if extraction.size > extraction.max_size {
+            return Err(format!(
+                "the archive is larger than the limit of {} bytes when extracted",
+                extraction.max_size
+            ));
+        }
+        extraction
+            .guard
+            .add(len as u64)
+            .map_err(|e| e.message().to_string())?;
+        file.write_all(&buf[..len]).map_err(|e| e.to_string())?;
+    }
+    extraction.guard.file_saved();
+    if let Some(mode) = mode {
+        // no setuid/setgid bits from untrusted archives
+        let _ = file.set_permissions(std::fs::Permissions::from_mode(mode & 0o777));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_relative_member_paths() {
+        assert_eq!(safe_path("a/b.txt"), Some(PathBuf::from("a/b.txt")));
+        assert_eq!(safe_path("./a/./b.txt"), Some(PathBuf::from("a/b.txt")));
+        assert_eq!(safe_path("dir/"), Some(PathBuf::from("dir")));
+        assert_eq!(safe_path("a\\b.txt"), Some(PathBuf::from("a/b.txt")));
+        assert_eq!(safe_path("notes:1.txt"), Some(PathBuf::from("notes:1.txt")));
+    }
+
+    #[test]
+    fn rejects_member_paths_outside_of_the_target() {
+        assert_eq!(safe_path("../x"), None);
+        assert_eq!(safe_path("a/../../x"), None);
+        assert_eq!(safe_path("a/.."), None);
+        assert_eq!(safe_path("/etc/passwd"), None);
+        assert_eq!(safe_path("..\\x"), None);
+        assert_eq!(safe_path("\\Windows\\x"), None);
+        assert_eq!(safe_path("C:\\Windows\\x"), None);
+        assert_eq!(safe_path("c:/x"), None);
+        assert_eq!(safe_path("C:"), None);
+    }
+}
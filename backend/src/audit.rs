@@ -0,0 +1,337 @@
+use crate::logging::{rotate, rotated_file, ROTATED_FILES};
+use crate::{proxy, AppError};
+use axum::async_trait;
+use axum::extract::{FromRequest, Query, RequestParts};
+use axum::response::Response;
+use axum::Json;
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, TimeZone};
+use common::{AuditEntry, JsonResponse};
+use once_cell::sync::OnceCell;
+use serde::Deserialize;
+use std::convert::Infallible;
+use std::fs::OpenOptions;
+use std::io::{BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+/// Entries returned by a query if no limit is given.
+const DEFAULT_QUERY_LIMIT: usize = 500;
+
+pub struct AuditConfig {
+    pub file: PathBuf,
+    /// The file is rotated once it grows larger than this, in bytes
+    pub max_size: u64,
+}
+
+static CONFIG: OnceCell<AuditConfig> = OnceCell::new();
+
+/// Serializes writes and rotation.
+static LOCK: Mutex<()> = Mutex::new(());
+
+pub fn init(config: AuditConfig) {
+    log::info!("audit log: {:?}", config.file);
+    CONFIG.set(config).ok();
+}
+
+#[derive(Clone, Copy)]
+pub enum Operation {
+    Upload,
+    Mkdir,
+    Delete,
+    Edit,
+    Extract,
+    Share,
+    Unshare,
+    DropBox,
+    RevokeDropBox,
+}
+
+impl Operation {
+    fn name(self) -> &'static str {
+        match self {
+            Operation::Upload => "upload",
+            Operation::Mkdir => "mkdir",
+            Operation::Delete => "delete",
+            Operation::Edit => "edit",
+            Operation::Extract => "extract",
+            Operation::Share => "share",
+            Operation::Unshare => "unshare",
+            Operation::DropBox => "dropbox",
+            Operation::RevokeDropBox => "revoke_dropbox",
+        }
+    }
+}
+
+/// Results of handlers that can tell whether the operation succeeded.
+pub trait Outcome {
+    /// The error message if the operation failed.
+    fn error(&self) -> Option<String>;
+}
+
+impl Outcome for Response {
+    fn error(&self) -> Option<String> {
+        let status = self.status();
+        (!status.is_success()).then(|| status.to_string())
+    }
+}
+
+impl<T> Outcome for Json<T> {
+    fn error(&self) -> Option<String> {
+        None
+    }
+}
+
+impl Outcome for JsonResponse {
+    fn error(&self) -> Option<String> {
+        match self {
+            JsonResponse::Succeeded { .. } => None,
+            JsonResponse::Failed { msg } => Some(msg.clone().unwrap_or_default()),
+        }
+    }
+}
+
+impl Outcome for Result<(), String> {
+    fn error(&self) -> Option<String> {
+        self.as_ref().err().cloned()
+    }
+}
+
+impl<T: Outcome> Outcome for Result<T, AppError> {
+    fn error(&self) -> Option<String> {
+        match self {
+            Ok(value) => value.error(),
+            Err(e) => Some(e.0.clone()),
+        }
+    }
+}
+
+/// The client a request came from, for handlers to record what it did.
+#[derive(Clone)]
+pub struct Client {
+    ip: Option<String>,
+    user: Option<String>,
+}
+
+#[async_trait]
+impl<B: Send> FromRequest<B> for Client {
+    type Rejection = Infallible;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        let ip = proxy::client_ip(req.extensions(), req.headers()).map(|ip| ip.to_string());
+        let user = proxy::user(req.extensions(), req.headers());
+        Ok(Client { ip, user })
+    }
+}
+
+impl Client {
+    /// The authenticated user, if the server runs behind a proxy telling it.
+    pub fn user(&self) -> Option<&str> {
+        self.user.as_deref()
+    }
+
+    /// Appends an entry to the audit log, if it is enabled.
+    pub fn record(
+        &self,
+        operation: Operation,
+        path: &str,
+        size: Option<u64>,
+        outcome: &impl Outcome,
+    ) {
+        let config = match CONFIG.get() {
+            Some(config) => config,
+            None => return,
+        };
+        let error = outcome.error();
+        let entry = AuditEntry {
+            time: Local::now().to_rfc3339(),
+            ip: self.ip.clone(),
+            user: self.user.clone(),
+            operation: operation.name().to_string(),
+            path: format!("/{}", path.trim_start_matches('/')),
+            size,
+            success: error.is_none(),
+            error,
+        };
+        if let Err(e) = append(config, &entry) {
+            log::error!("failed to write audit log {:?}: {}", config.file, e);
+        }
+    }
+
+    /// Records an operation only if it failed, for handlers that record the
+    /// details of a success themselves.
+    pub fn record_failure(&self, operation: Operation, path: &str, outcome: &impl Outcome) {
+        if outcome.error().is_some() {
+            self.record(operation, path, None, outcome);
+        }
+    }
+}
+
+fn append(config: &AuditConfig, entry: &AuditEntry) -> std::io::Result<()> {
+    let mut line = serde_json::to_string(entry)?;
+    line.push('\n');
+
+    let _lock = LOCK.lock().unwrap();
+    let size = std::fs::metadata(&config.file)
+        .map(|metadata| metadata.len())
+        .unwrap_or(0);
+    if size > 0 && size + line.len() as u64 > config.max_size {
+        rotate(&config.file)?;
+    }
+    // opened for every entry, so that the log can also be rotated externally
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&config.file)?
+        .write_all(line.as_bytes())
+}
+
+#[cfg(test)]
+impl Client {
+    /// A client known only by its user, for the tests of other modules.
+    pub fn with_user(user: Option<&str>) -> Self {
+        Client {
+            ip: None,
+            user: user.map(|user| user.to_string()),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct AuditQuery {
+    user: Option<String>,
+    /// Matches entries whose path contains it
+    path: Option<String>,
+    /// "YYYY-MM-DD" or "YYYY-MM-DDTHH:MM" in local time
+    since: Option<String>,
+    until: Option<String>,
+    limit: Option<usize>,
+}
+
+/// Searches the audit log, newest entries first.
+pub async fn query(Query(query): Query<AuditQuery>) -> Result<Json<Vec<AuditEntry>>, AppError> {
+    let config = CONFIG
+        .get()
+        .ok_or_else(|| AppError("the audit log is not enabled".to_string()))?;
+    let since = parse_time(query.since.as_deref())?;
+    let until = parse_time(query.until.as_deref())?;
+    let user = query.user.filter(|user| !user.is_empty());
+    let path = query.path.filter(|path| !path.is_empty());
+    let limit = query.limit.unwrap_or(DEFAULT_QUERY_LIMIT);
+
+    let matches = move |entry: &AuditEntry| {
+        let time = DateTime::parse_from_rfc3339(&entry.time).ok();
+        user.as_ref()
+            .map(|user| entry.user.as_ref() == Some(user))
+            .unwrap_or(true)
+            && path
+                .as_ref()
+                .map(|path| entry.path.contains(path.as_str()))
+                .unwrap_or(true)
+            && since
+                .map(|since| time.map(|time| time >= since).unwrap_or(false))
+                .unwrap_or(true)
+            && until
+                .map(|until| time.map(|time| time < until).unwrap_or(false))
+                .unwrap_or(true)
+    };
+
+    let files = std::iter::once(config.file.clone())
+        .chain((1..=ROTATED_FILES).map(|index| rotated_file(&config.file, index)))
+        .collect::<Vec<_>>();
+    let entries = tokio::task::spawn_blocking(move || {
+        let mut entries = vec![];
+        for file in files {
+            let file = match std::fs::File::open(&file) {
+                Ok(file) => file,
+                Err(_) => continue,
+            };
+            let mut file_entries = BufReader::new(file)
+                .lines()
+                .map_while(Result::ok)
+                .filter_map(|line| serde_json::from_str::<AuditEntry>(&line).ok())
+                .filter(&matches)
+                .collect::<Vec<_>>();
+            file_entries.reverse();
+            entries.extend(file_entries);
+            if entries.len() >= limit {
+                break;
+            }
+        }
+        entries.truncate(limit);
+        entries
+    })
+    .await
+    .map_err(|e| AppError(format!("failed to read the audit log: {}", e)))?;
+    Ok(Json(entries))
+}
+
+fn parse_time(value: Option<&str>) -> Result<Option<DateTime<Local>>, AppError> {
+    let value = match value.map(str::trim).filter(|value| !value.is_empty()) {
+        Some(value) => value,
+        None => return Ok(None),
+    };
+    let time = NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M")
+        .or_else(|_| NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S"))
+        .ok()
+        .or_else(|| {
+            NaiveDate::parse_from_str(value, "%Y-%m-%d")
+                .ok()
+                .and_then(|date| date.and_hms_opt(0, 0, 0))
+        })
+        .and_then(|time| Local.from_local_datetime(&time).earliest())
+        .ok_or_else(|| AppError(format!("invalid time: {}", value)))?;
+    Ok(Some(time))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(path: &str) -> AuditEntry {
+        AuditEntry {
+            time: "2024-01-01T00:00:00+00:00".to_string(),
+            ip: Some("10.0.0.1".to_string()),
+            user: None,
+            operation: "upload".to_string(),
+            path: path.to_string(),
+            size: Some(1),
+            success: true,
+            error: None,
+        }
+    }
+
+    fn paths(file: &std::path::Path) -> Vec<String> {
+        std::fs::read_to_string(file)
+            .unwrap_or_default()
+            .lines()
+            .map(|line| serde_json::from_str::<AuditEntry>(line).unwrap().path)
+            .collect()
+    }
+
+    #[test]
+    fn rotates_full_logs() {
+        let dir = std::env::temp_dir().join(format!("fileserver-rs-audit-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let line_len = serde_json::to_string(&entry("/00")).unwrap().len() as u64 + 1;
+        let config = AuditConfig {
+            file: dir.join("audit.log"),
+            // two entries fit
+            max_size: 2 * line_len,
+        };
+
+        for index in 10..(2 * ROTATED_FILES + 15) {
+            append(&config, &entry(&format!("/{}", index))).unwrap();
+        }
+        assert_eq!(paths(&config.file), ["/24"]);
+        assert_eq!(paths(&rotated_file(&config.file, 1)), ["/22", "/23"]);
+        assert_eq!(paths(&rotated_file(&config.file, 2)), ["/20", "/21"]);
+        // the oldest entries were dropped
+        assert_eq!(
+            paths(&rotated_file(&config.file, ROTATED_FILES)),
+            ["/14", "/15"]
+        );
+        assert!(!rotated_file(&config.file, ROTATED_FILES + 1).exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,218 @@
+use crate::{edit, resolve_path};
+use axum::http::{header, HeaderMap, HeaderValue, Method, Request, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use chrono::{DateTime, Utc};
+use common::{DirDesc, FileType};
+use once_cell::sync::OnceCell;
+use percent_encoding::percent_decode_str;
+use sha2::{Digest, Sha256};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// `Cache-Control` of files served from /api/static.
+static STATIC_CACHE_CONTROL: OnceCell<HeaderValue> = OnceCell::new();
+
+pub fn init(static_cache_control: Option<HeaderValue>) {
+    if let Some(value) = static_cache_control {
+        log::info!("cache control of static files: {:?}", value);
+        STATIC_CACHE_CONTROL.set(value).ok();
+    }
+}
+
+/// Whether `If-None-Match` lists `etag`, compared weakly as GET requests
+/// are.
+pub fn none_match(headers: &HeaderMap, etag: &str) -> bool {
+    let etag = etag.trim_start_matches("W/");
+    headers
+        .get_all(header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|tag| tag.trim().trim_start_matches("W/"))
+        .any(|tag| tag == "*" || tag == etag)
+}
+
+fn http_date(time: SystemTime) -> String {
+    DateTime::<Utc>::from(time)
+        .format("%a, %d %b %Y %H:%M:%S GMT")
+        .to_string()
+}
+
+fn nanos(time: Option<SystemTime>) -> u128 {
+    time.and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .unwrap_or_default()
+        .as_nanos()
+}
+
+/// ETag of a listing, derived from the mtime of the directory and the name,
+/// type, size and mtime of each entry, so that changes to files in it, which
+/// don't touch the directory, are noticed too. Access times are left out, as
+/// reading a file changes them. `entry_mtimes` goes along with
+/// `dir_desc.descendants`, missing ones are covered by `modified`.
+fn listing_etag(
+    dir_desc: &DirDesc,
+    modified: Option<SystemTime>,
+    entry_mtimes: &[Option<SystemTime>],
+) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(dir_desc.dir_name.as_bytes());
+    for (index, entry) in dir_desc.descendants.iter().enumerate() {
+        let file_type: u8 = match entry.file_type {
+            FileType::File => 0,
+            FileType::Directory => 1,
+            FileType::SymbolicLink => 2,
+        };
+        let mtime = nanos(entry_mtimes.get(index).copied().flatten());
+        hasher.update((entry.file_name.len() as u64).to_le_bytes());
+        hasher.update(entry.file_name.as_bytes());
+        hasher.update([file_type]);
+        hasher.update(entry.file_size.to_le_bytes());
+        hasher.update(mtime.to_le_bytes());
+    }
+    let digest = format!("{:x}", hasher.finalize());
+    format!("\"{:x}-{}\"", nanos(modified), &digest[..16])
+}
+
+/// Answers with a listing, or with 304 if the client's copy is still
+/// current, see `listing_etag`.
+pub fn listing(
+    headers: &HeaderMap,
+    dir_desc: &DirDesc,
+    modified: Option<SystemTime>,
+    entry_mtimes: &[Option<SystemTime>],
+) -> Response {
+    let etag = listing_etag(dir_desc, modified, entry_mtimes);
+    let mut response = if none_match(headers, &etag) {
+        StatusCode::NOT_MODIFIED.into_response()
+    } else {
+        match serde_json::to_vec(dir_desc) {
+            Ok(body) => ([(header::CONTENT_TYPE, "application/json")], body).into_response(),
+            Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+        }
+    };
+    let response_headers = response.headers_mut();
+    // cached, but always revalidated
+    response_headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+    if let Ok(etag) = HeaderValue::from_str(&etag) {
+        response_headers.insert(header::ETAG, etag);
+    }
+    if let Some(modified) = modified {
+        if let Ok(modified) = HeaderValue::from_str(&http_date(modified)) {
+            response_headers.insert(header::LAST_MODIFIED, modified);
+        }
+    }
+    response
+}
+
+/// Adds an ETag and the configured `Cache-Control` to files served from
+/// /api/static, answering with 304 if the client's copy is still current.
+/// `Last-Modified` and `If-Modified-Since` are handled by `ServeDir`.
+pub async fn static_files<B>(req: Request<B>, next: Next<B>) -> Response {
+    let etag = if matches!(*req.method(), Method::GET | Method::HEAD) {
+        let path = percent_decode_str(req.uri().path()).decode_utf8_lossy();
+        match resolve_path(&path) {
+            Ok(full_path) => tokio::fs::metadata(full_path)
+                .await
+                .ok()
+                .filter(|metadata| metadata.is_file())
+                .map(|metadata| edit::etag(&metadata)),
+            Err(_) => None,
+        }
+    } else {
+        None
+    };
+
+    let mut response = match &etag {
+        Some(etag) if none_match(req.headers(), etag) => StatusCode::NOT_MODIFIED.into_response(),
+        _ => next.run(req).await,
+    };
+    let status = response.status();
+    if status.is_success() || status == StatusCode::NOT_MODIFIED {
+        let headers = response.headers_mut();
+        if let Some(etag) = etag.and_then(|etag| HeaderValue::from_str(&etag).ok()) {
+            headers.insert(header::ETAG, etag);
+        }
+        if let Some(cache_control) = STATIC_CACHE_CONTROL.get() {
+            headers.insert(header::CACHE_CONTROL, cache_control.clone());
+        }
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::DirEntry;
+    use std::time::Duration;
+
+    fn if_none_match(values: &[&str]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for value in values {
+            headers.append(header::IF_NONE_MATCH, HeaderValue::from_str(value).unwrap());
+        }
+        headers
+    }
+
+    fn dir_desc(file_size: u64, last_accessed: &str) -> DirDesc {
+        DirDesc {
+            dir_name: "/dir".to_string(),
+            descendants: vec![DirEntry {
+                file_name: "a.txt".to_string(),
+                file_type: FileType::File,
+                file_size,
+                last_accessed: last_accessed.to_string(),
+            }],
+            in_archive: false,
+        }
+    }
+
+    fn time(secs: u64) -> Option<SystemTime> {
+        Some(UNIX_EPOCH + Duration::from_secs(secs))
+    }
+
+    #[test]
+    fn matches_etags_weakly() {
+        assert!(none_match(&if_none_match(&["\"abc\""]), "\"abc\""));
+        assert!(none_match(&if_none_match(&["W/\"abc\""]), "\"abc\""));
+        assert!(none_match(&if_none_match(&["\"abc\""]), "W/\"abc\""));
+        assert!(none_match(&if_none_match(&["\"x\", \"abc\""]), "\"abc\""));
+        assert!(none_match(&if_none_match(&["\"x\"", "\"abc\""]), "\"abc\""));
+        assert!(none_match(&if_none_match(&["*"]), "\"abc\""));
+        assert!(!none_match(&if_none_match(&["\"abcd\""]), "\"abc\""));
+        assert!(!none_match(&HeaderMap::new(), "\"abc\""));
+    }
+
+    #[test]
+    fn tags_listings_by_their_entries() {
+        let etag = listing_etag(&dir_desc(1, "2024-01-01 00:00:00"), time(1), &[time(2)]);
+        // reading a file doesn't change the listing
+        let accessed = listing_etag(&dir_desc(1, "2024-06-01 00:00:00"), time(1), &[time(2)]);
+        assert_eq!(etag, accessed);
+
+        let resized = listing_etag(&dir_desc(2, "2024-01-01 00:00:00"), time(1), &[time(2)]);
+        let modified = listing_etag(&dir_desc(1, "2024-01-01 00:00:00"), time(1), &[time(3)]);
+        let dir_modified = listing_etag(&dir_desc(1, "2024-01-01 00:00:00"), time(4), &[time(2)]);
+        assert_ne!(etag, resized);
+        assert_ne!(etag, modified);
+        assert_ne!(etag, dir_modified);
+    }
+
+    #[test]
+    fn answers_current_listings_with_not_modified() {
+        let dir_desc = dir_desc(1, "2024-01-01 00:00:00");
+        let response = listing(&HeaderMap::new(), &dir_desc, time(1), &[time(2)]);
+        assert_eq!(response.status(), StatusCode::OK);
+        let etag = response.headers()[header::ETAG]
+            .to_str()
+            .unwrap()
+            .to_string();
+        assert_eq!(response.headers()[header::CACHE_CONTROL], "no-cache");
+
+        let response = listing(&if_none_match(&[&etag]), &dir_desc, time(1), &[time(2)]);
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(response.headers()[header::ETAG], etag.as_str());
+
+        let response = listing(&if_none_match(&[&etag]), &dir_desc, time(1), &[time(3)]);
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+}
@@ -0,0 +1,266 @@
+use crate::{resolve_path, AppError};
+use axum::extract::{Path, Query};
+use axum::Json;
+use common::FileChecksum;
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+use sha2::Digest;
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::SystemTime;
+use tokio::sync::Semaphore;
+
+/// Max number of checksums kept in memory.
+const CACHE_SIZE: usize = 1024;
+
+/// Hashing large files is IO bound, limit how many run at once.
+static WORKERS: Semaphore = Semaphore::const_new(2);
+
+/// Checksums by path and algorithm, along with the mtime and size of the file
+/// they were computed for.
+#[allow(clippy::type_complexity)]
+static CACHE: Lazy<Mutex<HashMap<(PathBuf, Algo), (SystemTime, u64, String)>>> =
+    Lazy::new(Default::default);
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Algo {
+    Sha256,
+    Blake3,
+}
+
+impl Algo {
+    pub fn parse(name: &str) -> Option<Algo> {
+        match name.to_ascii_lowercase().as_str() {
+            "sha256" | "sha-256" => Some(Algo::Sha256),
+            "blake3" => Some(Algo::Blake3),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Algo::Sha256 => "sha256",
+            Algo::Blake3 => "blake3",
+        }
+    }
+}
+
+pub enum Hasher {
+    Sha256(sha2::Sha256),
+    Blake3(Box<blake3::Hasher>),
+}
+
+impl Hasher {
+    pub fn new(algo: Algo) -> Hasher {
+        match algo {
+            Algo::Sha256 => Hasher::Sha256(sha2::Sha256::new()),
+            Algo::Blake3 => Hasher::Blake3(Box::new(blake3::Hasher::new())),
+        }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        match self {
+            Hasher::Sha256(hasher) => hasher.update(data),
+            Hasher::Blake3(hasher) => {
+                hasher.update(data);
+            }
+        }
+    }
+
+    /// The checksum as lowercase hex.
+    pub fn finalize(self) -> String {
+        match self {
+            Hasher::Sha256(hasher) => format!("{:x}", hasher.finalize()),
+            Hasher::Blake3(hasher) => hasher.finalize().to_hex().to_string(),
+        }
+    }
+}
+
+/// A checksum a client expects an uploaded file to have, sent as
+/// `<algo>=<hex>`, e.g. "sha256=9f86d0...".
+#[derive(Clone)]
+pub struct Expected {
+    pub algo: Algo,
+    pub checksum: String,
+}
+
+impl Expected {
+    pub fn parse(value: &str) -> Result<Expected, AppError> {
+        let invalid = || AppError(format!("invalid checksum: {}", value));
+        let (algo, checksum) = value.trim().split_once('=').ok_or_else(invalid)?;
+        let algo = Algo::parse(algo).ok_or_else(invalid)?;
+        let checksum = checksum.trim().to_ascii_lowercase();
+        if checksum.len() != 64 || !checksum.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(invalid());
+        }
+        Ok(Expected { algo, checksum })
+    }
+}
+
+#[derive(Deserialize)]
+pub struct HashParams {
+    algo: Option<String>,
+}
+
+/// Computes the checksum of a file, SHA-256 unless `algo=blake3` is given.
+/// Checksums are cached until the file is modified.
+pub async fn hash(
+    Path(path): Path<String>,
+    Query(params): Query<HashParams>,
+) -> Result<Json<FileChecksum>, AppError> {
+    let algo_name = params.algo.unwrap_or_else(|| "sha256".to_string());
+    let algo = Algo::parse(&algo_name)
+        .ok_or_else(|| AppError(format!("unsupported algorithm: {}", algo_name)))?;
+    let full_path = resolve_path(&path)?;
+    let metadata = tokio::fs::metadata(&full_path)
+        .await
+        .map_err(|_| AppError(format!("file not found: {}", path)))?;
+    if !metadata.is_file() {
+        return Err(AppError(format!("not a file: {}", path)));
+    }
+    let mtime = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+    let size = metadata.len();
+
+    let key = (full_path.clone(), algo);
+    let cached = CACHE.lock().unwrap().get(&key).cloned();
+    let checksum = match cached {
+        Some((cached_mtime, cached_size, checksum))
+            if cached_mtime == mtime && cached_size == size =>
+        {
+            checksum
+        }
+        _ => {
+            let _permit = WORKERS.acquire().await;
+            let checksum = tokio::task::spawn_blocking(move || hash_file(&full_path, algo))
+                .await
+                .map_err(|e| AppError(format!("failed to hash {}: {}", path, e)))?
+                .map_err(|e| AppError(format!("failed to hash {}: {}", path, e)))?;
+            let mut cache = CACHE.lock().unwrap();
+            if cache.len() >= CACHE_SIZE {
+                cache.clear();
+            }
+            cache.insert(key, (mtime, size, checksum.clone()));
+            checksum
+        }
+    };
+
+    Ok(Json(FileChecksum {
+        path: format!("/{}", path.trim_start_matches('/')),
+        algo: algo.name().to_string(),
+        checksum,
+        size,
+    }))
+}
+
+fn hash_file(path: &std::path::Path, algo: Algo) -> std::io::Result<String> {
+    let mut file = std::fs::File::open(path)?;
+    let mut hasher = Hasher::new(algo);
+    let mut buf = vec![0; 256 * 1024];
+    loop {
+        match file.read(&mut buf)? {
+            0 => break,
+            n => hasher.update(&buf[..n]),
+        }
+    }
+    Ok(hasher.finalize())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{audit, limits, save_files};
+    use axum::body::Body;
+    use axum::extract::{FromRequest, Multipart, RequestParts};
+    use axum::http::{header, HeaderMap, HeaderValue, Request};
+
+    const TEST_SHA256: &str = "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08";
+    const EMPTY_BLAKE3: &str = "af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262";
+
+    #[test]
+    fn parses_expected_checksums() {
+        let expected = Expected::parse(&format!("sha256={}", TEST_SHA256.to_uppercase())).unwrap();
+        assert!(expected.algo == Algo::Sha256);
+        assert_eq!(expected.checksum, TEST_SHA256);
+        let expected = Expected::parse(&format!("SHA-256={}", TEST_SHA256)).unwrap();
+        assert!(expected.algo == Algo::Sha256);
+        let expected = Expected::parse(&format!("blake3={}", EMPTY_BLAKE3)).unwrap();
+        assert!(expected.algo == Algo::Blake3);
+        assert_eq!(expected.checksum, EMPTY_BLAKE3);
+
+        assert!(Expected::parse(TEST_SHA256).is_err());
+        assert!(Expected::parse(&format!("md5={}", TEST_SHA256)).is_err());
+        assert!(Expected::parse(&format!("sha256={}", &TEST_SHA256[1..])).is_err());
+        assert!(Expected::parse(&format!("sha256=x{}", &TEST_SHA256[1..])).is_err());
+    }
+
+    #[test]
+    fn hashes_data() {
+        let mut hasher = Hasher::new(Algo::Sha256);
+        hasher.update(b"te");
+        hasher.update(b"st");
+        assert_eq!(hasher.finalize(), TEST_SHA256);
+        assert_eq!(Hasher::new(Algo::Blake3).finalize(), EMPTY_BLAKE3);
+    }
+
+    /// Uploads "test" as `name` into `/checksum`, with `checksum` as the
+    /// expected checksum.
+    async fn upload(name: &str, checksum: &str) -> Result<(), AppError> {
+        let boundary = "checksum-test";
+        let body = format!(
+            "--{b}\r\n\
+             Content-Disposition: form-data; name=\"checksum\"\r\n\r\n\
+             sha256={checksum}\r\n\
+             --{b}\r\n\
+             Content-Disposition: form-data; name=\"file\"; filename=\"{name}\"\r\n\
+             Content-Type: text/plain\r\n\r\n\
+             test\r\n\
+             --{b}--\r\n",
+            b = boundary,
+            checksum = checksum,
+            name = name
+        );
+        let content_type = format!("multipart/form-data; boundary={}", boundary);
+        let request = Request::builder()
+            .method("POST")
+            .header(header::CONTENT_TYPE, &content_type)
+            .body(Body::from(body))
+            .unwrap();
+        let multipart = Multipart::from_request(&mut RequestParts::new(request))
+            .await
+            .unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_str(&content_type).unwrap(),
+        );
+
+        let dir = crate::test_serve_dir().join("checksum");
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut guard = limits::UploadGuard::new(&dir, None, None).await.unwrap();
+        let client = audit::Client::with_user(None);
+        save_files(&client, "/checksum", &mut guard, &headers, multipart, false)
+            .await
+            .map(|_| ())
+    }
+
+    fn files(prefix: &str) -> Vec<String> {
+        std::fs::read_dir(crate::test_serve_dir().join("checksum"))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .filter(|name| name.contains(prefix))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn verifies_uploads() {
+        upload("matching.txt", TEST_SHA256).await.unwrap();
+        assert_eq!(files("matching"), ["matching.txt"]);
+
+        let AppError(msg) = upload("mismatching.txt", EMPTY_BLAKE3).await.unwrap_err();
+        assert!(msg.contains("checksum mismatch"), "{}", msg);
+        // neither the file nor the partial file it was written to are left
+        assert!(files("mismatching").is_empty());
+    }
+}
@@ -0,0 +1,84 @@
+use axum::body::HttpBody;
+use axum::http::{header, HeaderValue, Request, Response, StatusCode};
+use axum::middleware::Next;
+use tower_http::compression::predicate::{And, Predicate, SizeAbove};
+use tower_http::compression::CompressionLayer;
+
+/// Responses smaller than this aren't worth compressing.
+const MIN_SIZE: u16 = 1024;
+
+/// Compresses responses with zstd, brotli, gzip or deflate, as negotiated
+/// with `Accept-Encoding`.
+pub fn layer() -> CompressionLayer<And<SizeAbove, Compressible>> {
+    CompressionLayer::new().compress_when(SizeAbove::new(MIN_SIZE).and(Compressible))
+}
+
+/// Listings, text and the wasm and js of the frontend, but neither media nor
+/// archives, which are compressed already, nor partial content, as ranges
+/// refer to the uncompressed file.
+#[derive(Clone, Copy)]
+pub struct Compressible;
+
+impl Predicate for Compressible {
+    fn should_compress<B>(&self, response: &Response<B>) -> bool
+    where
+        B: HttpBody,
+    {
+        response.status() != StatusCode::PARTIAL_CONTENT
+            && response
+                .headers()
+                .get(header::CONTENT_TYPE)
+                .and_then(|value| value.to_str().ok())
+                .map(is_compressible)
+                .unwrap_or(false)
+    }
+}
+
+fn is_compressible(content_type: &str) -> bool {
+    let essence = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    essence.starts_with("text/")
+        || essence.ends_with("+json")
+        || essence.ends_with("+xml")
+        || matches!(
+            essence.as_str(),
+            "application/json"
+                | "application/javascript"
+                | "application/wasm"
+                | "application/xml"
+                | "application/toml"
+                | "application/x-yaml"
+                | "application/x-sh"
+        )
+}
+
+/// Adds `Vary: Accept-Encoding` to responses that may be compressed, so that
+/// caches don't hand compressed responses to clients that can't decode them,
+/// and makes the ETags of compressed ones weak, as they no longer identify
+/// the bytes sent. Compressed files served as they are by `ServeDir` are
+/// covered too.
+pub async fn vary<B>(req: Request<B>, next: Next<B>) -> axum::response::Response {
+    let mut response = next.run(req).await;
+    let encoded = response.headers().contains_key(header::CONTENT_ENCODING);
+    if !encoded && !Compressible.should_compress(&response) {
+        return response;
+    }
+
+    let headers = response.headers_mut();
+    headers.append(header::VARY, HeaderValue::from_static("accept-encoding"));
+    if encoded {
+        let weak = headers
+            .get(header::ETAG)
+            .and_then(|etag| etag.to_str().ok())
+            .filter(|etag| !etag.starts_with("W/"))
+            .and_then(|etag| HeaderValue::from_str(&format!("W/{}", etag)).ok());
+        if let Some(weak) = weak {
+            headers.insert(header::ETAG, weak);
+        }
+    }
+    response
+}
@@ -0,0 +1,608 @@
+use crate::{resolve_path, AppError};
+use axum::extract::Path;
+use axum::Json;
+use common::{DocumentPreview, Sheet};
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use std::collections::HashMap;
+use std::io::Read;
+
+/// Documents larger than this are not previewed.
+const MAX_SIZE: u64 = 20 * 1024 * 1024;
+/// Limit on the uncompressed size of a single XML part, against zip bombs.
+const MAX_PART_SIZE: u64 = 64 * 1024 * 1024;
+const MAX_ROWS: usize = 1000;
+const MAX_COLUMNS: usize = 100;
+/// Repeat counts of OpenDocument rows and cells are clamped to this, which is
+/// still enough to notice that a sheet is truncated.
+const MAX_REPEAT: usize = MAX_ROWS + 1;
+
+/// Produces a preview of a document: the URL to embed for PDFs, which
+/// browsers can show natively, the text of word processor documents as simple
+/// HTML, and the cells of spreadsheets and CSV files as tables.
+pub async fn preview(Path(path): Path<String>) -> Result<Json<DocumentPreview>, AppError> {
+    let full_path = resolve_path(&path)?;
+    let metadata = tokio::fs::metadata(&full_path)
+        .await
+        .map_err(|_| AppError(format!("file not found: {}", path)))?;
+    if !metadata.is_file() {
+        return Err(AppError(format!("not a file: {}", path)));
+    }
+
+    let path = format!("/{}", path.trim_start_matches('/'));
+    let ext = path
+        .rsplit_once('.')
+        .map(|(_, ext)| ext.to_ascii_lowercase())
+        .unwrap_or_default();
+    if ext == "pdf" {
+        return Ok(Json(DocumentPreview::Pdf {
+            url: format!("/api/static{}", path),
+        }));
+    }
+    if metadata.len() > MAX_SIZE {
+        return Err(AppError(format!("too large to preview: {}", path)));
+    }
+
+    let preview = tokio::task::spawn_blocking(move || -> Result<DocumentPreview, String> {
+        Ok(match ext.as_str() {
+            "docx" => DocumentPreview::Html {
+                html: docx_to_html(&read_part(&full_path, "word/document.xml")?)?,
+            },
+            "odt" => DocumentPreview::Html {
+                html: odt_to_html(&read_part(&full_path, "content.xml")?)?,
+            },
+            "xlsx" => DocumentPreview::Sheets {
+                sheets: xlsx_sheets(&full_path)?,
+            },
+            "ods" => DocumentPreview::Sheets {
+                sheets: ods_sheets(&read_part(&full_path, "content.xml")?)?,
+            },
+            "csv" | "tsv" => DocumentPreview::Sheets {
+                sheets: vec![csv_sheet(
+                    &full_path,
+                    if ext == "tsv" { b'\t' } else { b',' },
+                )?],
+            },
+            _ => return Err("unsupported document type".to_string()),
+        })
+    })
+    .await
+    .map_err(|e| AppError(format!("failed to preview document: {}", e)))?
+    .map_err(|e| AppError(format!("failed to preview {}: {}", path, e)))?;
+
+    Ok(Json(preview))
+}
+
+/// Reads a file from the zip container of an OOXML or OpenDocument file.
+fn read_part(full_path: &std::path::Path, name: &str) -> Result<String, String> {
+    let file = std::fs::File::open(full_path).map_err(|e| e.to_string())?;
+    let mut archive = zip::ZipArchive::new(file).map_err(|e| e.to_string())?;
+    let part = archive
+        .by_name(name)
+        .map_err(|e| format!("{}: {}", name, e))?;
+    let mut xml = String::new();
+    part.take(MAX_PART_SIZE)
+        .read_to_string(&mut xml)
+        .map_err(|e| e.to_string())?;
+    Ok(xml)
+}
+
+fn attribute(e: &BytesStart, name: &str) -> Option<String> {
+    e.try_get_attribute(name)
+        .ok()
+        .flatten()
+        .and_then(|attr| attr.unescape_value().ok().map(|v| v.to_string()))
+}
+
+/// Escapes text for HTML content and double quoted attribute values.
+pub fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Paragraphs, headings and tables of `word/document.xml`.
+fn docx_to_html(xml: &str) -> Result<String, String> {
+    let mut reader = Reader::from_str(xml);
+    let mut html = String::new();
+    let mut para = String::new();
+    let mut heading = None;
+    let mut in_text = false;
+
+    loop {
+        match reader.read_event().map_err(|e| e.to_string())? {
+            Event::Start(e) => match e.local_name().as_ref() {
+                b"tbl" => html.push_str("<table>"),
+                b"tr" => html.push_str("<tr>"),
+                b"tc" => html.push_str("<td>"),
+                b"p" => {
+                    para.clear();
+                    heading = None;
+                }
+                b"t" => in_text = true,
+                _ => {}
+            },
+            Event::Empty(e) => match e.local_name().as_ref() {
+                b"pStyle" => {
+                    heading = attribute(&e, "w:val").and_then(|style| heading_level(&style))
+                }
+                b"tab" => para.push(' '),
+                b"br" => para.push_str("<br>"),
+                _ => {}
+            },
+            Event::Text(e) if in_text => {
+                para.push_str(&escape(&e.unescape().map_err(|e| e.to_string())?))
+            }
+            Event::End(e) => match e.local_name().as_ref() {
+                b"tbl" => html.push_str("</table>"),
+                b"tr" => html.push_str("</tr>"),
+                b"tc" => html.push_str("</td>"),
+                b"t" => in_text = false,
+                b"p" => push_paragraph(&mut html, &para, heading),
+                _ => {}
+            },
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    Ok(html)
+}
+
+/// "Heading2" => 2, "Title" => 1
+fn heading_level(style: &str) -> Option<u8> {
+    if style == "Title" {
+        return Some(1);
+    }
+    let level = style.strip_prefix("Heading")?.parse::<u8>().ok()?;
+    Some(level.clamp(1, 6))
+}
+
+fn push_paragraph(html: &mut String, para: &str, heading: Option<u8>) {
+    match heading {
+        Some(level) => html.push_str(&format!("<h{0}>{1}</h{0}>", level, para)),
+        None => html.push_str(&format!("<p>{}</p>", para)),
+    }
+}
+
+/// Paragraphs, headings and tables of an OpenDocument text `content.xml`.
+fn odt_to_html(xml: &str) -> Result<String, String> {
+    let mut reader = Reader::from_str(xml);
+    let mut html = String::new();
+    let mut para = String::new();
+    let mut heading = None;
+    // paragraphs nest, in frames and notes for example
+    let mut depth = 0;
+
+    loop {
+        match reader.read_event().map_err(|e| e.to_string())? {
+            Event::Start(e) => match e.local_name().as_ref() {
+                b"table" => html.push_str("<table>"),
+                b"table-row" => html.push_str("<tr>"),
+                b"table-cell" => html.push_str("<td>"),
+                b"h" | b"p" => {
+                    if depth == 0 {
+                        para.clear();
+                        heading = if e.local_name().as_ref() == b"h" {
+                            let level = attribute(&e, "text:outline-level")
+                                .and_then(|level| level.parse::<u8>().ok())
+                                .unwrap_or(1);
+                            Some(level.clamp(1, 6))
+                        } else {
+                            None
+                        };
+                    }
+                    depth += 1;
+                }
+                _ => {}
+            },
+            Event::Empty(e) => match e.local_name().as_ref() {
+                b"table-cell" => html.push_str("<td></td>"),
+                b"s" | b"tab" => para.push(' '),
+                b"line-break" => para.push_str("<br>"),
+                _ => {}
+            },
+            Event::Text(e) if depth > 0 => {
+                para.push_str(&escape(&e.unescape().map_err(|e| e.to_string())?))
+            }
+            Event::End(e) => match e.local_name().as_ref() {
+                b"table" => html.push_str("</table>"),
+                b"table-row" => html.push_str("</tr>"),
+                b"table-cell" => html.push_str("</td>"),
+                b"h" | b"p" => {
+                    depth -= 1;
+                    if depth == 0 {
+                        push_paragraph(&mut html, &para, heading);
+                    }
+                }
+                _ => {}
+            },
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    Ok(html)
+}
+
+/// Sheets of an Office Open XML workbook, with the names from
+/// `xl/workbook.xml` mapped to their parts through the workbook relationships.
+fn xlsx_sheets(full_path: &std::path::Path) -> Result<Vec<Sheet>, String> {
+    let shared_strings = match read_part(full_path, "xl/sharedStrings.xml") {
+        Ok(xml) => xlsx_shared_strings(&xml)?,
+        // workbooks with only numbers don't have any
+        Err(_) => vec![],
+    };
+
+    let mut targets = HashMap::new();
+    let rels = read_part(full_path, "xl/_rels/workbook.xml.rels")?;
+    let mut reader = Reader::from_str(&rels);
+    loop {
+        match reader.read_event().map_err(|e| e.to_string())? {
+            Event::Start(e) | Event::Empty(e) if e.local_name().as_ref() == b"Relationship" => {
+                if let (Some(id), Some(target)) = (attribute(&e, "Id"), attribute(&e, "Target")) {
+                    let target = match target.strip_prefix('/') {
+                        Some(absolute) => absolute.to_string(),
+                        None => format!("xl/{}", target),
+                    };
+                    targets.insert(id, target);
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+
+    let mut sheets = vec![];
+    let workbook = read_part(full_path, "xl/workbook.xml")?;
+    let mut reader = Reader::from_str(&workbook);
+    loop {
+        match reader.read_event().map_err(|e| e.to_string())? {
+            Event::Start(e) | Event::Empty(e) if e.local_name().as_ref() == b"sheet" => {
+                let name = attribute(&e, "name").unwrap_or_default();
+                let part = attribute(&e, "r:id").and_then(|id| targets.get(&id));
+                if let Some(part) = part {
+                    let xml = read_part(full_path, part)?;
+                    sheets.push(xlsx_sheet(name, &xml, &shared_strings)?);
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    Ok(sheets)
+}
+
+fn xlsx_shared_strings(xml: &str) -> Result<Vec<String>, String> {
+    let mut reader = Reader::from_str(xml);
+    let mut strings = vec![];
+    let mut in_text = false;
+    loop {
+        match reader.read_event().map_err(|e| e.to_string())? {
+            Event::Start(e) => match e.local_name().as_ref() {
+                b"si" => strings.push(String::new()),
+                b"t" => in_text = true,
+                _ => {}
+            },
+            Event::Text(e) if in_text => {
+                if let Some(string) = strings.last_mut() {
+                    string.push_str(&e.unescape().map_err(|e| e.to_string())?);
+                }
+            }
+            Event::End(e) if e.local_name().as_ref() == b"t" => in_text = false,
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    Ok(strings)
+}
+
+fn xlsx_sheet(name: String, xml: &str, shared_strings: &[String]) -> Result<Sheet, String> {
+    let mut reader = Reader::from_str(xml);
+    let mut sheet = Sheet {
+        name,
+        rows: vec![],
+        truncated: false,
+    };
+    let mut row = vec![];
+    let mut column = 0;
+    let mut cell_type = None;
+    let mut in_value = false;
+
+    loop {
+        match reader.read_event().map_err(|e| e.to_string())? {
+            Event::Start(e) => match e.local_name().as_ref() {
+                b"row" => row = vec![],
+                b"c" => {
+                    column = attribute(&e, "r")
+                        .and_then(|r| column_index(&r))
+                        .unwrap_or(row.len());
+                    cell_type = attribute(&e, "t");
+                }
+                b"v" | b"t" => in_value = true,
+                _ => {}
+            },
+            Event::Text(e) if in_value && column < MAX_COLUMNS => {
+                let text = e.unescape().map_err(|e| e.to_string())?;
+                let value = match cell_type.as_deref() {
+                    Some("s") => text
+                        .parse::<usize>()
+                        .ok()
+                        .and_then(|i| shared_strings.get(i))
+                        .cloned()
+                        .unwrap_or_default(),
+                    Some("b") => (if text == "1" { "TRUE" } else { "FALSE" }).to_string(),
+                    _ => text.to_string(),
+                };
+                if row.len() <= column {
+                    row.resize(column + 1, String::new());
+                }
+                row[column].push_str(&value);
+            }
+            Event::End(e) => match e.local_name().as_ref() {
+                b"v" | b"t" => in_value = false,
+                b"row" => {
+                    if sheet.rows.len() == MAX_ROWS {
+                        sheet.truncated = true;
+                        break;
+                    }
+                    sheet.rows.push(std::mem::take(&mut row));
+                }
+                _ => {}
+            },
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    Ok(sheet)
+}
+
+/// "C12" => 2
+fn column_index(cell_ref: &str) -> Option<usize> {
+    let letters = cell_ref
+        .bytes()
+        .take_while(|b| b.is_ascii_uppercase())
+        .collect::<Vec<_>>();
+    if letters.is_empty() {
+        return None;
+    }
+    let index = letters.iter().try_fold(0usize, |index, b| {
+        index.checked_mul(26)?.checked_add((b - b'A') as usize + 1)
+    })?;
+    Some(index - 1)
+}
+
+/// Sheets of an OpenDocument spreadsheet `content.xml`. Runs of identical
+/// rows and cells are stored once with a repeat count, which is expanded up to
+/// the row and column limits, trailing empty cells and rows are dropped.
+fn ods_sheets(xml: &str) -> Result<Vec<Sheet>, String> {
+    let mut reader = Reader::from_str(xml);
+    let mut sheets = vec![];
+    let mut row = vec![];
+    let mut row_repeat = 1;
+    let mut cell = String::new();
+    let mut cell_repeat = 1;
+    let mut in_cell = false;
+    let mut empty_rows = 0;
+
+    let repeat = |e: &BytesStart, name: &str| {
+        attribute(e, name)
+            .and_then(|n| n.parse::<usize>().ok())
+            .unwrap_or(1)
+            .min(MAX_REPEAT)
+    };
+
+    loop {
+        match reader.read_event().map_err(|e| e.to_string())? {
+            Event::Start(e) => match e.local_name().as_ref() {
+                b"table" => sheets.push(Sheet {
+                    name: attribute(&e, "table:name").unwrap_or_default(),
+                    rows: vec![],
+                    truncated: false,
+                }),
+                b"table-row" => {
+                    row = vec![];
+                    row_repeat = repeat(&e, "table:number-rows-repeated");
+                }
+                b"table-cell" | b"covered-table-cell" => {
+                    cell.clear();
+                    cell_repeat = repeat(&e, "table:number-columns-repeated");
+                    in_cell = true;
+                }
+                b"p" if in_cell && !cell.is_empty() => cell.push('\n'),
+                _ => {}
+            },
+            Event::Empty(e) => match e.local_name().as_ref() {
+                b"table-cell" | b"covered-table-cell" => {
+                    let count = repeat(&e, "table:number-columns-repeated");
+                    let count = count.min(MAX_COLUMNS.saturating_sub(row.len()));
+                    row.resize(row.len() + count, String::new());
+                }
+                b"s" if in_cell => cell.push(' '),
+                _ => {}
+            },
+            Event::Text(e) if in_cell => cell.push_str(&e.unescape().map_err(|e| e.to_string())?),
+            Event::End(e) => match e.local_name().as_ref() {
+                b"table-cell" | b"covered-table-cell" => {
+                    let count = cell_repeat.min(MAX_COLUMNS.saturating_sub(row.len()));
+                    row.resize(row.len() + count, cell.clone());
+                    in_cell = false;
+                }
+                b"table-row" => {
+                    while row.last().map(|cell| cell.is_empty()).unwrap_or(false) {
+                        row.pop();
+                    }
+                    // empty rows are only added once followed by a non-empty one
+                    if row.is_empty() {
+                        empty_rows = (empty_rows + row_repeat).min(MAX_REPEAT);
+                        continue;
+                    }
+                    if let Some(sheet) = sheets.last_mut() {
+                        for i in 0..empty_rows + row_repeat {
+                            if sheet.rows.len() == MAX_ROWS {
+                                sheet.truncated = true;
+                                break;
+                            }
+                            sheet
+                                .rows
+                                .push(if i < empty_rows { vec![] } else { row.clone() });
+                        }
+                    }
+                    empty_rows = 0;
+                }
+                b"table" => empty_rows = 0,
+                _ => {}
+            },
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    Ok(sheets)
+}
+
+fn csv_sheet(full_path: &std::path::Path, delimiter: u8) -> Result<Sheet, String> {
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(delimiter)
+        .has_headers(false)
+        .flexible(true)
+        .from_path(full_path)
+        .map_err(|e| e.to_string())?;
+
+    let mut sheet = Sheet {
+        name: String::new(),
+        rows: vec![],
+        truncated: false,
+    };
+    for record in reader.records() {
+        if sheet.rows.len() == MAX_ROWS {
+            sheet.truncated = true;
+            break;
+        }
+        let record = record.map_err(|e| e.to_string())?;
+        sheet
+            .rows
+            .push(record.iter().take(MAX_COLUMNS).map(String::from).collect());
+    }
+    Ok(sheet)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escapes_html() {
+        assert_eq!(
+            escape(r#"<a href="x">Tom & Jerry</a>"#),
+            "&lt;a href=&quot;x&quot;&gt;Tom &amp; Jerry&lt;/a&gt;"
+        );
+        assert_eq!(escape("&amp;"), "&amp;amp;");
+        assert_eq!(escape("plain 'text'"), "plain 'text'");
+    }
+
+    #[test]
+    fn parses_column_indexes() {
+        assert_eq!(column_index("A1"), Some(0));
+        assert_eq!(column_index("C12"), Some(2));
+        assert_eq!(column_index("AA3"), Some(26));
+        assert_eq!(column_index("XFD1048576"), Some(16383));
+        assert_eq!(column_index("12"), None);
+        assert_eq!(column_index("AAAAAAAAAAAAAAA1"), None);
+        assert_eq!(column_index(&"Z".repeat(100)), None);
+    }
+
+    #[test]
+    fn parses_xlsx_sheets() {
+        let shared_strings = xlsx_shared_strings(
+            r#"<sst><si><t>Name</t></si><si><r><t>Ri</t></r><r><t>ch &amp; bold</t></r></si></sst>"#,
+        )
+        .unwrap();
+        assert_eq!(shared_strings, vec!["Name", "Rich & bold"]);
+
+        let xml = r#"<worksheet><sheetData>
+            <row r="1"><c r="A1" t="s"><v>0</v></c><c r="C1" t="s"><v>1</v></c></row>
+            <row r="2"><c r="A2"><v>42</v></c><c r="B2" t="b"><v>1</v></c><c r="C2" t="inlineStr"><is><t>inline</t></is></c></row>
+            <row r="3"><c r="AAAAAAAAAAAAAAA3"><v>7</v></c><c r="ZZ3"><v>8</v></c></row>
+            <row r="4"><c r="A4" t="s"><v>99</v></c></row>
+        </sheetData></worksheet>"#;
+        let sheet = xlsx_sheet("Sheet1".to_string(), xml, &shared_strings).unwrap();
+        assert_eq!(sheet.name, "Sheet1");
+        assert!(!sheet.truncated);
+        assert_eq!(
+            sheet.rows,
+            vec![
+                vec!["Name", "", "Rich & bold"],
+                vec!["42", "TRUE", "inline"],
+                vec!["7"],
+                vec![""],
+            ]
+        );
+    }
+
+    #[test]
+    fn truncates_long_xlsx_sheets() {
+        let xml = format!(
+            "<worksheet><sheetData>{}</sheetData></worksheet>",
+            "<row><c><v>1</v></c></row>".repeat(MAX_ROWS + 5)
+        );
+        let sheet = xlsx_sheet(String::new(), &xml, &[]).unwrap();
+        assert_eq!(sheet.rows.len(), MAX_ROWS);
+        assert!(sheet.truncated);
+    }
+
+    #[test]
+    fn parses_ods_sheets() {
+        let xml = r#"<office:document-content><office:body><office:spreadsheet>
+            <table:table table:name="First">
+                <table:table-row>
+                    <table:table-cell><text:p>a</text:p><text:p>b<text:s/>c</text:p></table:table-cell>
+                    <table:table-cell table:number-columns-repeated="2"><text:p>x</text:p></table:table-cell>
+                    <table:table-cell table:number-columns-repeated="1000"/>
+                </table:table-row>
+                <table:table-row table:number-rows-repeated="2"><table:table-cell/></table:table-row>
+                <table:table-row><table:table-cell><text:p>end</text:p></table:table-cell></table:table-row>
+                <table:table-row table:number-rows-repeated="1048570"><table:table-cell/></table:table-row>
+            </table:table>
+            <table:table table:name="Second">
+                <table:table-row><table:table-cell><text:p>1</text:p></table:table-cell></table:table-row>
+            </table:table>
+        </office:spreadsheet></office:body></office:document-content>"#;
+        let sheets = ods_sheets(xml).unwrap();
+        assert_eq!(sheets.len(), 2);
+        assert_eq!(sheets[0].name, "First");
+        assert_eq!(
+            sheets[0].rows,
+            vec![vec!["a\nb c", "x", "x"], vec![], vec![], vec!["end"]]
+        );
+        assert!(!sheets[0].truncated);
+        assert_eq!(sheets[1].name, "Second");
+        assert_eq!(sheets[1].rows, vec![vec!["1"]]);
+    }
+
+    #[test]
+    fn clamps_ods_repeats() {
+        let xml = format!(
+            r#"<table:table table:name="Huge">
+                <table:table-row table:number-rows-repeated="{max}"><table:table-cell/></table:table-row>
+                <table:table-row table:number-rows-repeated="{max}"><table:table-cell/></table:table-row>
+                <table:table-row table:number-rows-repeated="{max}">
+                    <table:table-cell table:number-columns-repeated="{max}"><text:p>x</text:p></table:table-cell>
+                </table:table-row>
+            </table:table>"#,
+            max = usize::MAX
+        );
+        let sheets = ods_sheets(&xml).unwrap();
+        assert_eq!(sheets[0].rows.len(), MAX_ROWS);
+        assert!(sheets[0].rows.iter().all(|row| row.is_empty()));
+        assert!(sheets[0].truncated);
+
+        let xml = r#"<table:table>
+                <table:table-row table:number-rows-repeated="5000">
+                    <table:table-cell table:number-columns-repeated="5000"><text:p>x</text:p></table:table-cell>
+                </table:table-row>
+            </table:table>"#;
+        let sheets = ods_sheets(xml).unwrap();
+        assert_eq!(sheets[0].rows.len(), MAX_ROWS);
+        assert_eq!(sheets[0].rows[0].len(), MAX_COLUMNS);
+        assert!(sheets[0].truncated);
+    }
+}
@@ -0,0 +1,408 @@
+use crate::store::{format_time, load_json, not_found, now, page, random_hex, save_json};
+use crate::{audit, content_length, limits, resolve_path, save_files, AppError};
+use axum::extract::{Multipart, Path};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use common::{CreateDropBox, DropBoxInfo, JsonResponse};
+use once_cell::sync::{Lazy, OnceCell};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+#[derive(Serialize, Deserialize, Clone)]
+struct DropBox {
+    token: String,
+    /// Request path of the directory files are uploaded into, e.g. "/inbox"
+    path: String,
+    /// Unix timestamps
+    created: i64,
+    expires: Option<i64>,
+    max_file_size: Option<u64>,
+    max_total_size: Option<u64>,
+    uploaded: u64,
+    /// Bytes set aside for uploads in progress
+    #[serde(skip)]
+    reserved: u64,
+}
+
+impl DropBox {
+    fn is_valid(&self, now: i64) -> bool {
+        self.expires.map(|expires| now < expires).unwrap_or(true)
+    }
+
+    /// Bytes that may still be uploaded through the link.
+    fn left(&self) -> Option<u64> {
+        self.max_total_size
+            .map(|max| max.saturating_sub(self.uploaded + self.reserved))
+    }
+
+    fn info(&self) -> DropBoxInfo {
+        DropBoxInfo {
+            token: self.token.clone(),
+            path: self.path.clone(),
+            created: format_time(self.created),
+            expires: self.expires.map(format_time),
+            max_file_size: self.max_file_size,
+            max_total_size: self.max_total_size,
+            uploaded: self.uploaded,
+        }
+    }
+}
+
+static DROP_BOXES: Lazy<Mutex<HashMap<String, DropBox>>> = Lazy::new(Default::default);
+
+/// File the drop boxes are persisted in, they only live in memory without it.
+static DROP_BOXES_FILE: OnceCell<Option<PathBuf>> = OnceCell::new();
+
+pub fn init(drop_boxes_file: Option<PathBuf>) {
+    if let Some(file) = &drop_boxes_file {
+        let drop_boxes = load_json::<DropBox>(file);
+        log::info!(
+            "loaded {} drop box link(s) from {:?}",
+            drop_boxes.len(),
+            file
+        );
+        let mut all = DROP_BOXES.lock().unwrap();
+        for drop_box in drop_boxes {
+            all.insert(drop_box.token.clone(), drop_box);
+        }
+    }
+    DROP_BOXES_FILE.set(drop_boxes_file).ok();
+}
+
+fn persist(drop_boxes: &HashMap<String, DropBox>) {
+    if let Some(Some(file)) = DROP_BOXES_FILE.get() {
+        save_json(file, &drop_boxes.values().collect::<Vec<_>>());
+    }
+}
+
+/// Creates a drop box link for a directory.
+pub async fn create(
+    client: audit::Client,
+    Json(req): Json<CreateDropBox>,
+) -> Result<Json<DropBoxInfo>, AppError> {
+    let result = create_drop_box(&req);
+    client.record(audit::Operation::DropBox, &req.path, None, &result);
+    result
+}
+
+fn create_drop_box(req: &CreateDropBox) -> Result<Json<DropBoxInfo>, AppError> {
+    let full_path = resolve_path(&req.path)?;
+    if !full_path.is_dir() {
+        return Err(AppError(format!("not a directory: {}", req.path)));
+    }
+
+    let created = now();
+    let drop_box = DropBox {
+        token: random_hex(16)?,
+        path: format!("/{}", req.path.trim_matches('/')),
+        created,
+        expires: req
+            .expires_in_hours
+            .map(|hours| created + hours as i64 * 3600),
+        max_file_size: req.max_file_size,
+        max_total_size: req.max_total_size,
+        uploaded: 0,
+        reserved: 0,
+    };
+    log::info!("created drop box link for {}", drop_box.path);
+
+    let info = drop_box.info();
+    let mut drop_boxes = DROP_BOXES.lock().unwrap();
+    drop_boxes.insert(drop_box.token.clone(), drop_box);
+    persist(&drop_boxes);
+    Ok(Json(info))
+}
+
+/// Lists the drop box links that haven't expired, newest first. Expired
+/// links are dropped.
+pub async fn list() -> Json<Vec<DropBoxInfo>> {
+    let now = now();
+    let mut drop_boxes = DROP_BOXES.lock().unwrap();
+    let count = drop_boxes.len();
+    drop_boxes.retain(|_, drop_box| drop_box.is_valid(now));
+    if drop_boxes.len() != count {
+        persist(&drop_boxes);
+    }
+
+    let mut infos = drop_boxes.values().collect::<Vec<_>>();
+    infos.sort_by_key(|drop_box| std::cmp::Reverse(drop_box.created));
+    Json(infos.into_iter().map(DropBox::info).collect())
+}
+
+pub async fn revoke(
+    client: audit::Client,
+    Path(token): Path<String>,
+) -> Result<Json<JsonResponse>, AppError> {
+    let drop_box = {
+        let mut drop_boxes = DROP_BOXES.lock().unwrap();
+        let drop_box = drop_boxes.remove(&token);
+        if drop_box.is_some() {
+            persist(&drop_boxes);
+        }
+        drop_box
+    };
+    match drop_box {
+        Some(drop_box) => {
+            log::info!("revoked drop box link for {}", drop_box.path);
+            let resp = Json(JsonResponse::Succeeded { msg: None });
+            client.record(audit::Operation::RevokeDropBox, &drop_box.path, None, &resp);
+            Ok(resp)
+        }
+        None => {
+            let result = Err(AppError("no such drop box link".to_string()));
+            let path = format!("/u/{}", token);
+            client.record(audit::Operation::RevokeDropBox, &path, None, &result);
+            result
+        }
+    }
+}
+
+fn find(token: &str) -> Option<DropBox> {
+    DROP_BOXES
+        .lock()
+        .unwrap()
+        .get(token)
+        .filter(|drop_box| drop_box.is_valid(now()))
+        .cloned()
+}
+
+/// Finds a valid drop box and sets aside the bytes an upload of
+/// `content_length` bytes may take from what is left of it, so that
+/// concurrent uploads can't exceed its total size together.
+fn reserve(token: &str, content_length: Option<u64>) -> Option<(DropBox, Option<u64>)> {
+    let mut drop_boxes = DROP_BOXES.lock().unwrap();
+    let drop_box = drop_boxes
+        .get_mut(token)
+        .filter(|drop_box| drop_box.is_valid(now()))?;
+    let reserved = drop_box
+        .left()
+        .map(|left| content_length.map_or(left, |length| length.min(left)));
+    drop_box.reserved += reserved.unwrap_or(0);
+    Some((drop_box.clone(), reserved))
+}
+
+/// Receives files uploaded through a drop box link, the same way
+/// `/api/upload` does, except that existing files are never replaced.
+pub async fn upload(
+    client: audit::Client,
+    Path(token): Path<String>,
+    headers: HeaderMap,
+    multipart: Multipart,
+) -> Result<Response, AppError> {
+    let (drop_box, reserved) = match reserve(&token, content_length(&headers)) {
+        Some(reservation) => reservation,
+        None => {
+            let msg = "this link is invalid, has expired or was revoked".to_string();
+            let json_resp = Json(JsonResponse::Failed { msg: Some(msg) });
+            return Ok((StatusCode::NOT_FOUND, json_resp).into_response());
+        }
+    };
+
+    let mut saved = 0;
+    let result = receive(
+        &client, &drop_box, reserved, &headers, multipart, &mut saved,
+    )
+    .await;
+    client.record_failure(audit::Operation::Upload, &drop_box.path, &result);
+
+    // files saved before a failure count too, the rest of the reservation is
+    // given back
+    let mut drop_boxes = DROP_BOXES.lock().unwrap();
+    if let Some(drop_box) = drop_boxes.get_mut(&token) {
+        drop_box.reserved = drop_box.reserved.saturating_sub(reserved.unwrap_or(0));
+        drop_box.uploaded += saved;
+        log::info!(
+            "{} bytes uploaded through drop box link of {}",
+            saved,
+            drop_box.path
+        );
+    }
+    persist(&drop_boxes);
+    result
+}
+
+async fn receive(
+    client: &audit::Client,
+    drop_box: &DropBox,
+    reserved: Option<u64>,
+    headers: &HeaderMap,
+    multipart: Multipart,
+    saved: &mut u64,
+) -> Result<Response, AppError> {
+    let dir = resolve_path(&drop_box.path)?;
+    let length = content_length(headers);
+    let mut guard = match limits::UploadGuard::new(&dir, length, client.user()).await {
+        Ok(guard) => guard,
+        Err(e) => return Ok(e.into_response()),
+    };
+    guard.restrict(drop_box.max_file_size, reserved);
+    let result = save_files(client, &drop_box.path, &mut guard, headers, multipart, true).await;
+    *saved = guard.saved();
+    result
+}
+
+/// A standalone page to upload files through a drop box link.
+pub async fn upload_page(Path(token): Path<String>) -> Response {
+    let drop_box = match find(&token) {
+        Some(drop_box) => drop_box,
+        None => return not_found(),
+    };
+
+    let mut limits = vec![];
+    if let Some(max) = drop_box.max_file_size {
+        limits.push(format!("Files may be up to {} in size.", format_size(max)));
+    }
+    if let Some(left) = drop_box.left() {
+        limits.push(format!("{} can still be uploaded.", format_size(left)));
+    }
+    if let Some(expires) = drop_box.expires {
+        limits.push(format!("This link expires at {}.", format_time(expires)));
+    }
+
+    let body = format!(
+        r#"<p>Files uploaded here can't be seen by anyone else who has this link. {limits}</p>
+<form id="upload_form">
+<input type="file" id="files" multiple>
+<button>Upload</button>
+</form>
+<ul id="uploads"></ul>
+<script>
+const maxFileSize = {max_file_size};
+const form = document.getElementById('upload_form');
+const input = document.getElementById('files');
+const list = document.getElementById('uploads');
+
+function upload(file, row) {{
+    return new Promise(resolve => {{
+        const status = row.querySelector('span');
+        const progress = row.querySelector('progress');
+        if (maxFileSize > 0 && file.size > maxFileSize) {{
+            status.textContent = 'Too large';
+            return resolve();
+        }}
+        const data = new FormData();
+        data.append('file', file, file.name);
+        const xhr = new XMLHttpRequest();
+        xhr.open('POST', location.pathname);
+        xhr.upload.onprogress = e => {{
+            if (e.lengthComputable) progress.value = e.loaded / e.total;
+        }};
+        xhr.onload = () => {{
+            let msg = 'HTTP ' + xhr.status;
+            try {{
+                const resp = JSON.parse(xhr.responseText);
+                msg = resp.Succeeded ? 'Done' : (resp.Failed && resp.Failed.msg) || msg;
+            }} catch (e) {{}}
+            progress.value = 1;
+            status.textContent = msg;
+            resolve();
+        }};
+        xhr.onerror = () => {{
+            status.textContent = 'Failed';
+            resolve();
+        }};
+        xhr.send(data);
+    }});
+}}
+
+form.addEventListener('submit', async e => {{
+    e.preventDefault();
+    const files = Array.from(input.files);
+    input.value = '';
+    for (const file of files) {{
+        const row = document.createElement('li');
+        row.textContent = file.name + ' ';
+        row.appendChild(document.createElement('progress'));
+        row.appendChild(document.createTextNode(' '));
+        row.appendChild(document.createElement('span'));
+        list.appendChild(row);
+        await upload(file, row);
+    }}
+}});
+</script>"#,
+        limits = limits.join(" "),
+        max_file_size = drop_box.max_file_size.unwrap_or(0)
+    );
+    page(StatusCode::OK, "Upload files", &body)
+}
+
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Adds a drop box into `/dropbox` that expires `expires_in` seconds from
+    /// now.
+    fn add_drop_box(token: &str, expires_in: i64, max_total_size: Option<u64>) {
+        let drop_box = DropBox {
+            token: token.to_string(),
+            path: "/dropbox".to_string(),
+            created: now(),
+            expires: Some(now() + expires_in),
+            max_file_size: None,
+            max_total_size,
+            uploaded: 0,
+            reserved: 0,
+        };
+        DROP_BOXES
+            .lock()
+            .unwrap()
+            .insert(token.to_string(), drop_box);
+    }
+
+    #[tokio::test]
+    async fn concurrent_uploads_share_the_total_size() {
+        std::fs::create_dir_all(crate::test_serve_dir().join("dropbox")).unwrap();
+        add_drop_box("concurrent", 3600, Some(100));
+
+        // two uploads in progress can't take more than is left together
+        let (_, first) = reserve("concurrent", Some(60)).unwrap();
+        let (_, second) = reserve("concurrent", Some(60)).unwrap();
+        assert_eq!((first, second), (Some(60), Some(40)));
+        let (_, third) = reserve("concurrent", None).unwrap();
+        assert_eq!(third, Some(0));
+
+        // and each is stopped once it exceeds what it set aside
+        let dir = crate::test_serve_dir().join("dropbox");
+        let mut guard = limits::UploadGuard::new(&dir, None, None).await.unwrap();
+        guard.restrict(None, second);
+        guard.start_file();
+        guard.add(40).unwrap();
+        assert!(guard.add(1).is_err());
+
+        // a link without a total size isn't limited
+        add_drop_box("unlimited", 3600, None);
+        let (_, reserved) = reserve("unlimited", Some(60)).unwrap();
+        assert_eq!(reserved, None);
+    }
+
+    #[tokio::test]
+    async fn refuses_expired_links() {
+        add_drop_box("expired", -1, None);
+        assert!(find("expired").is_none());
+        assert!(reserve("expired", Some(1)).is_none());
+        let resp = upload_page(Path("expired".to_string())).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        add_drop_box("valid", 3600, None);
+        assert!(find("valid").is_some());
+        assert!(find("unknown").is_none());
+    }
+}
@@ -0,0 +1,309 @@
+use crate::{audit, limits, resolve_path, shutdown, AppError};
+use axum::body::Bytes;
+use axum::extract::Path;
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use common::{EditableFile, JsonResponse};
+use std::time::UNIX_EPOCH;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+
+/// Files larger than this are not opened in the editor.
+const MAX_SIZE: u64 = 2 * 1024 * 1024;
+
+/// Held from checking the precondition of a save until the file is replaced,
+/// so that concurrent saves of the same version can't both succeed.
+static SAVE_LOCK: Mutex<()> = Mutex::const_new(());
+
+/// Entity tag of a file version, derived from its mtime and size.
+pub fn etag(metadata: &std::fs::Metadata) -> String {
+    let mtime = metadata
+        .modified()
+        .ok()
+        .and_then(|mtime| mtime.duration_since(UNIX_EPOCH).ok())
+        .unwrap_or_default();
+    format!("\"{:x}-{:x}\"", mtime.as_nanos(), metadata.len())
+}
+
+/// Opens a text file for editing, the `etag` of the file identifies the
+/// version to send back in `If-Match` when saving.
+pub async fn open(Path(path): Path<String>) -> Result<Response, AppError> {
+    let full_path = resolve_path(&path)?;
+    let metadata = tokio::fs::metadata(&full_path)
+        .await
+        .map_err(|_| AppError(format!("file not found: {}", path)))?;
+    if !metadata.is_file() {
+        return Err(AppError(format!("not a file: {}", path)));
+    }
+    if metadata.len() > MAX_SIZE {
+        return Err(AppError(format!("too large to edit: {}", path)));
+    }
+
+    let bytes = tokio::fs::read(&full_path)
+        .await
+        .map_err(|e| AppError(format!("failed to read file: {}", e)))?;
+    let content = String::from_utf8(bytes)
+        .map_err(|_| AppError(format!("not a UTF-8 text file: {}", path)))?;
+
+    let file = EditableFile {
+        path: format!("/{}", path.trim_start_matches('/')),
+        content,
+        etag: etag(&metadata),
+    };
+    Ok(([(header::ETAG, file.etag.clone())], Json(file)).into_response())
+}
+
+/// Saves a file with optimistic concurrency: `If-Match` must carry the ETag
+/// the file was opened with (or `*` to overwrite whatever is there), and
+/// `If-None-Match: *` creates a new file, failing if it exists. A failed
+/// precondition is answered with 412 so that the editor can tell a conflict
+/// from other errors, successful saves return the new ETag.
+pub async fn save(
+    client: audit::Client,
+    Path(path): Path<String>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Response, AppError> {
+    let result = save_file(&client, &path, &headers, &body).await;
+    let size = Some(body.len() as u64);
+    client.record(audit::Operation::Edit, &path, size, &result);
+    result
+}
+
+async fn save_file(
+    client: &audit::Client,
+    path: &str,
+    headers: &HeaderMap,
+    body: &Bytes,
+) -> Result<Response, AppError> {
+    let full_path = resolve_path(path)?;
+    if body.len() as u64 > MAX_SIZE {
+        return Err(AppError(format!("too large to save: {}", path)));
+    }
+    let if_match = headers
+        .get(header::IF_MATCH)
+        .and_then(|value| value.to_str().ok());
+    let create = headers
+        .get(header::IF_NONE_MATCH)
+        .map(|value| value == "*")
+        .unwrap_or(false);
+
+    let _lock = SAVE_LOCK.lock().await;
+    let metadata = tokio::fs::metadata(&full_path).await.ok();
+    let precondition = match (&metadata, if_match, create) {
+        (None, _, true) => Ok(()),
+        (Some(_), _, true) => Err(format!("{} already exists", path)),
+        (Some(metadata), Some(if_match), false) if metadata.is_file() => {
+            if if_match == "*" || if_match == etag(metadata) {
+                Ok(())
+            } else {
+                Err(format!("{} was changed since it was opened", path))
+            }
+        }
+        (None, Some(_), false) => Err(format!("{} was deleted since it was opened", path)),
+        (Some(_), Some(_), false) => return Err(AppError(format!("not a file: {}", path))),
+        (_, None, false) => {
+            return Ok((
+                StatusCode::PRECONDITION_REQUIRED,
+                Json(JsonResponse::Failed {
+                    msg: Some("If-Match or If-None-Match is required".to_string()),
+                }),
+            )
+                .into_response())
+        }
+    };
+    if let Err(msg) = precondition {
+        return Ok((
+            StatusCode::PRECONDITION_FAILED,
+            Json(JsonResponse::Failed { msg: Some(msg) }),
+        )
+            .into_response());
+    }
+
+    // saved files count against the quotas and free space like uploads
+    let dir = full_path
+        .parent()
+        .ok_or_else(|| AppError(format!("invalid path: {}", path)))?;
+    let length = Some(body.len() as u64);
+    let mut guard = match limits::UploadGuard::new(dir, length, client.user()).await {
+        Ok(guard) => guard,
+        Err(e) => return Ok(e.into_response()),
+    };
+    guard.start_file();
+    if let Err(e) = guard.add(body.len() as u64) {
+        return Ok(e.into_response());
+    }
+
+    // written next to the file and renamed over it, so that readers never see
+    // a half written file
+    let partial_file = shutdown::PartialFile::new(&full_path);
+    let mut file = tokio::fs::File::create(partial_file.path())
+        .await
+        .map_err(|e| AppError(format!("failed to save {}: {}", path, e)))?;
+    file.write_all(body)
+        .await
+        .map_err(|e| AppError(format!("failed to save {}: {}", path, e)))?;
+    file.flush()
+        .await
+        .map_err(|e| AppError(format!("failed to save {}: {}", path, e)))?;
+    if let Some(metadata) = &metadata {
+        // keep the mode of the original file, e.g. for scripts
+        let _ = tokio::fs::set_permissions(partial_file.path(), metadata.permissions()).await;
+    }
+    partial_file
+        .persist(&full_path)
+        .await
+        .map_err(|e| AppError(format!("failed to save {}: {}", path, e)))?;
+
+    let metadata = tokio::fs::metadata(&full_path)
+        .await
+        .map_err(|e| AppError(format!("failed to save {}: {}", path, e)))?;
+    guard.file_saved();
+    limits::record_upload(client.user(), &full_path);
+    log::info!("saved {:?}", full_path);
+    Ok((
+        [(header::ETAG, etag(&metadata))],
+        Json(JsonResponse::Succeeded { msg: None }),
+    )
+        .into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    fn test_dir() -> std::path::PathBuf {
+        let dir = crate::test_serve_dir().join("edit");
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Saves `body` as `/edit/<name>`, returning the status and ETag.
+    async fn save(
+        name: &str,
+        headers: &[(header::HeaderName, &str)],
+        body: &'static str,
+    ) -> (StatusCode, Option<String>) {
+        let mut map = HeaderMap::new();
+        for (name, value) in headers {
+            map.insert(name, HeaderValue::from_str(value).unwrap());
+        }
+        let client = audit::Client::with_user(None);
+        let path = format!("/edit/{}", name);
+        let resp = save_file(&client, &path, &map, &Bytes::from(body))
+            .await
+            .unwrap_or_else(|AppError(e)| panic!("{}", e));
+        let etag = resp
+            .headers()
+            .get(header::ETAG)
+            .map(|etag| etag.to_str().unwrap().to_string());
+        (resp.status(), etag)
+    }
+
+    fn current_etag(name: &str) -> String {
+        etag(&std::fs::metadata(test_dir().join(name)).unwrap())
+    }
+
+    fn content(name: &str) -> String {
+        std::fs::read_to_string(test_dir().join(name)).unwrap()
+    }
+
+    #[tokio::test]
+    async fn requires_preconditions() {
+        std::fs::write(test_dir().join("required.txt"), "old").unwrap();
+        let (status, _) = save("required.txt", &[], "new").await;
+        assert_eq!(status, StatusCode::PRECONDITION_REQUIRED);
+        assert_eq!(content("required.txt"), "old");
+    }
+
+    #[tokio::test]
+    async fn rejects_stale_versions() {
+        std::fs::write(test_dir().join("stale.txt"), "old").unwrap();
+        let stale = [(header::IF_MATCH, "\"1-3\"")];
+        let (status, _) = save("stale.txt", &stale, "new").await;
+        assert_eq!(status, StatusCode::PRECONDITION_FAILED);
+        assert_eq!(content("stale.txt"), "old");
+    }
+
+    #[tokio::test]
+    async fn returns_the_new_etag() {
+        std::fs::write(test_dir().join("versions.txt"), "first").unwrap();
+        let opened = current_etag("versions.txt");
+        let (status, saved) = save("versions.txt", &[(header::IF_MATCH, &opened)], "second").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(content("versions.txt"), "second");
+        let saved = saved.unwrap();
+        assert_eq!(saved, current_etag("versions.txt"));
+        assert_ne!(saved, opened);
+
+        // the version opened before is outdated now, the saved one isn't
+        let (status, _) = save("versions.txt", &[(header::IF_MATCH, &opened)], "lost").await;
+        assert_eq!(status, StatusCode::PRECONDITION_FAILED);
+        let (status, _) = save("versions.txt", &[(header::IF_MATCH, &saved)], "third").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(content("versions.txt"), "third");
+    }
+
+    #[tokio::test]
+    async fn overwrites_any_version_with_wildcard() {
+        std::fs::write(test_dir().join("wildcard.txt"), "old").unwrap();
+        let (status, etag) = save("wildcard.txt", &[(header::IF_MATCH, "*")], "new").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(etag.unwrap(), current_etag("wildcard.txt"));
+        assert_eq!(content("wildcard.txt"), "new");
+    }
+
+    #[tokio::test]
+    async fn creates_only_new_files() {
+        let _ = std::fs::remove_file(test_dir().join("created.txt"));
+        let create = [(header::IF_NONE_MATCH, "*")];
+        let (status, _) = save("created.txt", &create, "new").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(content("created.txt"), "new");
+
+        let (status, _) = save("created.txt", &create, "other").await;
+        assert_eq!(status, StatusCode::PRECONDITION_FAILED);
+        assert_eq!(content("created.txt"), "new");
+    }
+
+    #[tokio::test]
+    async fn saves_files_opened_compressed() {
+        use axum::body::{Body, HttpBody};
+        use axum::{http::Request, middleware, routing::get, Router};
+        use std::io::Read;
+        use tower::{ServiceBuilder, ServiceExt};
+
+        std::fs::write(test_dir().join("compressed.txt"), "text ".repeat(1000)).unwrap();
+        let app = Router::new().route("/api/edit/*path", get(open)).layer(
+            ServiceBuilder::new()
+                .layer(middleware::from_fn(crate::compression::vary))
+                .layer(crate::compression::layer()),
+        );
+        let request = Request::get("/api/edit/edit/compressed.txt")
+            .header(header::ACCEPT_ENCODING, "gzip")
+            .body(Body::empty())
+            .unwrap();
+        let resp = app.oneshot(request).await.unwrap();
+        assert_eq!(resp.headers()[header::CONTENT_ENCODING], "gzip");
+        let weak = resp.headers()[header::ETAG].to_str().unwrap().to_string();
+        assert!(weak.starts_with("W/"), "{}", weak);
+        let (mut body, mut gzipped) = (resp.into_body(), Vec::new());
+        while let Some(chunk) = body.data().await {
+            gzipped.extend_from_slice(&chunk.unwrap());
+        }
+        let mut json = String::new();
+        flate2::read::GzDecoder::new(&gzipped[..])
+            .read_to_string(&mut json)
+            .unwrap();
+        let file: EditableFile = serde_json::from_str(&json).unwrap();
+
+        // the weakened ETag header no longer matches, the one of the file does
+        let (status, _) = save("compressed.txt", &[(header::IF_MATCH, &weak)], "saved").await;
+        assert_eq!(status, StatusCode::PRECONDITION_FAILED);
+        let (status, _) = save("compressed.txt", &[(header::IF_MATCH, &file.etag)], "saved").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(content("compressed.txt"), "saved");
+    }
+}
@@ -0,0 +1,164 @@
+use crate::{resolve_path, AppError};
+use axum::extract::Path;
+use axum::Json;
+use common::{ExifInfo, GpsPosition};
+use exif::{Exif, In, Tag, Value};
+
+/// Extracts camera, date taken and GPS position from an image's EXIF data.
+pub async fn exif(Path(path): Path<String>) -> Result<Json<ExifInfo>, AppError> {
+    let full_path = resolve_path(&path)?;
+    let exif = tokio::task::spawn_blocking(move || {
+        let file = std::fs::File::open(full_path).ok()?;
+        exif::Reader::new()
+            .read_from_container(&mut std::io::BufReader::new(file))
+            .ok()
+    })
+    .await
+    .ok()
+    .flatten()
+    .ok_or_else(|| AppError("no EXIF metadata".to_string()))?;
+
+    let make = string_field(&exif, Tag::Make);
+    let model = string_field(&exif, Tag::Model);
+    let camera = match (make, model) {
+        // models usually repeat the make, e.g. "Canon" / "Canon EOS R5"
+        (Some(make), Some(model)) if model.starts_with(&make) => Some(model),
+        (Some(make), Some(model)) => Some(format!("{} {}", make, model)),
+        (make, model) => make.or(model),
+    };
+
+    Ok(Json(ExifInfo {
+        camera,
+        lens: string_field(&exif, Tag::LensModel),
+        date_taken: string_field(&exif, Tag::DateTimeOriginal)
+            .or_else(|| string_field(&exif, Tag::DateTime)),
+        exposure_time: display_field(&exif, Tag::ExposureTime),
+        f_number: display_field(&exif, Tag::FNumber),
+        iso: display_field(&exif, Tag::PhotographicSensitivity),
+        focal_length: display_field(&exif, Tag::FocalLength),
+        width: uint_field(&exif, Tag::PixelXDimension),
+        height: uint_field(&exif, Tag::PixelYDimension),
+        gps: gps_position(&exif),
+    }))
+}
+
+fn string_field(exif: &Exif, tag: Tag) -> Option<String> {
+    let field = exif.get_field(tag, In::PRIMARY)?;
+    match &field.value {
+        Value::Ascii(values) => {
+            let value = String::from_utf8_lossy(values.first()?).trim().to_string();
+            if value.is_empty() {
+                None
+            } else {
+                Some(value)
+            }
+        }
+        _ => None,
+    }
+}
+
+fn display_field(exif: &Exif, tag: Tag) -> Option<String> {
+    let field = exif.get_field(tag, In::PRIMARY)?;
+    Some(field.display_value().with_unit(exif).to_string())
+}
+
+fn uint_field(exif: &Exif, tag: Tag) -> Option<u32> {
+    exif.get_field(tag, In::PRIMARY)?.value.get_uint(0)
+}
+
+fn gps_position(exif: &Exif) -> Option<GpsPosition> {
+    let latitude = gps_coordinate(exif, Tag::GPSLatitude, Tag::GPSLatitudeRef, "S")?;
+    let longitude = gps_coordinate(exif, Tag::GPSLongitude, Tag::GPSLongitudeRef, "W")?;
+    // many devices record no altitude, the position is still worth showing
+    let altitude = exif
+        .get_field(Tag::GPSAltitude, In::PRIMARY)
+        .and_then(|field| match &field.value {
+            Value::Rational(values) => values.first().map(|v| v.to_f64()),
+            _ => None,
+        });
+    Some(GpsPosition {
+        latitude,
+        longitude,
+        altitude,
+    })
+}
+
+/// Converts degrees/minutes/seconds to signed decimal degrees.
+fn gps_coordinate(exif: &Exif, tag: Tag, ref_tag: Tag, negative_ref: &str) -> Option<f64> {
+    let dms = match &exif.get_field(tag, In::PRIMARY)?.value {
+        Value::Rational(values) if values.len() >= 3 => values.clone(),
+        _ => return None,
+    };
+    let degrees = dms[0].to_f64() + dms[1].to_f64() / 60.0 + dms[2].to_f64() / 3600.0;
+    match string_field(exif, ref_tag) {
+        Some(r) if r == negative_ref => Some(-degrees),
+        _ => Some(degrees),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use exif::experimental::Writer;
+    use exif::{Field, Rational};
+    use std::io::Cursor;
+
+    fn build_exif(fields: &[Field]) -> Exif {
+        let mut writer = Writer::new();
+        for field in fields {
+            writer.push_field(field);
+        }
+        let mut buf = Cursor::new(Vec::new());
+        writer.write(&mut buf, false).unwrap();
+        exif::Reader::new().read_raw(buf.into_inner()).unwrap()
+    }
+
+    fn field(tag: Tag, value: Value) -> Field {
+        Field {
+            tag,
+            ifd_num: In::PRIMARY,
+            value,
+        }
+    }
+
+    fn dms(degrees: u32, minutes: u32, seconds: u32) -> Value {
+        Value::Rational(vec![
+            Rational::from((degrees, 1)),
+            Rational::from((minutes, 1)),
+            Rational::from((seconds, 1)),
+        ])
+    }
+
+    #[test]
+    fn reads_gps_position() {
+        let exif = build_exif(&[
+            field(Tag::GPSLatitudeRef, Value::Ascii(vec![b"S".to_vec()])),
+            field(Tag::GPSLatitude, dms(33, 51, 36)),
+            field(Tag::GPSLongitudeRef, Value::Ascii(vec![b"E".to_vec()])),
+            field(Tag::GPSLongitude, dms(151, 12, 0)),
+            field(
+                Tag::GPSAltitude,
+                Value::Rational(vec![Rational::from((58, 2))]),
+            ),
+        ]);
+        let gps = gps_position(&exif).unwrap();
+        assert!((gps.latitude + 33.86).abs() < 1e-9);
+        assert!((gps.longitude - 151.2).abs() < 1e-9);
+        assert_eq!(gps.altitude, Some(29.0));
+    }
+
+    #[test]
+    fn reads_gps_position_without_altitude() {
+        let exif = build_exif(&[
+            field(Tag::GPSLatitude, dms(48, 51, 0)),
+            field(Tag::GPSLongitude, dms(2, 21, 0)),
+        ]);
+        let gps = gps_position(&exif).unwrap();
+        assert!((gps.latitude - 48.85).abs() < 1e-9);
+        assert!((gps.longitude - 2.35).abs() < 1e-9);
+        assert_eq!(gps.altitude, None);
+
+        let exif = build_exif(&[field(Tag::GPSLatitude, dms(48, 51, 0))]);
+        assert_eq!(gps_position(&exif), None);
+    }
+}
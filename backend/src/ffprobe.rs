@@ -0,0 +1,339 @@
+use crate::{metrics, resolve_path, AppError};
+use axum::extract::Path;
+use axum::Json;
+use common::{MediaInfo, StreamInfo, StreamKind};
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+const PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Probed files are few compared to what's served, the cache is simply
+/// cleared once it grows past this.
+const MAX_CACHE_ENTRIES: usize = 4096;
+
+/// Probe results keyed by path, valid as long as the file's mtime and size
+/// haven't changed.
+static CACHE: Lazy<Mutex<HashMap<PathBuf, CacheEntry>>> = Lazy::new(Default::default);
+
+struct CacheEntry {
+    mtime: SystemTime,
+    size: u64,
+    info: MediaInfo,
+}
+
+#[derive(Debug)]
+pub enum ProbeError {
+    NotInstalled,
+    Unsupported,
+    Timeout,
+    Io(std::io::Error),
+}
+
+impl std::fmt::Display for ProbeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProbeError::NotInstalled => write!(f, "ffprobe is not installed on the server"),
+            ProbeError::Unsupported => write!(f, "not a supported media file"),
+            ProbeError::Timeout => write!(f, "ffprobe timed out"),
+            ProbeError::Io(e) => write!(f, "failed to run ffprobe: {}", e),
+        }
+    }
+}
+
+/// Raw `ffprobe -print_format json -show_format -show_streams` output, ffprobe
+/// reports most numbers as strings.
+#[derive(Deserialize)]
+struct ProbeOutput {
+    format: ProbeFormat,
+    #[serde(default)]
+    streams: Vec<ProbeStream>,
+}
+
+#[derive(Deserialize)]
+struct ProbeFormat {
+    format_name: String,
+    format_long_name: Option<String>,
+    duration: Option<String>,
+    bit_rate: Option<String>,
+    size: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ProbeStream {
+    index: u32,
+    codec_type: Option<String>,
+    codec_name: Option<String>,
+    codec_long_name: Option<String>,
+    profile: Option<String>,
+    width: Option<u32>,
+    height: Option<u32>,
+    avg_frame_rate: Option<String>,
+    channels: Option<u32>,
+    sample_rate: Option<String>,
+    bit_rate: Option<String>,
+    #[serde(default)]
+    tags: HashMap<String, String>,
+}
+
+pub async fn media_info(Path(path): Path<String>) -> Result<Json<MediaInfo>, AppError> {
+    let full_path = resolve_path(&path)?;
+    if !full_path.is_file() {
+        return Err(AppError(format!("file not found: {}", path)));
+    }
+    probe(&full_path)
+        .await
+        .map(Json)
+        .map_err(|e| AppError(e.to_string()))
+}
+
+/// Runs ffprobe on `full_path`, or returns the cached result if the file
+/// hasn't changed since it was last probed.
+pub async fn probe(full_path: &std::path::Path) -> Result<MediaInfo, ProbeError> {
+    let metadata = tokio::fs::metadata(full_path)
+        .await
+        .map_err(ProbeError::Io)?;
+    let mtime = metadata.modified().map_err(ProbeError::Io)?;
+    let size = metadata.len();
+
+    if let Some(entry) = CACHE.lock().unwrap().get(full_path) {
+        if entry.mtime == mtime && entry.size == size {
+            return Ok(entry.info.clone());
+        }
+    }
+
+    let result = run_ffprobe("ffprobe", full_path).await;
+    metrics::count_ffprobe(result.is_ok());
+    let info = result?;
+
+    let mut cache = CACHE.lock().unwrap();
+    if cache.len() >= MAX_CACHE_ENTRIES {
+        cache.clear();
+    }
+    cache.insert(
+        full_path.to_path_buf(),
+        CacheEntry {
+            mtime,
+            size,
+            info: info.clone(),
+        },
+    );
+
+    Ok(info)
+}
+
+/// Runs `program`, which is ffprobe outside of the tests.
+async fn run_ffprobe(program: &str, full_path: &std::path::Path) -> Result<MediaInfo, ProbeError> {
+    let command = tokio::process::Command::new(program)
+        .args([
+            "-v",
+            "quiet",
+            "-print_format",
+            "json",
+            "-show_format",
+            "-show_streams",
+        ])
+        .arg(full_path)
+        .kill_on_drop(true)
+        .output();
+
+    let output = match tokio::time::timeout(PROBE_TIMEOUT, command).await {
+        Err(_) => return Err(ProbeError::Timeout),
+        Ok(Err(e)) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Err(ProbeError::NotInstalled)
+        }
+        Ok(Err(e)) => return Err(ProbeError::Io(e)),
+        Ok(Ok(output)) => output,
+    };
+    if !output.status.success() {
+        return Err(ProbeError::Unsupported);
+    }
+
+    let raw: ProbeOutput =
+        serde_json::from_slice(&output.stdout).map_err(|_| ProbeError::Unsupported)?;
+    Ok(convert_probe_output(raw))
+}
+
+fn convert_probe_output(raw: ProbeOutput) -> MediaInfo {
+    MediaInfo {
+        format_name: raw.format.format_name,
+        format_long_name: raw.format.format_long_name,
+        duration: parse(&raw.format.duration),
+        bit_rate: parse(&raw.format.bit_rate),
+        size: parse(&raw.format.size),
+        streams: raw
+            .streams
+            .into_iter()
+            .map(|stream| StreamInfo {
+                index: stream.index,
+                kind: match stream.codec_type.as_deref() {
+                    Some("video") => StreamKind::Video,
+                    Some("audio") => StreamKind::Audio,
+                    Some("subtitle") => StreamKind::Subtitle,
+                    _ => StreamKind::Other,
+                },
+                frame_rate: stream.avg_frame_rate.as_deref().and_then(parse_rational),
+                sample_rate: parse(&stream.sample_rate),
+                bit_rate: parse(&stream.bit_rate),
+                language: stream.tags.get("language").cloned(),
+                title: stream.tags.get("title").cloned(),
+                codec_name: stream.codec_name,
+                codec_long_name: stream.codec_long_name,
+                profile: stream.profile,
+                width: stream.width,
+                height: stream.height,
+                channels: stream.channels,
+            })
+            .collect(),
+    }
+}
+
+fn parse<T: std::str::FromStr>(value: &Option<String>) -> Option<T> {
+    value.as_deref().and_then(|v| v.parse().ok())
+}
+
+/// Parses ffprobe's "30000/1001" style frame rates, "0/0" means unknown.
+fn parse_rational(value: &str) -> Option<f64> {
+    let (num, den) = value.split_once('/')?;
+    let (num, den) = (num.parse::<f64>().ok()?, den.parse::<f64>().ok()?);
+    if num > 0.0 && den > 0.0 {
+        Some(num / den)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Output of ffprobe for an MKV with H.264 video, AAC audio and subtitles.
+    const FIXTURE: &str = r#"{
+    "streams": [
+        {
+            "index": 0,
+            "codec_name": "h264",
+            "codec_long_name": "H.264 / AVC / MPEG-4 AVC / MPEG-4 part 10",
+            "profile": "High",
+            "codec_type": "video",
+            "width": 1920,
+            "height": 1080,
+            "r_frame_rate": "24000/1001",
+            "avg_frame_rate": "24000/1001",
+            "disposition": {
+                "default": 1
+            },
+            "tags": {
+                "language": "eng",
+                "DURATION": "00:21:04.829000000"
+            }
+        },
+        {
+            "index": 1,
+            "codec_name": "aac",
+            "codec_long_name": "AAC (Advanced Audio Coding)",
+            "profile": "LC",
+            "codec_type": "audio",
+            "sample_fmt": "fltp",
+            "sample_rate": "48000",
+            "channels": 6,
+            "channel_layout": "5.1",
+            "bit_rate": "384000",
+            "avg_frame_rate": "0/0",
+            "tags": {
+                "language": "jpn",
+                "title": "Surround"
+            }
+        },
+        {
+            "index": 2,
+            "codec_name": "subrip",
+            "codec_long_name": "SubRip subtitle",
+            "codec_type": "subtitle",
+            "avg_frame_rate": "0/0",
+            "tags": {
+                "language": "eng"
+            }
+        },
+        {
+            "index": 3,
+            "codec_type": "attachment",
+            "tags": {
+                "filename": "font.ttf",
+                "mimetype": "application/x-truetype-font"
+            }
+        }
+    ],
+    "format": {
+        "filename": "episode.mkv",
+        "nb_streams": 4,
+        "format_name": "matroska,webm",
+        "format_long_name": "Matroska / WebM",
+        "start_time": "0.000000",
+        "duration": "1264.829000",
+        "size": "734003200",
+        "bit_rate": "4642525",
+        "probe_score": 100
+    }
+}"#;
+
+    #[test]
+    fn converts_ffprobe_output() {
+        let raw = serde_json::from_str::<ProbeOutput>(FIXTURE).unwrap();
+        let info = convert_probe_output(raw);
+        assert_eq!(info.format_name, "matroska,webm");
+        assert_eq!(info.format_long_name.as_deref(), Some("Matroska / WebM"));
+        assert_eq!(info.duration, Some(1264.829));
+        assert_eq!(info.bit_rate, Some(4642525));
+        assert_eq!(info.size, Some(734003200));
+
+        let kinds = info.streams.iter().map(|s| s.kind).collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            [
+                StreamKind::Video,
+                StreamKind::Audio,
+                StreamKind::Subtitle,
+                StreamKind::Other
+            ]
+        );
+
+        let video = info.first_stream(StreamKind::Video).unwrap();
+        assert_eq!(video.codec_name.as_deref(), Some("h264"));
+        assert_eq!(video.profile.as_deref(), Some("High"));
+        assert_eq!((video.width, video.height), (Some(1920), Some(1080)));
+        let frame_rate = video.frame_rate.unwrap();
+        assert!((frame_rate - 23.976).abs() < 0.001);
+        assert_eq!(video.bit_rate, None);
+
+        let audio = info.first_stream(StreamKind::Audio).unwrap();
+        assert_eq!(audio.index, 1);
+        assert_eq!(audio.channels, Some(6));
+        assert_eq!(audio.sample_rate, Some(48000));
+        assert_eq!(audio.bit_rate, Some(384000));
+        // "0/0" means unknown
+        assert_eq!(audio.frame_rate, None);
+        assert_eq!(audio.language.as_deref(), Some("jpn"));
+        assert_eq!(audio.title.as_deref(), Some("Surround"));
+
+        let subtitle = info.first_stream(StreamKind::Subtitle).unwrap();
+        assert_eq!(subtitle.language.as_deref(), Some("eng"));
+        assert_eq!(subtitle.title, None);
+    }
+
+    #[tokio::test]
+    async fn maps_probe_failures() {
+        let path = std::path::Path::new("episode.mkv");
+        let missing = run_ffprobe("fileserver-rs-no-such-ffprobe", path).await;
+        assert!(matches!(missing, Err(ProbeError::NotInstalled)));
+        // ffprobe exits with an error for files it can't read
+        let failed = run_ffprobe("false", path).await;
+        assert!(matches!(failed, Err(ProbeError::Unsupported)));
+        // or prints something that isn't a probe result
+        let garbage = run_ffprobe("echo", path).await;
+        assert!(matches!(garbage, Err(ProbeError::Unsupported)));
+    }
+}
@@ -0,0 +1,498 @@
+use crate::{ffprobe, resolve_path, AppError};
+use axum::extract::Path;
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use common::{MediaInfo, StreamKind};
+use once_cell::sync::{Lazy, OnceCell};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, UNIX_EPOCH};
+use tokio::process::Child;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio_util::sync::CancellationToken;
+
+const PLAYLIST_NAME: &str = "index.m3u8";
+const SEGMENT_SECONDS: &str = "6";
+
+/// Transcode jobs whose viewers haven't requested anything for this long are
+/// considered abandoned and killed.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How long a playlist request waits for ffmpeg to produce the first segments.
+const PLAYLIST_WAIT: Duration = Duration::from_secs(30);
+
+/// Codecs that can be copied into MPEG-TS segments as is, and that browsers
+/// (through hls.js or native HLS) can decode.
+const HLS_VIDEO_CODECS: &[&str] = &["h264"];
+const HLS_AUDIO_CODECS: &[&str] = &["aac", "mp3"];
+
+/// Codecs and containers browsers play without any help.
+const BROWSER_VIDEO_CODECS: &[&str] = &["h264", "vp8", "vp9", "av1"];
+const BROWSER_AUDIO_CODECS: &[&str] = &["aac", "mp3", "opus", "vorbis", "flac"];
+const BROWSER_CONTAINERS: &[&str] = &["mp4", "m4v", "webm", "ogv", "mov"];
+
+struct HlsConfig {
+    cache_dir: PathBuf,
+    cache_size: u64,
+    job_slots: Arc<Semaphore>,
+}
+
+struct Job {
+    cancel: CancellationToken,
+    last_access: Instant,
+}
+
+static CONFIG: OnceCell<HlsConfig> = OnceCell::new();
+
+/// Running transcode jobs, keyed by cache key.
+static JOBS: Lazy<Mutex<HashMap<String, Job>>> = Lazy::new(Default::default);
+
+/// Last time each cache entry was served, used to evict the least recently
+/// watched entries first.
+static LAST_ACCESS: Lazy<Mutex<HashMap<String, Instant>>> = Lazy::new(Default::default);
+
+/// What needs to be done to a video for it to be played in the browser.
+#[derive(Debug, PartialEq)]
+struct StreamPlan {
+    copy_video: bool,
+    copy_audio: bool,
+}
+
+pub fn init(cache_dir: PathBuf, cache_size: u64, max_jobs: usize) {
+    if let Err(e) = std::fs::create_dir_all(&cache_dir) {
+        log::error!("failed to create HLS cache dir {:?}: {}", cache_dir, e);
+    }
+    log::info!(
+        "HLS cache: {:?}, size limit: {} bytes, max transcode jobs: {}",
+        cache_dir,
+        cache_size,
+        max_jobs
+    );
+
+    CONFIG
+        .set(HlsConfig {
+            cache_dir,
+            cache_size,
+            job_slots: Arc::new(Semaphore::new(max_jobs)),
+        })
+        .ok();
+
+    tokio::spawn(async {
+        let mut ticker = tokio::time::interval(Duration::from_secs(10));
+        loop {
+            ticker.tick().await;
+            stop_idle_jobs();
+            evict_cache();
+        }
+    });
+}
+
+/// Returns the HLS playlist url to use for a video that browsers can't play
+/// directly, judging from its container and codecs.
+pub fn hls_url(path: &str, file_name: &str, info: &MediaInfo) -> Option<String> {
+    CONFIG.get()?;
+    let video_codec = first_codec(info, StreamKind::Video)?;
+    let audio_codec = first_codec(info, StreamKind::Audio);
+
+    let ext = file_name.rsplit_once('.')?.1.to_ascii_lowercase();
+    let playable = BROWSER_CONTAINERS.contains(&ext.as_str())
+        && BROWSER_VIDEO_CODECS.contains(&video_codec)
+        && audio_codec.iter().all(|codec| BROWSER_AUDIO_CODECS.contains(codec));
+    if playable {
+        return None;
+    }
+
+    Some(format!("/api/hls{}/{}", path, PLAYLIST_NAME))
+}
+
+/// Serves `<media path>/index.m3u8`, starting a remux/transcode job if the
+/// segments aren't cached yet, and `<media path>/segNNNNN.ts` segments.
+pub async fn serve(Path(path): Path<String>) -> Response {
+    let (media_path, name) = match path.rsplit_once('/') {
+        Some(parts) => parts,
+        None => return StatusCode::NOT_FOUND.into_response(),
+    };
+    let config = match CONFIG.get() {
+        Some(config) => config,
+        None => return AppError("HLS is disabled".to_string()).into_response(),
+    };
+    let full_path = match resolve_path(media_path) {
+        Ok(full_path) if full_path.is_file() => full_path,
+        Ok(_) => return StatusCode::NOT_FOUND.into_response(),
+        Err(e) => return e.into_response(),
+    };
+
+    let key = cache_key(&full_path);
+    let job_dir = config.cache_dir.join(&key);
+    touch(&key);
+
+    if name == PLAYLIST_NAME {
+        serve_playlist(config, &key, &job_dir, &full_path).await
+    } else if is_segment_name(name) {
+        match tokio::fs::read(job_dir.join(name)).await {
+            Ok(bytes) => ([(header::CONTENT_TYPE, "video/mp2t")], bytes).into_response(),
+            Err(_) => StatusCode::NOT_FOUND.into_response(),
+        }
+    } else {
+        StatusCode::NOT_FOUND.into_response()
+    }
+}
+
+async fn serve_playlist(
+    config: &HlsConfig,
+    key: &str,
+    job_dir: &std::path::Path,
+    full_path: &std::path::Path,
+) -> Response {
+    let playlist_path = job_dir.join(PLAYLIST_NAME);
+    let cached = tokio::fs::read_to_string(&playlist_path)
+        .await
+        .map(|playlist| playlist.contains("#EXT-X-ENDLIST"))
+        .unwrap_or(false);
+    let running = JOBS.lock().unwrap().contains_key(key);
+
+    if !cached && !running {
+        if let Err(resp) = start_job(config, key, job_dir, full_path).await {
+            return resp;
+        }
+    }
+
+    let deadline = Instant::now() + PLAYLIST_WAIT;
+    loop {
+        if let Ok(playlist) = tokio::fs::read_to_string(&playlist_path).await {
+            if playlist.contains("#EXTINF") {
+                return (
+                    [(header::CONTENT_TYPE, "application/vnd.apple.mpegurl")],
+                    playlist,
+                )
+                    .into_response();
+            }
+        }
+        if Instant::now() > deadline || !JOBS.lock().unwrap().contains_key(key) {
+            return StatusCode::SERVICE_UNAVAILABLE.into_response();
+        }
+        tokio::time::sleep(Duration::from_millis(250)).await;
+    }
+}
+
+async fn start_job(
+    config: &HlsConfig,
+    key: &str,
+    job_dir: &std::path::Path,
+    full_path: &std::path::Path,
+) -> Result<(), Response> {
+    let cancel = CancellationToken::new();
+    {
+        let mut jobs = JOBS.lock().unwrap();
+        if jobs.contains_key(key) {
+            // started by a concurrent request for the same video
+            return Ok(());
+        }
+        jobs.insert(
+            key.to_string(),
+            Job {
+                cancel: cancel.clone(),
+                last_access: Instant::now(),
+            },
+        );
+    }
+
+    match spawn_ffmpeg(config, key, job_dir, full_path).await {
+        Ok((child, permit)) => {
+            let key = key.to_string();
+            let job_dir = job_dir.to_path_buf();
+            tokio::spawn(async move {
+                let _permit = permit;
+                wait_for_job(child, cancel, &key, &job_dir).await;
+                JOBS.lock().unwrap().remove(&key);
+            });
+            Ok(())
+        }
+        Err(resp) => {
+            JOBS.lock().unwrap().remove(key);
+            Err(resp)
+        }
+    }
+}
+
+async fn spawn_ffmpeg(
+    config: &HlsConfig,
+    key: &str,
+    job_dir: &std::path::Path,
+    full_path: &std::path::Path,
+) -> Result<(Child, OwnedSemaphorePermit), Response> {
+    let permit = match config.job_slots.clone().try_acquire_owned() {
+        Ok(permit) => permit,
+        Err(_) => {
+            log::warn!("all transcode job slots are busy, rejecting {:?}", full_path);
+            return Err(StatusCode::SERVICE_UNAVAILABLE.into_response());
+        }
+    };
+
+    let plan = match ffprobe::probe(full_path).await.map(|info| stream_plan(&info)) {
+        Ok(Some(plan)) => plan,
+        Ok(None) => return Err(AppError("not a video".to_string()).into_response()),
+        Err(e) => return Err(AppError(e.to_string()).into_response()),
+    };
+
+    // start over, segments of an aborted job can't be resumed
+    let _ = tokio::fs::remove_dir_all(job_dir).await;
+    tokio::fs::create_dir_all(job_dir)
+        .await
+        .map_err(|e| AppError(format!("failed to create HLS cache dir: {}", e)).into_response())?;
+
+    let child = tokio::process::Command::new("ffmpeg")
+        .args(["-nostdin", "-v", "error", "-i"])
+        .arg(full_path)
+        .args(["-map", "0:v:0", "-map", "0:a:0?", "-sn"])
+        .args(if plan.copy_video {
+            &["-c:v", "copy"][..]
+        } else {
+            &["-c:v", "libx264", "-preset", "veryfast", "-pix_fmt", "yuv420p"][..]
+        })
+        .args(if plan.copy_audio {
+            &["-c:a", "copy"][..]
+        } else {
+            &["-c:a", "aac", "-ac", "2"][..]
+        })
+        .args([
+            "-f",
+            "hls",
+            "-hls_time",
+            SEGMENT_SECONDS,
+            "-hls_list_size",
+            "0",
+            "-hls_playlist_type",
+            "event",
+            "-hls_flags",
+            "temp_file",
+            "-hls_segment_filename",
+        ])
+        .arg(job_dir.join("seg%05d.ts"))
+        .arg(job_dir.join(PLAYLIST_NAME))
+        .stdin(Stdio::null())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| AppError(format!("failed to run ffmpeg: {}", e)).into_response())?;
+
+    log::info!("started HLS job {} for {:?}, {:?}", key, full_path, plan);
+    Ok((child, permit))
+}
+
+async fn wait_for_job(
+    mut child: Child,
+    cancel: CancellationToken,
+    key: &str,
+    job_dir: &std::path::Path,
+) {
+    tokio::select! {
+        status = child.wait() => match status {
+            Ok(status) if status.success() => log::info!("HLS job {} finished", key),
+            _ => {
+                log::warn!("HLS job {} failed: {:?}", key, status);
+                let _ = tokio::fs::remove_dir_all(job_dir).await;
+            }
+        },
+        _ = cancel.cancelled() => {
+            log::info!("HLS job {} has no viewers, stopping", key);
+            let _ = child.kill().await;
+            let _ = tokio::fs::remove_dir_all(job_dir).await;
+        }
+    }
+}
+
+fn stream_plan(info: &MediaInfo) -> Option<StreamPlan> {
+    let video_codec = first_codec(info, StreamKind::Video)?;
+    let audio_codec = first_codec(info, StreamKind::Audio);
+    Some(StreamPlan {
+        copy_video: HLS_VIDEO_CODECS.contains(&video_codec),
+        copy_audio: audio_codec.iter().all(|codec| HLS_AUDIO_CODECS.contains(codec)),
+    })
+}
+
+fn first_codec(info: &MediaInfo, kind: StreamKind) -> Option<&str> {
+    info.first_stream(kind)?.codec_name.as_deref()
+}
+
+fn touch(key: &str) {
+    let now = Instant::now();
+    LAST_ACCESS.lock().unwrap().insert(key.to_string(), now);
+    if let Some(job) = JOBS.lock().unwrap().get_mut(key) {
+        job.last_access = now;
+    }
+}
+
+fn stop_idle_jobs() {
+    for job in JOBS.lock().unwrap().values() {
+        if job.last_access.elapsed() > IDLE_TIMEOUT {
+            job.cancel.cancel();
+        }
+    }
+}
+
+/// Removes the least recently watched cache entries until the cache fits in
+/// its size limit, entries of running jobs are never removed.
+fn evict_cache() {
+    let config = match CONFIG.get() {
+        Some(config) => config,
+        None => return,
+    };
+    let entries = match std::fs::read_dir(&config.cache_dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+
+    let last_access = LAST_ACCESS.lock().unwrap().clone();
+    let mut entries = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| {
+            let key = entry.file_name().to_string_lossy().to_string();
+            let size = walkdir::WalkDir::new(entry.path())
+                .into_iter()
+                .filter_map(|e| e.ok())
+                .filter_map(|e| e.metadata().ok())
+                .map(|m| m.len())
+                .sum::<u64>();
+            // entries from a previous run fall back to the dir's mtime
+            let accessed = last_access.get(&key).copied().or_else(|| {
+                let modified = entry.metadata().ok()?.modified().ok()?;
+                Instant::now().checked_sub(modified.elapsed().ok()?)
+            });
+            (key, entry.path(), size, accessed)
+        })
+        .collect::<Vec<_>>();
+
+    let mut total = entries.iter().map(|e| e.2).sum::<u64>();
+    entries.sort_by_key(|e| e.3);
+    for (key, path, size, _) in entries {
+        if total <= config.cache_size {
+            break;
+        }
+        if JOBS.lock().unwrap().contains_key(&key) {
+            continue;
+        }
+        log::info!("evicting HLS cache entry: {:?}", path);
+        if std::fs::remove_dir_all(&path).is_ok() {
+            total -= size;
+            LAST_ACCESS.lock().unwrap().remove(&key);
+        }
+    }
+}
+
+fn cache_key(full_path: &std::path::Path) -> String {
+    let mut hasher = DefaultHasher::new();
+    full_path.hash(&mut hasher);
+    if let Ok(metadata) = full_path.metadata() {
+        metadata.len().hash(&mut hasher);
+        if let Ok(modified) = metadata.modified() {
+            modified
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .hash(&mut hasher);
+        }
+    }
+    format!("{:016x}", hasher.finish())
+}
+
+fn is_segment_name(name: &str) -> bool {
+    let number = name
+        .strip_prefix("seg")
+        .and_then(|name| name.strip_suffix(".ts"));
+    matches!(number, Some(n) if !n.is_empty() && n.chars().all(|c| c.is_ascii_digit()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::StreamInfo;
+
+    fn media(codecs: &[(StreamKind, &str)]) -> MediaInfo {
+        let streams = codecs
+            .iter()
+            .enumerate()
+            .map(|(index, (kind, codec))| StreamInfo {
+                index: index as u32,
+                kind: *kind,
+                codec_name: Some(codec.to_string()),
+                codec_long_name: None,
+                profile: None,
+                width: None,
+                height: None,
+                frame_rate: None,
+                channels: None,
+                sample_rate: None,
+                bit_rate: None,
+                language: None,
+                title: None,
+            })
+            .collect();
+        MediaInfo {
+            format_name: String::new(),
+            format_long_name: None,
+            duration: None,
+            bit_rate: None,
+            size: None,
+            streams,
+        }
+    }
+
+    #[test]
+    fn plans_streams() {
+        let info = media(&[(StreamKind::Video, "h264"), (StreamKind::Audio, "aac")]);
+        let plan = StreamPlan {
+            copy_video: true,
+            copy_audio: true,
+        };
+        assert_eq!(stream_plan(&info), Some(plan));
+
+        let info = media(&[(StreamKind::Video, "hevc"), (StreamKind::Audio, "ac3")]);
+        let plan = StreamPlan {
+            copy_video: false,
+            copy_audio: false,
+        };
+        assert_eq!(stream_plan(&info), Some(plan));
+
+        let info = media(&[(StreamKind::Video, "h264")]);
+        assert_eq!(stream_plan(&info).map(|plan| plan.copy_audio), Some(true));
+        assert_eq!(stream_plan(&media(&[(StreamKind::Audio, "aac")])), None);
+    }
+
+    #[test]
+    fn accepts_only_segment_names() {
+        assert!(is_segment_name("seg0.ts"));
+        assert!(is_segment_name("seg123.ts"));
+        assert!(!is_segment_name("seg.ts"));
+        assert!(!is_segment_name("seg1.ts.part"));
+        assert!(!is_segment_name("seg-1.ts"));
+        assert!(!is_segment_name("../seg1.ts"));
+        assert!(!is_segment_name("seg../1.ts"));
+        assert!(!is_segment_name("/tmp/seg1.ts"));
+        assert!(!is_segment_name(PLAYLIST_NAME));
+    }
+
+    #[test]
+    fn transcodes_only_unplayable_videos() {
+        CONFIG.get_or_init(|| HlsConfig {
+            cache_dir: std::env::temp_dir(),
+            cache_size: 0,
+            job_slots: Arc::new(Semaphore::new(1)),
+        });
+
+        let mkv = media(&[(StreamKind::Video, "hevc"), (StreamKind::Audio, "aac")]);
+        assert_eq!(
+            hls_url("/videos/movie.mkv", "movie.mkv", &mkv).as_deref(),
+            Some("/api/hls/videos/movie.mkv/index.m3u8")
+        );
+        let mp4 = media(&[(StreamKind::Video, "h264"), (StreamKind::Audio, "aac")]);
+        assert_eq!(hls_url("/videos/movie.MP4", "movie.MP4", &mp4), None);
+        assert!(hls_url("/videos/movie.mkv", "movie.mkv", &mp4).is_some());
+        let ac3 = media(&[(StreamKind::Video, "h264"), (StreamKind::Audio, "ac3")]);
+        assert!(hls_url("/videos/movie.mp4", "movie.mp4", &ac3).is_some());
+        let audio = media(&[(StreamKind::Audio, "flac")]);
+        assert_eq!(hls_url("/music/song.mkv", "song.mkv", &audio), None);
+    }
+}
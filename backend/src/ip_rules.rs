@@ -0,0 +1,315 @@
+use crate::proxy;
+use axum::http::{Method, Request, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use common::JsonResponse;
+use once_cell::sync::OnceCell;
+use percent_encoding::percent_decode_str;
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+/// What a rule grants the clients it matches.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum Access {
+    Allow,
+    /// Everything but uploads, deletions and other changes
+    ReadOnly,
+    Deny,
+}
+
+/// A network such as 10.1.0.0/16, a plain IP is a network of its own.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix_len: u8,
+}
+
+impl Cidr {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, canonical(ip)) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - self.prefix_len as u32)
+                    .unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - self.prefix_len as u32)
+                    .unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix_len) = match s.split_once('/') {
+            Some((addr, prefix_len)) => (addr, Some(prefix_len)),
+            None => (s, None),
+        };
+        let addr = addr
+            .parse::<IpAddr>()
+            .map_err(|e| format!("invalid address {:?}: {}", addr, e))?;
+        let max_len = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix_len = match prefix_len {
+            Some(prefix_len) => prefix_len
+                .parse::<u8>()
+                .ok()
+                .filter(|prefix_len| *prefix_len <= max_len)
+                .ok_or_else(|| format!("invalid prefix length: {}", prefix_len))?,
+            None => max_len,
+        };
+        Ok(Cidr { addr, prefix_len })
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix_len)
+    }
+}
+
+/// IPv4 clients of a dual stack listener show up as IPv4-mapped IPv6
+/// addresses.
+fn canonical(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(ip),
+        ip => ip,
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct IpRule {
+    pub access: Access,
+    pub network: Cidr,
+    /// Path prefix the rule is limited to, e.g. "/api/audit", relative to
+    /// the base path
+    pub path: Option<String>,
+}
+
+impl IpRule {
+    fn matches(&self, ip: IpAddr, path: &str) -> bool {
+        let in_scope = match &self.path {
+            Some(prefix) => match path.strip_prefix(prefix.as_str()) {
+                Some(rest) => rest.is_empty() || rest.starts_with('/'),
+                None => false,
+            },
+            None => true,
+        };
+        in_scope && self.network.contains(ip)
+    }
+}
+
+/// Parses `<access>=<cidr>[@<path>]`, e.g. read-only=10.1.0.0/16 or
+/// deny=192.168.50.0/24@/api/audit.
+impl FromStr for IpRule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (access, rest) = s
+            .split_once('=')
+            .ok_or_else(|| "expected <allow|read-only|deny>=<cidr>[@<path>]".to_string())?;
+        let access = <Access as clap::ValueEnum>::from_str(access.trim(), true)?;
+        let (network, path) = match rest.split_once('@') {
+            Some((network, path)) => (network, Some(format!("/{}", path.trim_matches('/')))),
+            None => (rest, None),
+        };
+        Ok(IpRule {
+            access,
+            network: network.trim().parse()?,
+            path,
+        })
+    }
+}
+
+static RULES: OnceCell<Vec<IpRule>> = OnceCell::new();
+
+pub fn init(rules: Vec<IpRule>) {
+    for rule in &rules {
+        log::info!(
+            "ip rule: {:?} {}{}",
+            rule.access,
+            rule.network,
+            rule.path
+                .as_ref()
+                .map(|path| format!(" on {}", path))
+                .unwrap_or_default()
+        );
+    }
+    RULES.set(rules).ok();
+}
+
+/// Whether a request changes anything on the server.
+fn is_write(method: &Method, path: &str) -> bool {
+    match *method {
+        Method::GET | Method::HEAD | Method::OPTIONS => false,
+        // reading the root directory and unlocking share links are posts too
+        Method::POST => path != "/api/listing" && !path.starts_with("/s/"),
+        _ => true,
+    }
+}
+
+/// Decodes a request path and collapses repeated slashes the way the router
+/// and the file handlers see it, so that "/api/%61udit" or "//api/audit"
+/// can't slip past a rule for "/api/audit".
+fn normalize(path: &str) -> String {
+    let path = percent_decode_str(path).decode_utf8_lossy();
+    let mut normalized = String::with_capacity(path.len());
+    for c in path.chars() {
+        if !(c == '/' && normalized.ends_with('/')) {
+            normalized.push(c);
+        }
+    }
+    normalized
+}
+
+/// Checks a request against the first rule matching its client and path,
+/// requests no rule matches are allowed.
+fn check<B>(rules: &[IpRule], req: &Request<B>) -> Result<(), &'static str> {
+    let ip = match proxy::client_ip(req.extensions(), req.headers()) {
+        Some(ip) => ip,
+        None => return Err("access denied"),
+    };
+    let path = normalize(req.uri().path());
+    let path = path.strip_prefix(proxy::base_path()).unwrap_or(&path);
+    match rules.iter().find(|rule| rule.matches(ip, path)) {
+        Some(rule) if rule.access == Access::Deny => Err("access denied"),
+        Some(rule) if rule.access == Access::ReadOnly && is_write(req.method(), path) => {
+            Err("read-only access from this network")
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Answers requests the rules deny with 403.
+pub async fn enforce<B>(req: Request<B>, next: Next<B>) -> Response {
+    let rules = match RULES.get() {
+        Some(rules) if !rules.is_empty() => rules,
+        _ => return next.run(req).await,
+    };
+    if let Err(msg) = check(rules, &req) {
+        log::warn!("{} {} denied by ip rules", req.method(), req.uri());
+        let json_resp = Json(JsonResponse::Failed {
+            msg: Some(msg.to_string()),
+        });
+        return (StatusCode::FORBIDDEN, json_resp).into_response();
+    }
+    next.run(req).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::extract::ConnectInfo;
+    use std::net::SocketAddr;
+
+    fn rules(rules: &[&str]) -> Vec<IpRule> {
+        rules.iter().map(|rule| rule.parse().unwrap()).collect()
+    }
+
+    fn request(method: Method, path: &str, peer: &str) -> Request<()> {
+        let mut req = Request::builder()
+            .method(method)
+            .uri(path)
+            .body(())
+            .unwrap();
+        let peer = SocketAddr::new(peer.parse().unwrap(), 40000);
+        req.extensions_mut().insert(ConnectInfo(peer));
+        req
+    }
+
+    #[test]
+    fn parses_rules() {
+        let rule: IpRule = "read-only=10.1.0.0/16".parse().unwrap();
+        assert_eq!(rule.access, Access::ReadOnly);
+        assert_eq!(rule.network, "10.1.0.0/16".parse().unwrap());
+        assert_eq!(rule.path, None);
+
+        let rule: IpRule = "deny=fd00::/8@/api/audit/".parse().unwrap();
+        assert_eq!(rule.access, Access::Deny);
+        assert_eq!(rule.network.to_string(), "fd00::/8");
+        assert_eq!(rule.path.as_deref(), Some("/api/audit"));
+
+        let rule: IpRule = "allow=10.0.0.1".parse().unwrap();
+        assert_eq!(rule.network.to_string(), "10.0.0.1/32");
+        assert!("allow=10.0.0.0/33".parse::<IpRule>().is_err());
+        assert!("permit=10.0.0.0/8".parse::<IpRule>().is_err());
+        assert!("allow".parse::<IpRule>().is_err());
+    }
+
+    #[test]
+    fn matches_networks() {
+        let net: Cidr = "10.1.0.0/16".parse().unwrap();
+        assert!(net.contains("10.1.2.3".parse().unwrap()));
+        assert!(net.contains("::ffff:10.1.2.3".parse().unwrap()));
+        assert!(!net.contains("10.2.0.1".parse().unwrap()));
+        assert!(!net.contains("fd00::1".parse().unwrap()));
+
+        let any: Cidr = "0.0.0.0/0".parse().unwrap();
+        assert!(any.contains("192.168.1.1".parse().unwrap()));
+
+        let net: Cidr = "fd00:1::/32".parse().unwrap();
+        assert!(net.contains("fd00:1::5".parse().unwrap()));
+        assert!(!net.contains("fd00:2::5".parse().unwrap()));
+    }
+
+    #[test]
+    fn allows_only_listed_networks() {
+        let rules = rules(&[
+            "allow=10.0.0.0/8",
+            "allow=::1",
+            "deny=0.0.0.0/0",
+            "deny=::/0",
+        ]);
+        assert!(check(&rules, &request(Method::GET, "/api/listing", "10.3.4.5")).is_ok());
+        assert!(check(&rules, &request(Method::GET, "/", "::1")).is_ok());
+        assert!(check(&rules, &request(Method::GET, "/api/listing", "192.168.1.5")).is_err());
+        assert!(check(&rules, &request(Method::GET, "/", "fd00::1")).is_err());
+    }
+
+    #[test]
+    fn makes_networks_read_only() {
+        let rules = rules(&["read-only=10.1.0.0/16"]);
+        let guest = "10.1.7.7";
+        assert!(check(&rules, &request(Method::GET, "/api/static/a.txt", guest)).is_ok());
+        assert!(check(&rules, &request(Method::POST, "/api/listing", guest)).is_ok());
+        assert!(check(&rules, &request(Method::POST, "/s/token", guest)).is_ok());
+        assert!(check(&rules, &request(Method::POST, "/api/upload/dir", guest)).is_err());
+        assert!(check(&rules, &request(Method::POST, "/api/delete/a.txt", guest)).is_err());
+        assert!(check(&rules, &request(Method::PUT, "/api/edit/a.txt", guest)).is_err());
+        assert!(check(&rules, &request(Method::POST, "/api/%6Cisting", guest)).is_ok());
+        assert!(check(
+            &rules,
+            &request(Method::POST, "/api/upload/dir", "10.2.0.1")
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn scopes_rules_to_paths() {
+        let rules = rules(&["deny=10.1.0.0/16@/api/audit", "read-only=10.1.0.0/16"]);
+        let guest = "10.1.7.7";
+        assert!(check(&rules, &request(Method::GET, "/api/audit", guest)).is_err());
+        assert!(check(&rules, &request(Method::GET, "/api/audit/x", guest)).is_err());
+        assert!(check(&rules, &request(Method::GET, "/api/%61udit", guest)).is_err());
+        assert!(check(&rules, &request(Method::GET, "//api/audit", guest)).is_err());
+        assert!(check(&rules, &request(Method::GET, "/api//audit/x", guest)).is_err());
+        assert!(check(&rules, &request(Method::GET, "/api/auditing", guest)).is_ok());
+        assert!(check(&rules, &request(Method::GET, "/api/listing", guest)).is_ok());
+        assert!(check(&rules, &request(Method::GET, "/api/audit", "10.2.0.1")).is_ok());
+    }
+
+    #[test]
+    fn denies_unknown_peers() {
+        let rules = rules(&["allow=0.0.0.0/0"]);
+        let req = Request::builder().uri("/").body(()).unwrap();
+        assert!(check(&rules, &req).is_err());
+    }
+}
@@ -0,0 +1,607 @@
+use crate::store::{load_json, save_json};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use common::JsonResponse;
+use once_cell::sync::{Lazy, OnceCell};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use walkdir::WalkDir;
+
+/// Free space is checked again after writing this many bytes.
+const SPACE_CHECK_INTERVAL: u64 = 8 * 1024 * 1024;
+
+pub struct UploadLimits {
+    pub max_file_size: Option<u64>,
+    pub max_request_size: Option<u64>,
+    /// Free space to leave on the disk
+    pub min_free_space: u64,
+    pub dir_quotas: Vec<DirQuota>,
+    /// Bytes each user may keep on the server, "*" for users without a quota
+    /// of their own
+    pub user_quotas: Vec<(String, u64)>,
+    /// File to keep track of who uploaded what in across restarts
+    pub uploads_file: Option<PathBuf>,
+}
+
+/// A size limit for a directory and everything under it.
+pub struct DirQuota {
+    /// Path of the directory as seen by clients, e.g. "/uploads"
+    pub path: String,
+    pub full_path: PathBuf,
+    pub quota: u64,
+}
+
+static LIMITS: OnceCell<UploadLimits> = OnceCell::new();
+
+/// A file or an extracted directory and the user who uploaded it.
+#[derive(Serialize, Deserialize)]
+struct Upload {
+    path: PathBuf,
+    user: String,
+}
+
+/// Uploader of each file, only tracked if there are user quotas. The files
+/// count against the quota of their uploader for as long as they exist.
+static UPLOADS: Lazy<Mutex<HashMap<PathBuf, String>>> = Lazy::new(Default::default);
+
+pub fn init(limits: UploadLimits) {
+    log::info!(
+        "upload limits: max file size: {:?}, max request size: {:?}, min free space: {} bytes",
+        limits.max_file_size,
+        limits.max_request_size,
+        limits.min_free_space
+    );
+    for dir_quota in &limits.dir_quotas {
+        log::info!("quota of {}: {} bytes", dir_quota.path, dir_quota.quota);
+    }
+    for (user, quota) in &limits.user_quotas {
+        log::info!("quota of user {}: {} bytes", user, quota);
+    }
+    if let Some(file) = &limits.uploads_file {
+        let uploads = load_json::<Upload>(file);
+        log::info!(
+            "loaded the uploaders of {} file(s) from {:?}",
+            uploads.len(),
+            file
+        );
+        let mut all = UPLOADS.lock().unwrap();
+        for upload in uploads {
+            all.insert(upload.path, upload.user);
+        }
+    }
+    LIMITS.set(limits).ok();
+}
+
+/// The limits the frontend checks files against before sending them.
+pub fn public_limits() -> common::UploadLimits {
+    match LIMITS.get() {
+        Some(limits) => common::UploadLimits {
+            max_file_size: limits.max_file_size,
+            max_request_size: limits.max_request_size,
+        },
+        None => Default::default(),
+    }
+}
+
+fn persist(uploads: &HashMap<PathBuf, String>) {
+    if let Some(file) = LIMITS.get().and_then(|limits| limits.uploads_file.as_ref()) {
+        let uploads = uploads
+            .iter()
+            .map(|(path, user)| Upload {
+                path: path.clone(),
+                user: user.clone(),
+            })
+            .collect::<Vec<_>>();
+        save_json(file, &uploads);
+    }
+}
+
+/// Records `user` as the uploader of `path`, a file or a directory that has
+/// been extracted, if users have quotas.
+pub fn record_upload(user: Option<&str>, path: &Path) {
+    let user = match (user, LIMITS.get()) {
+        (Some(user), Some(limits)) if !limits.user_quotas.is_empty() => user,
+        _ => return,
+    };
+    let mut uploads = UPLOADS.lock().unwrap();
+    uploads.insert(path.to_path_buf(), user.to_string());
+    persist(&uploads);
+}
+
+/// Bytes of the uploads of `user` that still exist, the others are
+/// forgotten.
+fn user_usage(user: &str) -> u64 {
+    let paths = UPLOADS
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|(_, uploader)| *uploader == user)
+        .map(|(path, _)| path.clone())
+        .collect::<Vec<_>>();
+    let mut used = 0;
+    let mut gone = vec![];
+    for path in paths {
+        match std::fs::metadata(&path) {
+            Ok(metadata) if metadata.is_dir() => used += dir_size(&path),
+            Ok(metadata) => used += metadata.len(),
+            Err(_) => gone.push(path),
+        }
+    }
+    if !gone.is_empty() {
+        let mut uploads = UPLOADS.lock().unwrap();
+        for path in gone {
+            uploads.remove(&path);
+        }
+        persist(&uploads);
+    }
+    used
+}
+
+/// An upload exceeding a limit, answered with 413 or 507 and the usual
+/// `JsonResponse::Failed` body.
+#[derive(Debug)]
+pub struct LimitError(StatusCode, String);
+
+impl LimitError {
+    pub fn message(&self) -> &str {
+        &self.1
+    }
+}
+
+impl IntoResponse for LimitError {
+    fn into_response(self) -> Response {
+        let json_resp = Json(JsonResponse::Failed { msg: Some(self.1) });
+        (self.0, json_resp).into_response()
+    }
+}
+
+/// Checks the bytes of an upload request against the limits as they are
+/// written, so that an upload is aborted as soon as it exceeds one.
+pub struct UploadGuard {
+    dir: PathBuf,
+    written: u64,
+    file_written: u64,
+    /// Bytes of the files saved so far
+    saved: u64,
+    unchecked: u64,
+    /// The quotas of the directories files went into so far
+    dir_quotas: Vec<QuotaUsage>,
+    /// Index into `dir_quotas` of the quota of the current file
+    file_quota: Option<usize>,
+    /// The quota of the user and the bytes left of it
+    user_left: Option<(u64, u64)>,
+    /// Limits of the drop box link the upload came through
+    link_max_file_size: Option<u64>,
+    link_left: Option<u64>,
+}
+
+/// Bytes left in the quota of a directory and written into it.
+struct QuotaUsage {
+    dir_quota: &'static DirQuota,
+    left: u64,
+    written: u64,
+}
+
+impl UploadGuard {
+    /// Rejects the request up front if its `Content-Length` already exceeds
+    /// a limit. Files are saved into `dir` unless `set_dir` says otherwise,
+    /// `user` is the user uploading them, if known.
+    pub async fn new(
+        dir: &Path,
+        content_length: Option<u64>,
+        user: Option<&str>,
+    ) -> Result<Self, LimitError> {
+        let limits = match LIMITS.get() {
+            Some(limits) => limits,
+            None => return Ok(Self::unlimited(dir)),
+        };
+        let length = content_length.unwrap_or(0);
+        if let Some(max) = limits.max_request_size {
+            if length > max {
+                return Err(too_large(format!(
+                    "the upload is larger than the limit of {} bytes",
+                    max
+                )));
+            }
+        }
+
+        let mut guard = Self::unlimited(dir);
+        guard.check_space(length)?;
+
+        let user_quota = user.and_then(|user| {
+            let quota = |name: &str| {
+                limits
+                    .user_quotas
+                    .iter()
+                    .find(|(user, _)| user == name)
+                    .map(|(_, quota)| *quota)
+            };
+            quota(user)
+                .or_else(|| quota("*"))
+                .map(|quota| (user.to_string(), quota))
+        });
+        if let Some((user, quota)) = user_quota {
+            let used = tokio::task::spawn_blocking(move || user_usage(&user))
+                .await
+                .unwrap_or(0);
+            let left = quota.saturating_sub(used);
+            if length > left {
+                return Err(user_quota_exceeded(quota));
+            }
+            guard.user_left = Some((quota, left));
+        }
+
+        guard.set_dir(dir).await;
+        // all files count against the quota of the directory, unless a
+        // directory under it has one of its own
+        let nested = limits
+            .dir_quotas
+            .iter()
+            .any(|dir_quota| dir_quota.full_path.starts_with(dir) && dir_quota.full_path != dir);
+        if let Some(usage) = guard.file_quota.map(|i| &guard.dir_quotas[i]) {
+            if length > usage.left && !nested {
+                return Err(quota_exceeded(usage.dir_quota));
+            }
+        }
+        Ok(guard)
+    }
+
+    /// Files saved from now on go into `dir`, the innermost quota of which
+    /// applies to them.
+    pub async fn set_dir(&mut self, dir: &Path) {
+        let limits = match LIMITS.get() {
+            Some(limits) => limits,
+            None => return,
+        };
+        let dir_quota = limits
+            .dir_quotas
+            .iter()
+            .filter(|dir_quota| dir.starts_with(&dir_quota.full_path))
+            .max_by_key(|dir_quota| dir_quota.full_path.components().count());
+        let dir_quota = match dir_quota {
+            Some(dir_quota) => dir_quota,
+            None => {
+                self.file_quota = None;
+                return;
+            }
+        };
+        let known = self
+            .dir_quotas
+            .iter()
+            .position(|usage| std::ptr::eq(usage.dir_quota, dir_quota));
+        self.file_quota = match known {
+            Some(i) => Some(i),
+            None => {
+                let scan_dir = dir_quota.full_path.clone();
+                let used = tokio::task::spawn_blocking(move || dir_size(&scan_dir))
+                    .await
+                    .unwrap_or(0);
+                self.dir_quotas.push(QuotaUsage {
+                    dir_quota,
+                    left: dir_quota.quota.saturating_sub(used),
+                    written: 0,
+                });
+                Some(self.dir_quotas.len() - 1)
+            }
+        };
+    }
+
+    fn unlimited(dir: &Path) -> Self {
+        UploadGuard {
+            dir: dir.to_path_buf(),
+            written: 0,
+            file_written: 0,
+            saved: 0,
+            unchecked: 0,
+            dir_quotas: vec![],
+            file_quota: None,
+            user_left: None,
+            link_max_file_size: None,
+            link_left: None,
+        }
+    }
+
+    /// Applies the limits of a drop box link on top of the global ones,
+    /// `left` being the bytes the link may still take. Unlike the global
+    /// limits these are small enough for the multipart overhead to matter, so
+    /// they are only checked against the bytes of the files.
+    pub fn restrict(&mut self, max_file_size: Option<u64>, left: Option<u64>) {
+        self.link_max_file_size = max_file_size;
+        self.link_left = left;
+    }
+
+    /// Bytes of the files saved so far.
+    pub fn saved(&self) -> u64 {
+        self.saved
+    }
+
+    pub fn start_file(&mut self) {
+        self.file_written = 0;
+    }
+
+    /// Accounts for the current file being saved, returns its size.
+    pub fn file_saved(&mut self) -> u64 {
+        self.saved += self.file_written;
+        self.file_written
+    }
+
+    /// Accounts for `len` more bytes about to be written.
+    pub fn add(&mut self, len: u64) -> Result<(), LimitError> {
+        self.written += len;
+        self.file_written += len;
+        self.unchecked += len;
+
+        if let Some(max) = self.link_max_file_size {
+            if self.file_written > max {
+                return Err(too_large(format!(
+                    "the file is larger than the limit of {} bytes",
+                    max
+                )));
+            }
+        }
+        if let Some(left) = self.link_left {
+            if self.written > left {
+                return Err(link_exceeded());
+            }
+        }
+
+        let limits = match LIMITS.get() {
+            Some(limits) => limits,
+            None => return Ok(()),
+        };
+
+        if let Some(max) = limits.max_file_size {
+            if self.file_written > max {
+                return Err(too_large(format!(
+                    "the file is larger than the limit of {} bytes",
+                    max
+                )));
+            }
+        }
+        if let Some(max) = limits.max_request_size {
+            if self.written > max {
+                return Err(too_large(format!(
+                    "the upload is larger than the limit of {} bytes",
+                    max
+                )));
+            }
+        }
+        if let Some(usage) = self.file_quota.map(|i| &mut self.dir_quotas[i]) {
+            usage.written += len;
+            if usage.written > usage.left {
+                return Err(quota_exceeded(usage.dir_quota));
+            }
+        }
+        if let Some((quota, left)) = self.user_left {
+            if self.written > left {
+                return Err(user_quota_exceeded(quota));
+            }
+        }
+        if self.unchecked >= SPACE_CHECK_INTERVAL {
+            self.unchecked = 0;
+            self.check_space(0)?;
+        }
+        Ok(())
+    }
+
+    fn check_space(&self, len: u64) -> Result<(), LimitError> {
+        let min_free_space = LIMITS
+            .get()
+            .map(|limits| limits.min_free_space)
+            .unwrap_or(0);
+        if min_free_space == 0 {
+            return Ok(());
+        }
+        match available_space(&self.dir) {
+            Some(available) if available < min_free_space.saturating_add(len) => Err(LimitError(
+                StatusCode::INSUFFICIENT_STORAGE,
+                "not enough free disk space on the server".to_string(),
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+fn too_large(msg: String) -> LimitError {
+    LimitError(StatusCode::PAYLOAD_TOO_LARGE, msg)
+}
+
+fn quota_exceeded(dir_quota: &DirQuota) -> LimitError {
+    too_large(format!(
+        "the upload exceeds the quota of {} bytes of {}",
+        dir_quota.quota, dir_quota.path
+    ))
+}
+
+fn user_quota_exceeded(quota: u64) -> LimitError {
+    too_large(format!("the upload exceeds your quota of {} bytes", quota))
+}
+
+fn link_exceeded() -> LimitError {
+    too_large("the upload exceeds the size limit of this link".to_string())
+}
+
+/// Total size of the files under `dir`.
+fn dir_size(dir: &Path) -> u64 {
+    WalkDir::new(dir)
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_file())
+        .filter_map(|entry| entry.metadata().ok())
+        .map(|metadata| metadata.len())
+        .sum()
+}
+
+/// Bytes available to unprivileged users on the file system of `path`, or of
+/// its closest existing ancestor for directories created by the upload.
+pub fn available_space(path: &Path) -> Option<u64> {
+    let path = path.ancestors().find(|path| path.exists())?;
+    let c_path = CString::new(path.as_os_str().as_bytes()).ok()?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(c_path.as_ptr(), &mut stat) } != 0 {
+        return None;
+    }
+    Some(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Directory of the tests of this module, in the served directory.
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = crate::test_serve_dir().join("limits").join(name);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn init_limits() {
+        let dir_quota = |path: &str, quota| DirQuota {
+            path: format!("/limits/{}", path),
+            full_path: test_dir(path),
+            quota,
+        };
+        // shared by the tests of all modules, the quotas only apply to the
+        // directories of this one
+        LIMITS.get_or_init(|| UploadLimits {
+            max_file_size: Some(1_000_000),
+            max_request_size: Some(2_000_000),
+            min_free_space: 0,
+            dir_quotas: vec![dir_quota("nested", 1000), dir_quota("nested/inner", 300)],
+            user_quotas: vec![("alice".to_string(), 500), ("*".to_string(), 1000)],
+            uploads_file: None,
+        });
+    }
+
+    async fn guard(dir: &Path, length: Option<u64>, user: Option<&str>) -> UploadGuard {
+        init_limits();
+        match UploadGuard::new(dir, length, user).await {
+            Ok(guard) => guard,
+            Err(e) => panic!("unexpected rejection: {}", e.message()),
+        }
+    }
+
+    async fn rejects(dir: &Path, length: Option<u64>, user: Option<&str>) -> String {
+        init_limits();
+        match UploadGuard::new(dir, length, user).await {
+            Ok(_) => panic!("accepted {:?} bytes into {:?}", length, dir),
+            Err(e) => e.message().to_string(),
+        }
+    }
+
+    fn exceeds(result: Result<(), LimitError>) -> String {
+        match result {
+            Ok(()) => panic!("not exceeded"),
+            Err(LimitError(status, msg)) => {
+                assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+                msg
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn applies_the_innermost_quota() {
+        let outer = test_dir("nested");
+        let inner = test_dir("nested/inner");
+        std::fs::write(outer.join("existing"), vec![0; 600]).unwrap();
+
+        // the inner quota has room left even though the outer one hasn't
+        let msg = rejects(&inner, Some(301), None).await;
+        assert!(msg.contains("300 bytes of /limits/nested/inner"), "{}", msg);
+        let mut upload = guard(&outer, Some(800), None).await;
+        upload.set_dir(&inner).await;
+        upload.start_file();
+        upload.add(250).unwrap();
+        upload.file_saved();
+
+        // files in the inner directory don't count against the outer quota
+        upload.set_dir(&outer).await;
+        upload.start_file();
+        upload.add(400).unwrap();
+        let msg = exceeds(upload.add(1));
+        assert!(msg.contains("1000 bytes of /limits/nested"), "{}", msg);
+
+        // but the bytes written before do against the inner one
+        upload.set_dir(&inner).await;
+        upload.start_file();
+        upload.add(50).unwrap();
+        exceeds(upload.add(1));
+    }
+
+    #[tokio::test]
+    async fn applies_user_quotas() {
+        let dir = test_dir("users");
+        let file = dir.join("alice.bin");
+        std::fs::write(&file, vec![0; 400]).unwrap();
+        record_upload(Some("alice"), &file);
+
+        let msg = rejects(&dir, Some(101), Some("alice")).await;
+        assert!(msg.contains("your quota of 500 bytes"), "{}", msg);
+        let mut upload = guard(&dir, None, Some("alice")).await;
+        upload.start_file();
+        upload.add(100).unwrap();
+        exceeds(upload.add(1));
+
+        // other users have a quota of their own, users that aren't known
+        // have none
+        guard(&dir, Some(1000), Some("bob")).await;
+        rejects(&dir, Some(1001), Some("bob")).await;
+        guard(&dir, Some(1001), None).await;
+
+        // deleted files don't count anymore
+        std::fs::remove_file(&file).unwrap();
+        guard(&dir, Some(500), Some("alice")).await;
+    }
+
+    #[tokio::test]
+    async fn applies_link_restrictions() {
+        let dir = test_dir("restricted");
+        let mut upload = guard(&dir, None, None).await;
+        upload.restrict(Some(100), Some(150));
+        upload.start_file();
+        upload.add(90).unwrap();
+        assert_eq!(upload.file_saved(), 90);
+        upload.start_file();
+        upload.add(60).unwrap();
+        let msg = exceeds(upload.add(1));
+        assert!(msg.contains("this link"), "{}", msg);
+        assert_eq!(upload.saved(), 90);
+
+        let mut upload = guard(&dir, None, None).await;
+        upload.restrict(Some(100), None);
+        upload.start_file();
+        let msg = exceeds(upload.add(101));
+        assert!(msg.contains("limit of 100 bytes"), "{}", msg);
+    }
+
+    #[tokio::test]
+    async fn aborts_uploads_exceeding_limits_mid_stream() {
+        let dir = test_dir("stream");
+        let msg = rejects(&dir, Some(2_000_001), None).await;
+        assert!(msg.contains("limit of 2000000 bytes"), "{}", msg);
+
+        // without a Content-Length the size is only known while receiving
+        let mut upload = guard(&dir, None, None).await;
+        upload.start_file();
+        let chunk = 64 * 1024;
+        let accepted = (1..=20).take_while(|_| upload.add(chunk).is_ok()).count() as u64;
+        assert_eq!(accepted, 1_000_000 / chunk);
+
+        // the file size limit applies to each file, the request size limit to
+        // all of them
+        let mut upload = guard(&dir, None, None).await;
+        upload.start_file();
+        upload.add(1_000_000).unwrap();
+        let msg = exceeds(upload.add(1));
+        assert!(msg.contains("file is larger than the limit"), "{}", msg);
+        upload.start_file();
+        upload.add(999_999).unwrap();
+        let msg = exceeds(upload.add(1));
+        assert!(msg.contains("upload is larger than the limit"), "{}", msg);
+    }
+}
@@ -0,0 +1,224 @@
+use chrono::Local;
+use serde_json::{Map, Value};
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Mutex;
+use tracing::field::{Field, Visit};
+use tracing::level_filters::LevelFilter;
+use tracing::{Event, Subscriber};
+use tracing_log::NormalizeEvent;
+use tracing_subscriber::filter::Targets;
+use tracing_subscriber::fmt::format::Writer;
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
+use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormatFields, FormattedFields, MakeWriter};
+use tracing_subscriber::prelude::*;
+use tracing_subscriber::registry::LookupSpan;
+
+/// Number of rotated log files kept, as `<file>.1` to `<file>.N`.
+pub const ROTATED_FILES: usize = 5;
+
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+pub enum LogFormat {
+    Full,
+    Compact,
+    Pretty,
+    Json,
+}
+
+/// Installs the global subscriber, logging to `file` if given, to stderr
+/// otherwise. `RUST_LOG` takes precedence over `level`.
+pub fn init(level: &str, format: LogFormat, file: Option<RotatingFile>) {
+    let filter =
+        std::env::var("RUST_LOG").unwrap_or_else(|_| format!("{},hyper=info,mio=info", level));
+    let targets = Targets::from_str(&filter).unwrap_or_else(|e| {
+        eprintln!("invalid log filter {:?}: {}", filter, e);
+        Targets::new().with_default(LevelFilter::INFO)
+    });
+
+    let (writer, ansi) = match file {
+        Some(file) => (BoxMakeWriter::new(file), false),
+        None => (BoxMakeWriter::new(io::stderr), true),
+    };
+    // span fields are formatted with colors too, which JSON can do without
+    let ansi = ansi && !matches!(format, LogFormat::Json);
+    let layer = tracing_subscriber::fmt::layer()
+        .with_writer(writer)
+        .with_ansi(ansi);
+    let layer = match format {
+        LogFormat::Full => layer.boxed(),
+        LogFormat::Compact => layer.compact().boxed(),
+        LogFormat::Pretty => layer.pretty().boxed(),
+        LogFormat::Json => layer.event_format(JsonFormat).boxed(),
+    };
+    tracing_subscriber::registry()
+        .with(targets)
+        .with(layer)
+        .init();
+}
+
+/// Formats events as JSON lines with the time, level, target, message and
+/// other fields, plus the spans the event happened in.
+struct JsonFormat;
+
+impl<S, N> FormatEvent<S, N> for JsonFormat
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        // events of the log crate carry their real target in fields
+        let normalized = event.normalized_metadata();
+        let metadata = normalized.as_ref().unwrap_or_else(|| event.metadata());
+
+        let mut fields = JsonFields(Map::new());
+        fields.insert("time", Local::now().to_rfc3339().into());
+        fields.insert("level", metadata.level().to_string().into());
+        fields.insert("target", metadata.target().into());
+        event.record(&mut fields);
+
+        if let Some(scope) = ctx.event_scope() {
+            let spans = scope
+                .from_root()
+                .map(|span| {
+                    let mut value = Map::new();
+                    value.insert("name".to_string(), span.name().into());
+                    if let Some(fields) = span.extensions().get::<FormattedFields<N>>() {
+                        if !fields.is_empty() {
+                            value.insert("fields".to_string(), fields.as_str().into());
+                        }
+                    }
+                    Value::Object(value)
+                })
+                .collect::<Vec<_>>();
+            if !spans.is_empty() {
+                fields.insert("spans", spans.into());
+            }
+        }
+
+        writeln!(writer, "{}", Value::Object(fields.0))
+    }
+}
+
+struct JsonFields(Map<String, Value>);
+
+impl JsonFields {
+    fn insert(&mut self, name: &str, value: Value) {
+        self.0.insert(name.to_string(), value);
+    }
+
+    fn record_value(&mut self, field: &Field, value: Value) {
+        // already part of the normalized metadata
+        if !field.name().starts_with("log.") {
+            self.insert(field.name(), value);
+        }
+    }
+}
+
+impl Visit for JsonFields {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.record_value(field, format!("{:?}", value).into());
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.record_value(field, value.into());
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.record_value(field, value.into());
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.record_value(field, value.into());
+    }
+
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.record_value(field, value.into());
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.record_value(field, value.into());
+    }
+}
+
+/// A log file that is rotated once it grows larger than `max_size` bytes.
+pub struct RotatingFile {
+    path: PathBuf,
+    max_size: u64,
+    /// The open file and its size
+    file: Mutex<(File, u64)>,
+}
+
+impl RotatingFile {
+    pub fn open(path: PathBuf, max_size: u64) -> io::Result<RotatingFile> {
+        let file = open_append(&path)?;
+        let size = file.metadata()?.len();
+        Ok(RotatingFile {
+            path,
+            max_size,
+            file: Mutex::new((file, size)),
+        })
+    }
+
+    pub fn append(&self, buf: &[u8]) -> io::Result<()> {
+        let mut file = self.file.lock().unwrap();
+        if file.1 > 0 && file.1 + buf.len() as u64 > self.max_size {
+            // keeps writing to the current file if it can't be rotated
+            match rotate(&self.path).and_then(|_| open_append(&self.path)) {
+                Ok(new_file) => *file = (new_file, 0),
+                Err(e) => eprintln!("failed to rotate log file {:?}: {}", self.path, e),
+            }
+        }
+        file.0.write_all(buf)?;
+        file.1 += buf.len() as u64;
+        Ok(())
+    }
+}
+
+impl Write for &RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.append(buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<'a> MakeWriter<'a> for RotatingFile {
+    type Writer = &'a RotatingFile;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        self
+    }
+}
+
+fn open_append(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+pub fn rotated_file(file: &Path, index: usize) -> PathBuf {
+    let mut name = file.as_os_str().to_owned();
+    name.push(format!(".{}", index));
+    PathBuf::from(name)
+}
+
+/// Renames `<file>` to `<file>.1`, `<file>.1` to `<file>.2` and so on,
+/// dropping the oldest one.
+pub fn rotate(file: &Path) -> io::Result<()> {
+    for index in (1..ROTATED_FILES).rev() {
+        let from = rotated_file(file, index);
+        if from.exists() {
+            std::fs::rename(&from, rotated_file(file, index + 1))?;
+        }
+    }
+    std::fs::rename(file, rotated_file(file, 1))
+}
@@ -1,30 +1,63 @@
 use axum::extract::{Multipart, Path};
-use axum::http::StatusCode;
-use axum::response::Redirect;
-use axum::routing::{get_service, post};
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
+use axum::middleware;
+use axum::response::{Html, Redirect, Response};
+use axum::routing::{delete, get_service, post};
 use axum::Json;
 use axum::{response::IntoResponse, routing::get, Router};
-use axum_extra::routing::SpaRouter;
 use chrono::{DateTime, Local};
 use clap::Parser;
-use common::{DirDesc, DirEntry, FileType, JsonRequest, JsonResponse};
+use common::{DirDesc, DirEntry, FileType, JsonRequest, JsonResponse, ServerConfig};
 use local_ip_address::local_ip;
 use log::info;
+use once_cell::sync::OnceCell;
 use path_absolutize::Absolutize;
 use path_dedot::*;
 use serde::Deserialize;
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use std::path::PathBuf;
-use std::process::Command;
+use std::os::unix::io::RawFd;
 use std::str::FromStr;
+use std::time::Duration;
 use tokio::io::AsyncWriteExt;
+use tokio_util::sync::CancellationToken;
 use tower_http::services::ServeDir;
 
 use tower::ServiceBuilder;
 use tower_http::trace::TraceLayer;
 use walkdir::WalkDir;
 
+mod access_log;
+mod archive;
+mod audit;
+mod caching;
+mod checksum;
+mod compression;
+mod document;
+mod dropbox;
+mod edit;
+mod exif_info;
+mod ffprobe;
+mod hls;
+mod ip_rules;
+mod limits;
+mod logging;
+mod markdown;
+mod mdns;
+mod media;
+mod metrics;
+mod proxy;
+mod rate_limit;
+mod share;
+mod shutdown;
+mod store;
+mod systemd;
+mod text;
+mod thumbnail;
+
 static mut SERVE_DIR: Option<PathBuf> = None;
+static SERVER_CONFIG: OnceCell<ServerConfig> = OnceCell::new();
+static INDEX_FILE: OnceCell<PathBuf> = OnceCell::new();
 
 #[derive(Parser, Debug)]
 #[clap(
@@ -36,6 +69,32 @@ struct Opt {
     #[clap(short = 'l', long = "log", default_value = "debug")]
     log_level: String,
 
+    /// Format of the log messages
+    #[clap(long = "log-format", value_enum, default_value = "full")]
+    log_format: logging::LogFormat,
+
+    /// File to write the log to instead of stderr
+    #[clap(long = "log-file")]
+    log_file: Option<PathBuf>,
+
+    /// Size the log file and access log are rotated at, in MB
+    #[clap(long = "log-max-size", default_value = "10")]
+    log_max_size: u64,
+
+    /// Write an access log line for every request to this file, or to stdout
+    /// if "-", no access log is written if not specified
+    #[clap(long = "access-log")]
+    access_log: Option<PathBuf>,
+
+    /// Format of the access log
+    #[clap(long = "access-log-format", value_enum, default_value = "combined")]
+    access_log_format: access_log::AccessLogFormat,
+
+    /// IP of a reverse proxy whose X-Forwarded-For header is trusted to tell
+    /// the client IP, can be given multiple times
+    #[clap(long = "trusted-proxy")]
+    trusted_proxy: Vec<IpAddr>,
+
     /// Listen addr
     #[clap(short = 'a', long = "addr", default_value = "0.0.0.0")]
     addr: String,
@@ -51,18 +110,223 @@ struct Opt {
     /// Directory to serve, default to the current directory if not specified
     #[clap(long = "serve-dir", default_value = ".")]
     serve_dir: String,
+
+    /// Path to serve everything under, e.g. /files to be reachable as
+    /// https://intranet/files/ behind a reverse proxy that doesn't strip it
+    #[clap(long = "base-path", default_value = "", value_parser = parse_base_path)]
+    base_path: String,
+
+    /// Seconds to wait for in-flight transfers to finish after SIGINT/SIGTERM
+    #[clap(long = "shutdown-timeout", default_value = "30")]
+    shutdown_timeout: u64,
+
+    /// Serve on an already bound listening socket inherited as this fd,
+    /// instead of binding addr:port (systemd's LISTEN_FDS is honored too)
+    #[clap(long = "listen-fd")]
+    listen_fd: Option<RawFd>,
+
+    /// Advertise the server on the LAN over mDNS/DNS-SD
+    #[clap(long = "mdns")]
+    mdns: bool,
+
+    /// Instance name used for mDNS, the server is reachable as <name>.local
+    #[clap(long = "mdns-name", default_value = "fileserver")]
+    mdns_name: String,
+
+    /// Directory to cache HLS segments of transcoded videos in
+    #[clap(long = "hls-cache-dir")]
+    hls_cache_dir: Option<PathBuf>,
+
+    /// Size limit of the HLS segment cache, in MB
+    #[clap(long = "hls-cache-size", default_value = "2048")]
+    hls_cache_size: u64,
+
+    /// Max number of concurrent ffmpeg remux/transcode jobs
+    #[clap(long = "hls-max-jobs", default_value = "2")]
+    hls_max_jobs: usize,
+
+    /// Directory to cache image and video thumbnails in
+    #[clap(long = "thumbnail-cache-dir")]
+    thumbnail_cache_dir: Option<PathBuf>,
+
+    /// Size limit of the thumbnail cache, in MB
+    #[clap(long = "thumbnail-cache-size", default_value = "256")]
+    thumbnail_cache_size: u64,
+
+    /// Max size of an uploaded file, in MB
+    #[clap(long = "max-file-size")]
+    max_file_size: Option<u64>,
+
+    /// Max size of an upload request, in MB
+    #[clap(long = "max-request-size")]
+    max_request_size: Option<u64>,
+
+    /// Max size of the files extracted from an archive, in MB
+    #[clap(long = "max-extract-size", default_value = "4096")]
+    max_extract_size: u64,
+
+    /// Free disk space to keep, uploads that would use it are rejected, in MB
+    #[clap(long = "min-free-space", default_value = "256")]
+    min_free_space: u64,
+
+    /// Size limit of a directory and everything under it, as <dir>=<MB> with
+    /// dir relative to serve-dir, can be given multiple times
+    #[clap(long = "dir-quota", value_parser = parse_dir_quota)]
+    dir_quota: Vec<(String, u64)>,
+
+    /// Size limit of the files a user has uploaded, as <user>=<MB>, "*" for
+    /// users without a quota of their own, can be given multiple times. Users
+    /// are identified by the header set with --audit-user-header
+    #[clap(long = "user-quota", value_parser = parse_user_quota)]
+    user_quota: Vec<(String, u64)>,
+
+    /// File to keep track of who uploaded which files in across restarts,
+    /// for user quotas, it only lives in memory if not specified
+    #[clap(long = "uploads-file")]
+    uploads_file: Option<PathBuf>,
+
+    /// File to keep share links in across restarts, they only live in memory
+    /// if not specified
+    #[clap(long = "shares-file")]
+    shares_file: Option<PathBuf>,
+
+    /// File to keep drop box links in across restarts, they only live in
+    /// memory if not specified
+    #[clap(long = "drop-boxes-file")]
+    drop_boxes_file: Option<PathBuf>,
+
+    /// File to append a JSON line to for every upload, deletion and other
+    /// change, no audit log is kept if not specified
+    #[clap(long = "audit-log")]
+    audit_log: Option<PathBuf>,
+
+    /// Size the audit log is rotated at, in MB
+    #[clap(long = "audit-log-max-size", default_value = "10")]
+    audit_log_max_size: u64,
+
+    /// Header an authenticating reverse proxy passes the user name in, e.g.
+    /// Remote-User, recorded in the audit log and access log
+    #[clap(long = "audit-user-header")]
+    audit_user_header: Option<String>,
+
+    /// Address to serve Prometheus metrics at /metrics on, e.g.
+    /// 127.0.0.1:9100, they are served along with everything else if not
+    /// specified
+    #[clap(long = "metrics-listen")]
+    metrics_listen: Option<SocketAddr>,
+
+    /// Requests a client may make to a class of routes, as <class>=<N>/<s|m|h>,
+    /// e.g. listing=120/m, class being one of listing, download, upload and
+    /// login, applied per IP and per user, can be given multiple times
+    #[clap(long = "rate-limit", value_parser = parse_rate_limit)]
+    rate_limit: Vec<(rate_limit::RouteClass, rate_limit::Rate)>,
+
+    /// Max speed of a single download, in KB/s
+    #[clap(long = "download-rate")]
+    download_rate: Option<u64>,
+
+    /// Max speed of a single upload, in KB/s
+    #[clap(long = "upload-rate")]
+    upload_rate: Option<u64>,
+
+    /// Max number of uploads and downloads running at the same time, further
+    /// ones are answered with 429
+    #[clap(long = "max-transfers")]
+    max_transfers: Option<usize>,
+
+    /// Failed share link logins and path traversal attempts after which an IP
+    /// is banned, 0 to never ban
+    #[clap(long = "ban-after", default_value = "10")]
+    ban_after: u32,
+
+    /// How long a banned IP is refused, in seconds
+    #[clap(long = "ban-duration", default_value = "600")]
+    ban_duration: u64,
+
+    /// Access rule for a network, as <allow|read-only|deny>=<cidr>[@<path>],
+    /// e.g. read-only=10.1.0.0/16 or deny=192.168.50.0/24@/api/audit, the
+    /// first rule matching a request applies and requests no rule matches
+    /// are allowed, can be given multiple times
+    #[clap(long = "ip-rule")]
+    ip_rule: Vec<ip_rules::IpRule>,
+
+    /// Cache-Control header of files downloaded from /api/static, e.g.
+    /// max-age=3600, browsers choose how long to cache them if not specified
+    #[clap(long = "static-cache-control")]
+    static_cache_control: Option<HeaderValue>,
+
+    /// Serve the .zst, .br or .gz sibling of a file under serve-dir instead
+    /// of the file to clients accepting that encoding, if there is one
+    #[clap(long = "precompressed")]
+    precompressed: bool,
+}
+
+fn parse_dir_quota(arg: &str) -> Result<(String, u64), String> {
+    let (dir, size) = arg
+        .rsplit_once('=')
+        .ok_or_else(|| "expected <dir>=<MB>".to_string())?;
+    let size = size
+        .trim()
+        .parse::<u64>()
+        .map_err(|e| format!("invalid size: {}", e))?;
+    Ok((format!("/{}", dir.trim_matches('/')), size))
+}
+
+fn parse_user_quota(arg: &str) -> Result<(String, u64), String> {
+    let (user, size) = arg
+        .rsplit_once('=')
+        .ok_or_else(|| "expected <user>=<MB>".to_string())?;
+    let size = size
+        .trim()
+        .parse::<u64>()
+        .map_err(|e| format!("invalid size: {}", e))?;
+    Ok((user.trim().to_string(), size))
+}
+
+fn parse_rate_limit(arg: &str) -> Result<(rate_limit::RouteClass, rate_limit::Rate), String> {
+    let (class, rate) = arg
+        .split_once('=')
+        .ok_or_else(|| "expected <class>=<N>/<s|m|h>".to_string())?;
+    let class = <rate_limit::RouteClass as clap::ValueEnum>::from_str(class.trim(), true)?;
+    let (requests, period) = rate
+        .split_once('/')
+        .ok_or_else(|| "expected <N>/<s|m|h>".to_string())?;
+    let requests = requests
+        .trim()
+        .parse::<u32>()
+        .ok()
+        .filter(|requests| *requests > 0)
+        .ok_or_else(|| format!("invalid number of requests: {}", requests))?;
+    let period = match period.trim() {
+        "s" => Duration::from_secs(1),
+        "m" => Duration::from_secs(60),
+        "h" => Duration::from_secs(60 * 60),
+        period => return Err(format!("invalid period: {}", period)),
+    };
+    Ok((class, rate_limit::Rate { requests, period }))
+}
+
+fn parse_base_path(arg: &str) -> Result<String, String> {
+    let path = arg.trim_matches('/');
+    if path.contains(['*', ':', '?', '#']) {
+        return Err("must be a plain path".to_string());
+    }
+    Ok(if path.is_empty() {
+        "".to_string()
+    } else {
+        format!("/{}", path)
+    })
 }
 
 #[tokio::main]
 async fn main() {
     let opt = Opt::parse();
 
-    // Setup logging & RUST_LOG from args
-    if std::env::var("RUST_LOG").is_err() {
-        std::env::set_var("RUST_LOG", format!("{},hyper=info,mio=info", opt.log_level))
-    }
-    // enable console logging
-    tracing_subscriber::fmt::init();
+    let log_file = opt.log_file.as_ref().map(|file| {
+        logging::RotatingFile::open(file.clone(), opt.log_max_size * 1024 * 1024)
+            .expect("Unable to open log file")
+    });
+    logging::init(&opt.log_level, opt.log_format, log_file);
 
     match PathBuf::from(&opt.serve_dir).parse_dot() {
         Ok(serve_dir) if serve_dir.is_dir() => {
@@ -99,33 +363,279 @@ async fn main() {
             Some(IpAddr::from_str(opt.addr.as_str()).unwrap_or(IpAddr::V4(Ipv4Addr::LOCALHOST)));
     }
 
-    let app = Router::new()
+    hls::init(
+        opt.hls_cache_dir
+            .clone()
+            .unwrap_or_else(|| std::env::temp_dir().join("fileserver-rs-hls")),
+        opt.hls_cache_size * 1024 * 1024,
+        opt.hls_max_jobs,
+    );
+
+    thumbnail::init(
+        opt.thumbnail_cache_dir
+            .clone()
+            .unwrap_or_else(|| std::env::temp_dir().join("fileserver-rs-thumbnails")),
+        opt.thumbnail_cache_size * 1024 * 1024,
+    );
+
+    limits::init(limits::UploadLimits {
+        max_file_size: opt.max_file_size.map(|size| size * 1024 * 1024),
+        max_request_size: opt.max_request_size.map(|size| size * 1024 * 1024),
+        min_free_space: opt.min_free_space * 1024 * 1024,
+        dir_quotas: opt
+            .dir_quota
+            .iter()
+            .map(|(path, quota)| limits::DirQuota {
+                path: path.clone(),
+                full_path: resolve_path(path).expect("dir-quota must be under serve-dir"),
+                quota: quota * 1024 * 1024,
+            })
+            .collect(),
+        user_quotas: opt
+            .user_quota
+            .iter()
+            .map(|(user, quota)| (user.clone(), quota * 1024 * 1024))
+            .collect(),
+        uploads_file: opt.uploads_file.clone(),
+    });
+
+    archive::init(opt.max_extract_size * 1024 * 1024);
+    share::init(opt.shares_file.clone());
+    dropbox::init(opt.drop_boxes_file.clone());
+    if let Some(file) = &opt.audit_log {
+        audit::init(audit::AuditConfig {
+            file: file.clone(),
+            max_size: opt.audit_log_max_size * 1024 * 1024,
+        });
+    }
+    proxy::init(proxy::ProxyConfig {
+        trusted_proxies: opt.trusted_proxy.clone(),
+        user_header: opt.audit_user_header.clone(),
+        base_path: opt.base_path.clone(),
+    });
+    if let Some(file) = &opt.access_log {
+        access_log::init(access_log::AccessLog {
+            format: opt.access_log_format,
+            file: (file.as_os_str() != "-").then(|| {
+                logging::RotatingFile::open(file.clone(), opt.log_max_size * 1024 * 1024)
+                    .expect("Unable to open access log")
+            }),
+        });
+    }
+
+    ip_rules::init(opt.ip_rule.clone());
+    caching::init(opt.static_cache_control.clone());
+    rate_limit::init(rate_limit::RateLimits {
+        rates: opt.rate_limit.clone(),
+        download_rate: opt.download_rate.map(|rate| rate * 1024),
+        upload_rate: opt.upload_rate.map(|rate| rate * 1024),
+        max_transfers: opt.max_transfers,
+        ban_after: opt.ban_after,
+        ban_duration: Duration::from_secs(opt.ban_duration),
+    });
+
+    metrics::init(vec![PathBuf::from(&opt.serve_dir)]);
+
+    let mut static_files =
+        ServeDir::new(unsafe { SERVE_DIR.as_ref().unwrap().to_string_lossy().to_string() });
+    if opt.precompressed {
+        static_files = static_files
+            .precompressed_zstd()
+            .precompressed_br()
+            .precompressed_gzip();
+    }
+
+    let mut app = Router::new()
+        .route("/api/config", get(server_config))
         .route("/api/listing", get(serve_root).post(serve_root))
         .route("/api/listing/*path", get(list_files).post(create_dir))
         .route("/api/upload/*path", post(save_request_body))
         .route("/api/delete/*path", post(delete_path))
-        .route("/api/ffprobe/*path", get(ffprobe))
+        .route("/api/ffprobe/*path", get(ffprobe::media_info))
+        .route("/api/media/*path", get(media::media_desc))
+        .route("/api/subtitle/*path", get(media::subtitle))
+        .route("/api/hls/*path", get(hls::serve))
+        .route("/api/thumbnail/*path", get(thumbnail::thumbnail))
+        .route("/api/exif/*path", get(exif_info::exif))
+        .route("/api/text/*path", get(text::text))
+        .route("/api/markdown/*path", get(markdown::markdown))
+        .route("/api/document/*path", get(document::preview))
+        .route("/api/edit/*path", get(edit::open).put(edit::save))
+        .route("/api/archive/*path", get(archive::member))
+        .route("/api/hash/*path", get(checksum::hash))
+        .route("/api/shares", get(share::list).post(share::create))
+        .route("/api/shares/:token", delete(share::revoke))
+        .route("/s/:token", get(share::serve_root).post(share::unlock))
+        .route("/s/:token/*path", get(share::serve))
+        .route("/api/dropboxes", get(dropbox::list).post(dropbox::create))
+        .route("/api/dropboxes/:token", delete(dropbox::revoke))
+        .route("/u/:token", get(dropbox::upload_page).post(dropbox::upload))
+        .route("/api/audit", get(audit::query))
+        .route(
+            "/api/extract/*path",
+            get(archive::extract_status).post(archive::extract),
+        )
         .nest(
             "/api/static",
-            get_service(ServeDir::new(unsafe {
-                SERVE_DIR.as_ref().unwrap().to_string_lossy().to_string()
-            }))
-            .handle_error(|_| async move { AppError("Static file not found".to_string()) }),
+            get_service(static_files)
+                .handle_error(|_| async move { AppError("Static file not found".to_string()) })
+                .layer(middleware::from_fn(caching::static_files)),
+        );
+    if opt.metrics_listen.is_none() {
+        app = app.route("/metrics", get(metrics::metrics));
+    }
+    let app = app
+        .route_layer(middleware::from_fn(rate_limit::limit))
+        .route_layer(middleware::from_fn(metrics::track));
+    let app = if opt.base_path.is_empty() {
+        app
+    } else {
+        log::info!("base path: {}", opt.base_path);
+        Router::new().nest(&opt.base_path, app)
+    };
+
+    INDEX_FILE.set(assets_dir.join("index.html")).ok();
+    let app = app
+        .nest(
+            &format!("{}/assets", opt.base_path),
+            // the wasm and js of the frontend are precompressed when building it
+            get_service(
+                ServeDir::new(assets_dir)
+                    .precompressed_zstd()
+                    .precompressed_br()
+                    .precompressed_gzip(),
+            )
+            .handle_error(|_| async move { AppError("Asset not found".to_string()) }),
         )
-        .merge(SpaRouter::new("/assets", assets_dir).index_file("index.html"))
-        .layer(ServiceBuilder::new().layer(TraceLayer::new_for_http()));
+        // index.html is adjusted to the base path
+        .fallback(get(serve_index))
+        .layer(
+            ServiceBuilder::new()
+                .layer(TraceLayer::new_for_http())
+                .layer(middleware::from_fn(access_log::log))
+                .layer(middleware::from_fn(ip_rules::enforce))
+                .layer(middleware::from_fn(compression::vary))
+                .layer(compression::layer()),
+        );
 
-    let sock_addr = SocketAddr::from((ip_addr.unwrap(), opt.port));
+    let listener = match systemd::take_listener(opt.listen_fd) {
+        Some(listener) => listener,
+        None => {
+            let sock_addr = SocketAddr::from((ip_addr.unwrap(), opt.port));
+            std::net::TcpListener::bind(sock_addr).expect("Unable to bind listen addr")
+        }
+    };
+    listener
+        .set_nonblocking(true)
+        .expect("Unable to set listener to non-blocking");
 
-    log::info!("listening on http://{}", sock_addr);
+    let local_addr = listener.local_addr().unwrap();
+    log::info!("listening on http://{}", local_addr);
 
-    axum::Server::bind(&sock_addr)
-        .serve(app.into_make_service())
-        .await
-        .expect("Unable to start server");
+    let mut mdns_daemon = None;
+    let mut mdns_host = None;
+    if opt.mdns {
+        let path = format!("{}/", opt.base_path);
+        match mdns::advertise(&opt.mdns_name, ip_addr.unwrap(), local_addr.port(), &path) {
+            Ok((daemon, host)) => {
+                mdns_daemon = Some(daemon);
+                mdns_host = Some(host);
+            }
+            Err(e) => log::error!("failed to advertise over mDNS: {}", e),
+        }
+    }
+    SERVER_CONFIG
+        .set(ServerConfig {
+            mdns_host,
+            public_url: None,
+            upload_limits: limits::public_limits(),
+        })
+        .ok();
+
+    let shutdown_token = CancellationToken::new();
+    shutdown::listen_for_signals(shutdown_token.clone());
+
+    if let Some(metrics_addr) = opt.metrics_listen {
+        let metrics_app = Router::new().route("/metrics", get(metrics::metrics));
+        let metrics_server = axum::Server::try_bind(&metrics_addr)
+            .expect("Unable to bind metrics listen addr")
+            .serve(metrics_app.into_make_service())
+            .with_graceful_shutdown(shutdown_token.clone().cancelled_owned());
+        log::info!("serving metrics on http://{}/metrics", metrics_addr);
+        tokio::spawn(async move {
+            if let Err(e) = metrics_server.await {
+                log::error!("metrics server failed: {}", e);
+            }
+        });
+    }
+
+    let server = axum::Server::from_tcp(listener)
+        .expect("Unable to start server")
+        .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+        .with_graceful_shutdown(shutdown_token.clone().cancelled_owned());
+
+    systemd::notify_ready();
+
+    let shutdown_timeout = Duration::from_secs(opt.shutdown_timeout);
+    tokio::select! {
+        result = server => result.expect("Unable to start server"),
+        _ = async {
+            shutdown_token.cancelled().await;
+            log::info!("shutting down, waiting up to {:?} for in-flight transfers", shutdown_timeout);
+            systemd::notify_stopping();
+            tokio::time::sleep(shutdown_timeout).await;
+        } => {
+            log::warn!("shutdown timeout expired, aborting in-flight transfers");
+        }
+    }
+
+    if let Some(daemon) = mdns_daemon {
+        mdns::stop(daemon);
+    }
+    shutdown::remove_partial_files();
+    log::info!("server stopped");
+}
+
+async fn server_config(proxy::PublicUrl(public_url): proxy::PublicUrl) -> impl IntoResponse {
+    let mut config = SERVER_CONFIG.get().cloned().unwrap_or_default();
+    config.public_url = public_url;
+    Json(config)
 }
 
-async fn create_dir(Path(path): Path<String>, Json(req): Json<JsonRequest>) -> impl IntoResponse {
+/// Serves the frontend for every path that isn't a route of the backend, with
+/// the URLs of its assets and the base path it builds its URLs with adjusted
+/// to where clients reach the server.
+async fn serve_index(proxy::BasePath(base_path): proxy::BasePath) -> Response {
+    let index_file = match INDEX_FILE.get() {
+        Some(index_file) => index_file,
+        None => return StatusCode::NOT_FOUND.into_response(),
+    };
+    let html = match tokio::fs::read_to_string(index_file).await {
+        Ok(html) => html,
+        Err(e) => {
+            log::error!("failed to read {:?}: {}", index_file, e);
+            return StatusCode::NOT_FOUND.into_response();
+        }
+    };
+    let html = html
+        .replace("\"/assets/", &format!("\"{}/assets/", base_path))
+        .replace("'/assets/", &format!("'{}/assets/", base_path))
+        .replacen(
+            "<head>",
+            &format!(
+                "<head>\n    <meta name=\"base-path\" content=\"{}\">",
+                document::escape(&base_path)
+            ),
+            1,
+        );
+    Html(html).into_response()
+}
+
+async fn create_dir(
+    client: audit::Client,
+    Path(path): Path<String>,
+    Json(req): Json<JsonRequest>,
+) -> impl IntoResponse {
     let resp = match req {
         JsonRequest::CreateDirectory { dir_name } => {
             let full_path = format!(
@@ -135,21 +645,24 @@ async fn create_dir(Path(path): Path<String>, Json(req): Json<JsonRequest>) -> i
                 dir_name
             );
 
-            match std::fs::create_dir(&full_path) {
+            let resp = match std::fs::create_dir(&full_path) {
                 Err(err) => JsonResponse::Failed {
                     msg: Some(err.to_string()),
                 },
                 _ => JsonResponse::Succeeded {
                     msg: Some(format!("create dir: {}", full_path)),
                 },
-            }
+            };
+            let dir_path = format!("{}/{}", path.trim_end_matches('/'), dir_name);
+            client.record(audit::Operation::Mkdir, &dir_path, None, &resp);
+            resp
         }
     };
 
     (StatusCode::OK, Json(resp).into_response())
 }
 
-async fn delete_path(Path(path): Path<String>) -> impl IntoResponse {
+async fn delete_path(client: audit::Client, Path(path): Path<String>) -> impl IntoResponse {
     let parent_dir = unsafe { SERVE_DIR.as_ref().unwrap() };
     let full_path = parent_dir.join(path.trim_start_matches('/'));
 
@@ -174,6 +687,7 @@ async fn delete_path(Path(path): Path<String>) -> impl IntoResponse {
     } else {
         JsonResponse::Succeeded { msg: None }
     };
+    client.record(audit::Operation::Delete, &path, None, &json_resp);
 
     (StatusCode::OK, Json(json_resp).into_response())
 }
@@ -190,65 +704,240 @@ struct UploadForm {
     filename: String,
 }
 
+/// Saves uploaded files into the directory at `path`. A file field may be
+/// preceded by a "path" text field holding its path relative to that
+/// directory, e.g. "photos/2022/a.jpg" for folder uploads, intermediate
+/// directories are created as needed. It may also be preceded by a "checksum"
+/// field of the form "sha256=<hex>" (or "blake3=<hex>"), the `X-Checksum`
+/// header sets one for every file. Files not matching their checksum are
+/// discarded and the upload fails.
 async fn save_request_body(
+    client: audit::Client,
     Path(path): Path<String>,
-    mut multipart: Multipart,
-) -> Result<Json<JsonResponse>, AppError> {
-    let parent_dir = unsafe {
-        SERVE_DIR
-            .as_ref()
-            .unwrap()
-            .join(path.trim_start_matches('/'))
+    headers: HeaderMap,
+    multipart: Multipart,
+) -> Result<Response, AppError> {
+    let result = upload(&client, &path, &headers, multipart).await;
+    client.record_failure(audit::Operation::Upload, &path, &result);
+    result
+}
+
+async fn upload(
+    client: &audit::Client,
+    path: &str,
+    headers: &HeaderMap,
+    multipart: Multipart,
+) -> Result<Response, AppError> {
+    let dir = resolve_path(path)?;
+    let length = content_length(headers);
+    let mut guard = match limits::UploadGuard::new(&dir, length, client.user()).await {
+        Ok(guard) => guard,
+        Err(e) => return Ok(e.into_response()),
     };
+    save_files(client, path, &mut guard, headers, multipart, false).await
+}
+
+fn content_length(headers: &HeaderMap) -> Option<u64> {
+    headers
+        .get(header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse().ok())
+}
+
+/// Saves the files of a multipart upload into the directory at `path`, as
+/// described at `save_request_body`, recording each saved file in the audit
+/// log. Anonymous uploads through drop box links only keep the base name of
+/// files and never replace existing files, a number is appended to the name
+/// instead.
+async fn save_files(
+    client: &audit::Client,
+    path: &str,
+    guard: &mut limits::UploadGuard,
+    headers: &HeaderMap,
+    mut multipart: Multipart,
+    anonymous: bool,
+) -> Result<Response, AppError> {
+    let default_checksum = headers
+        .get("x-checksum")
+        .map(|value| checksum::Expected::parse(value.to_str().unwrap_or_default()))
+        .transpose()?;
 
     let mut count = 0;
+    let mut relative_path = None;
+    let mut expected_checksum = None;
     while let Some(mut field) = multipart
         .next_field()
         .await
         .map_err(|_| AppError("failed to iterate over uploaded files".to_string()))?
     {
-        let name = field.file_name().unwrap().to_string();
-        let filename = parent_dir.join(name.as_str());
-        let mut file = tokio::fs::File::create(filename).await.map_err(|_| {
-            AppError(format!(
-                "failed to create file at: {}/{}",
-                parent_dir.to_str().unwrap(),
-                name
-            ))
-        })?;
+        let file_name = match field.file_name() {
+            Some(file_name) => file_name.to_string(),
+            None => {
+                if field.name() == Some("path") {
+                    let text = field
+                        .text()
+                        .await
+                        .map_err(|_| AppError("failed to read the path of a file".to_string()))?;
+                    relative_path = Some(text);
+                } else if field.name() == Some("checksum") {
+                    let text = field.text().await.map_err(|_| {
+                        AppError("failed to read the checksum of a file".to_string())
+                    })?;
+                    expected_checksum = Some(checksum::Expected::parse(&text)?);
+                }
+                continue;
+            }
+        };
+        let expected = expected_checksum
+            .take()
+            .or_else(|| default_checksum.clone());
+        let mut hasher = expected
+            .as_ref()
+            .map(|expected| checksum::Hasher::new(expected.algo));
+        let name = match relative_path.take() {
+            Some(relative_path) if !anonymous => relative_path,
+            _ => file_name,
+        };
+        let name = if anonymous {
+            std::path::Path::new(&name)
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .ok_or_else(|| AppError(format!("invalid file name: {}", name)))?
+        } else {
+            name
+        };
+        // relative paths must stay in the directory uploaded to
+        let relative = std::path::Path::new(&name);
+        if !relative
+            .components()
+            .all(|component| matches!(component, std::path::Component::Normal(_)))
+        {
+            return Err(AppError(format!("invalid file name: {}", name)));
+        }
+        let mut filename = resolve_path(&format!("{}/{}", path.trim_end_matches('/'), name))?;
+        if anonymous {
+            filename = unique_path(filename);
+        }
+        if let Some(dir) = filename.parent() {
+            tokio::fs::create_dir_all(dir)
+                .await
+                .map_err(|e| AppError(format!("failed to create directory for {}: {}", name, e)))?;
+            guard.set_dir(dir).await;
+        }
+        guard.start_file();
+        let partial_file = shutdown::PartialFile::new(&filename);
+        let mut file = tokio::fs::File::create(partial_file.path())
+            .await
+            .map_err(|_| AppError(format!("failed to create file at: {}/{}", path, name)))?;
 
         while let Some(chunk) = field
             .chunk()
             .await
-            .map_err(|_| AppError(format!("failed to read from file: {}", name)))
-            .unwrap()
+            .map_err(|_| AppError(format!("failed to read from file: {}", name)))?
         {
+            // the partial file is removed when returning early
+            if let Err(e) = guard.add(chunk.len() as u64) {
+                log::warn!("rejected upload of {:?}: {}", filename, e.message());
+                return Ok(e.into_response());
+            }
+            metrics::add_uploaded_bytes(chunk.len() as u64);
+            if let Some(hasher) = &mut hasher {
+                hasher.update(&chunk);
+            }
             file.write_all(&chunk[..])
                 .await
                 .map_err(|_| AppError("failed to write file".to_string()))?;
         }
 
+        file.flush()
+            .await
+            .map_err(|_| AppError("failed to write file".to_string()))?;
+        if let (Some(hasher), Some(expected)) = (hasher, expected) {
+            let actual = hasher.finalize();
+            if actual != expected.checksum {
+                // dropping the partial file removes it
+                log::warn!("checksum mismatch of uploaded {:?}", filename);
+                return Err(AppError(format!(
+                    "checksum mismatch of {}: expected {}, got {}",
+                    name, expected.checksum, actual
+                )));
+            }
+        }
+        if anonymous {
+            // another upload may have taken the name in the meantime
+            filename = unique_path(filename);
+        }
+        partial_file
+            .persist(&filename)
+            .await
+            .map_err(|_| AppError(format!("failed to save file: {}", name)))?;
+        let size = guard.file_saved();
+        limits::record_upload(client.user(), &filename);
+        let saved_name = match filename.file_name() {
+            // renamed to keep an existing file
+            Some(saved_name) if anonymous => saved_name.to_string_lossy().to_string(),
+            _ => name,
+        };
+        let file_path = format!("{}/{}", path.trim_end_matches('/'), saved_name);
+        client.record(audit::Operation::Upload, &file_path, Some(size), &Ok(()));
+
         count += 1;
     }
 
     Ok(Json(JsonResponse::Succeeded {
         msg: Some(format!("{} file(s) uploaded!", count)),
-    }))
+    })
+    .into_response())
+}
+
+/// `path`, or "name (1).ext", "name (2).ext"... if it exists.
+fn unique_path(path: PathBuf) -> PathBuf {
+    if !path.exists() {
+        return path;
+    }
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let ext = path
+        .extension()
+        .map(|ext| format!(".{}", ext.to_string_lossy()))
+        .unwrap_or_default();
+    (1..)
+        .map(|n| path.with_file_name(format!("{} ({}){}", stem, n, ext)))
+        .find(|path| !path.exists())
+        .unwrap()
 }
 
-async fn serve_root() -> impl IntoResponse {
-    list_files(Path("/".to_string())).await
+async fn serve_root(headers: HeaderMap) -> impl IntoResponse {
+    list_files(Path("/".to_string()), headers).await
 }
 
-async fn list_files(Path(path): Path<String>) -> impl IntoResponse {
+async fn list_files(Path(path): Path<String>, headers: HeaderMap) -> impl IntoResponse {
     let path = path.trim_start_matches('/');
     let parent_dir = unsafe { SERVE_DIR.as_ref().unwrap() };
     let full_path = parent_dir.join(path.trim_start_matches('/'));
 
     log::debug!("list files for path: {:?}", full_path);
 
+    if let Some((archive_path, inner)) = archive::split_path(path) {
+        return match archive::list_dir(&archive_path, &inner).await {
+            Ok(dir_desc) => {
+                let modified = parent_dir
+                    .join(&archive_path)
+                    .metadata()
+                    .and_then(|metadata| metadata.modified())
+                    .ok();
+                // the entries change only along with the archive
+                caching::listing(&headers, &dir_desc, modified, &[])
+            }
+            Err(e) => e.into_response(),
+        };
+    }
+
     if full_path.is_dir() {
         let mut descendants = vec![];
+        let mut entry_mtimes = vec![];
         for entry in WalkDir::new(&full_path)
             .follow_links(true)
             .max_depth(1)
@@ -256,6 +945,12 @@ async fn list_files(Path(path): Path<String>) -> impl IntoResponse {
             .filter_map(|e| e.ok())
         {
             if entry.depth() > 0 {
+                entry_mtimes.push(
+                    entry
+                        .metadata()
+                        .ok()
+                        .and_then(|metadata| metadata.modified().ok()),
+                );
                 let entry = convert_dir_entry(&entry);
                 descendants.push(entry);
             }
@@ -264,9 +959,14 @@ async fn list_files(Path(path): Path<String>) -> impl IntoResponse {
         let dir_desc = DirDesc {
             dir_name: format!("/{}", path),
             descendants,
+            in_archive: false,
         };
 
-        return (StatusCode::OK, Json(dir_desc)).into_response();
+        let modified = full_path
+            .metadata()
+            .and_then(|metadata| metadata.modified())
+            .ok();
+        return caching::listing(&headers, &dir_desc, modified, &entry_mtimes);
     } else if full_path.is_symlink() || full_path.is_file() {
         return Redirect::permanent(format!("/static/{}", path).as_str()).into_response();
     }
@@ -274,6 +974,7 @@ async fn list_files(Path(path): Path<String>) -> impl IntoResponse {
     let dir_desc = DirDesc {
         dir_name: "".to_string(),
         descendants: vec![],
+        in_archive: false,
     };
     (StatusCode::OK, Json(dir_desc)).into_response()
 }
@@ -308,33 +1009,36 @@ fn convert_dir_entry(entry: &walkdir::DirEntry) -> DirEntry {
     }
 }
 
-async fn ffprobe(Path(path): Path<String>) -> impl IntoResponse {
-    let path = path.trim_start_matches('/');
-    let parent_dir = unsafe { SERVE_DIR.as_ref().unwrap() };
-    let full_path = parent_dir.join(path.trim_start_matches('/'));
-
-    if full_path.is_file() {
-        if let Ok(output) = Command::new("ffprobe")
-            .args([
-                "-v",
-                "quiet",
-                "-print_format",
-                "json",
-                "-show_format",
-                "-show_streams",
-            ])
-            .arg(full_path)
-            .output()
-        {
-            let json_str = String::from_utf8(output.stdout).unwrap_or("{}".to_string());
-            return (StatusCode::OK, Json(json_str)).into_response();
-        }
+/// Maps a request path to a path under the served directory, rejecting
+/// paths that would escape it.
+fn resolve_path(path: &str) -> Result<PathBuf, AppError> {
+    let serve_dir = unsafe { SERVE_DIR.as_ref().unwrap() };
+    let full_path = serve_dir.join(path.trim_start_matches('/'));
+    // normalized, so that "a/../b" is subject to the quota of "b"
+    let relative = match (full_path.absolutize(), serve_dir.absolutize()) {
+        (Ok(full), Ok(root)) => full
+            .strip_prefix(&root)
+            .map(|relative| relative.to_path_buf())
+            .ok(),
+        _ => None,
+    };
+    match relative {
+        Some(relative) => Ok(serve_dir.join(relative)),
+        None => Err(AppError(format!("invalid path: {}", path))),
     }
+}
 
-    let json_resp = Json(JsonResponse::Failed {
-        msg: Some("ffprobe not found".to_string()),
+/// The served directory of the tests, a temporary directory shared by the
+/// tests of all modules, each of which works in a directory of its own in it.
+#[cfg(test)]
+fn test_serve_dir() -> &'static std::path::Path {
+    static INIT: std::sync::Once = std::sync::Once::new();
+    INIT.call_once(|| {
+        let dir = std::env::temp_dir().join(format!("fileserver-rs-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        unsafe { SERVE_DIR = Some(dir) };
     });
-    (StatusCode::OK, json_resp).into_response()
+    unsafe { (*std::ptr::addr_of!(SERVE_DIR)).as_deref().unwrap() }
 }
 
 #[derive(Debug)]
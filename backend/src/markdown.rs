@@ -0,0 +1,113 @@
+use crate::proxy::BasePath;
+use crate::{resolve_path, AppError};
+use axum::extract::Path;
+use axum::Json;
+use common::{is_markdown, MarkdownDoc, MARKDOWN_ROUTE};
+use pulldown_cmark::{Event, Options, Parser, Tag};
+
+const MAX_SIZE: u64 = 2 * 1024 * 1024;
+
+/// Renders a markdown file to HTML, sanitized so that it can be inserted into
+/// the page as is. Relative links and images are resolved against the
+/// directory of the file, since the page showing it lives at another URL.
+pub async fn markdown(
+    BasePath(base_path): BasePath,
+    Path(path): Path<String>,
+) -> Result<Json<MarkdownDoc>, AppError> {
+    let full_path = resolve_path(&path)?;
+    let metadata = tokio::fs::metadata(&full_path)
+        .await
+        .map_err(|_| AppError(format!("file not found: {}", path)))?;
+    if !metadata.is_file() {
+        return Err(AppError(format!("not a file: {}", path)));
+    }
+    if metadata.len() > MAX_SIZE {
+        return Err(AppError(format!("too large to render: {}", path)));
+    }
+
+    let bytes = tokio::fs::read(&full_path)
+        .await
+        .map_err(|e| AppError(format!("failed to read file: {}", e)))?;
+    let source = String::from_utf8_lossy(&bytes);
+
+    let path = format!("/{}", path.trim_start_matches('/'));
+    let dir = &path[..path.rfind('/').unwrap_or(0)];
+    let html = render(&source, dir, &base_path);
+
+    Ok(Json(MarkdownDoc { path, html }))
+}
+
+fn render(source: &str, dir: &str, base_path: &str) -> String {
+    let options = Options::ENABLE_TABLES
+        | Options::ENABLE_FOOTNOTES
+        | Options::ENABLE_STRIKETHROUGH
+        | Options::ENABLE_TASKLISTS;
+    let parser = Parser::new_ext(source, options).map(|event| match event {
+        Event::Start(Tag::Link(kind, dest, title)) => Event::Start(Tag::Link(
+            kind,
+            resolve_link(dir, &dest, false, base_path).into(),
+            title,
+        )),
+        Event::Start(Tag::Image(kind, dest, title)) => Event::Start(Tag::Image(
+            kind,
+            resolve_link(dir, &dest, true, base_path).into(),
+            title,
+        )),
+        event => event,
+    });
+
+    let mut html = String::new();
+    pulldown_cmark::html::push_html(&mut html, parser);
+
+    ammonia::Builder::default()
+        .add_tags(["input"])
+        .add_tag_attributes("input", ["type", "checked", "disabled"])
+        .clean(&html)
+        .to_string()
+}
+
+/// Maps a link relative to the directory of the markdown file to where it is
+/// served: images and files to `/api/static`, markdown files to the markdown
+/// viewer and directories to their listing, all under the base path. Absolute
+/// URLs and anchors are left alone.
+fn resolve_link(dir: &str, dest: &str, image: bool, base_path: &str) -> String {
+    if dest.is_empty() || dest.starts_with('/') || dest.starts_with('#') || dest.contains("://") {
+        return dest.to_string();
+    }
+    if let Some((scheme, _)) = dest.split_once(':') {
+        if !scheme.contains('/') {
+            // mailto:, data: and the like
+            return dest.to_string();
+        }
+    }
+
+    let (link, fragment) = match dest.find(['#', '?']) {
+        Some(idx) => dest.split_at(idx),
+        None => (dest, ""),
+    };
+    let mut segments = dir.split('/').filter(|s| !s.is_empty()).collect::<Vec<_>>();
+    for segment in link.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop();
+            }
+            segment => segments.push(segment),
+        }
+    }
+    let path = format!("/{}", segments.join("/"));
+
+    let is_dir = link.ends_with('/')
+        || resolve_path(&path)
+            .map(|full_path| full_path.is_dir())
+            .unwrap_or(false);
+    if image {
+        format!("{}/api/static{}{}", base_path, path, fragment)
+    } else if is_dir {
+        format!("{}{}{}", base_path, path, fragment)
+    } else if is_markdown(&path) {
+        format!("{}{}{}{}", base_path, MARKDOWN_ROUTE, path, fragment)
+    } else {
+        format!("{}/api/static{}{}", base_path, path, fragment)
+    }
+}
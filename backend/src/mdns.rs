@@ -0,0 +1,67 @@
+use mdns_sd::{ServiceDaemon, ServiceInfo};
+use std::net::IpAddr;
+
+const SERVICE_TYPE: &str = "_http._tcp.local.";
+
+/// Advertises the server as an `_http._tcp` service over multicast DNS, the
+/// host is announced as `<instance_name>.local` with the given ip, and kept
+/// up to date as the host's addresses change. The returned daemon must be
+/// kept alive for as long as the service should stay discoverable.
+pub fn advertise(
+    instance_name: &str,
+    ip: IpAddr,
+    port: u16,
+    path: &str,
+) -> Result<(ServiceDaemon, String), mdns_sd::Error> {
+    let host_name = to_host_label(instance_name);
+    let daemon = ServiceDaemon::new()?;
+    let service = ServiceInfo::new(
+        SERVICE_TYPE,
+        instance_name,
+        format!("{}.local.", host_name).as_str(),
+        ip,
+        port,
+        &[("path", path)][..],
+    )?
+    .enable_addr_auto();
+    daemon.register(service)?;
+
+    let host = format!("{}.local", host_name);
+    log::info!(
+        "advertising \"{}\" ({}) as http://{}:{}{}",
+        instance_name,
+        SERVICE_TYPE,
+        host,
+        port,
+        path
+    );
+    Ok((daemon, host))
+}
+
+pub fn stop(daemon: ServiceDaemon) {
+    // sends goodbye packets so that clients drop the service right away
+    if let Err(e) = daemon.shutdown() {
+        log::warn!("failed to stop mDNS advertisement: {}", e);
+    }
+}
+
+/// Converts a free-form instance name ("Living Room NAS") into a valid DNS
+/// label ("living-room-nas").
+fn to_host_label(instance_name: &str) -> String {
+    let label = instance_name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_lowercase()
+            } else {
+                '-'
+            }
+        })
+        .collect::<String>();
+    let label = label.trim_matches('-');
+    if label.is_empty() {
+        "fileserver".to_string()
+    } else {
+        label.to_string()
+    }
+}
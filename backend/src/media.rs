@@ -0,0 +1,162 @@
+use crate::{ffprobe, hls, resolve_path, AppError};
+use axum::extract::Path;
+use axum::http::header;
+use axum::response::IntoResponse;
+use axum::Json;
+use common::{media_kind, MediaDesc, MediaKind, SubtitleTrack};
+
+/// Describes a media file for the player page: duration and codecs from
+/// ffprobe, sibling subtitle files and the previous/next media files in the
+/// directory for playlist navigation.
+pub async fn media_desc(Path(path): Path<String>) -> Result<Json<MediaDesc>, AppError> {
+    let full_path = resolve_path(&path)?;
+    let file_name = full_path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let kind = match media_kind(&file_name) {
+        Some(kind) if full_path.is_file() => kind,
+        _ => return Err(AppError(format!("not a media file: {}", path))),
+    };
+
+    let path = format!("/{}", path.trim_start_matches('/'));
+    let dir_path = path[..path.rfind('/').unwrap_or(0)].to_string();
+
+    let mut siblings = std::fs::read_dir(full_path.parent().unwrap())
+        .map_err(|e| AppError(format!("failed to read dir: {}", e)))?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().map(|t| t.is_file()).unwrap_or(false))
+        .map(|entry| entry.file_name().to_string_lossy().to_string())
+        .collect::<Vec<_>>();
+    siblings.sort();
+
+    let stem = file_name.rsplit_once('.').unwrap().0;
+    let subtitles = siblings
+        .iter()
+        .filter_map(|name| subtitle_track(stem, name, &dir_path))
+        .collect();
+
+    let playlist = siblings
+        .iter()
+        .filter(|name| media_kind(name).is_some())
+        .collect::<Vec<_>>();
+    let index = playlist.iter().position(|name| **name == file_name);
+    let playlist_entry = |i: Option<usize>| {
+        i.and_then(|i| playlist.get(i))
+            .map(|name| format!("{}/{}", dir_path, name))
+    };
+    let prev = playlist_entry(index.and_then(|i| i.checked_sub(1)));
+    let next = playlist_entry(index.map(|i| i + 1));
+
+    let (duration, codecs, hls_url) = match ffprobe::probe(&full_path).await {
+        Ok(info) => {
+            let codecs = info.streams.iter().map(|s| s.summary()).collect();
+            let hls_url = match kind {
+                MediaKind::Video => hls::hls_url(&path, &file_name, &info),
+                MediaKind::Audio => None,
+            };
+            (info.duration, codecs, hls_url)
+        }
+        Err(e) => {
+            log::debug!("no media info for {:?}: {}", full_path, e);
+            (None, vec![], None)
+        }
+    };
+
+    Ok(Json(MediaDesc {
+        path,
+        kind,
+        duration,
+        codecs,
+        subtitles,
+        hls_url,
+        prev,
+        next,
+    }))
+}
+
+/// Serves a subtitle file as WebVTT, converting SRT on the fly.
+pub async fn subtitle(Path(path): Path<String>) -> Result<impl IntoResponse, AppError> {
+    let full_path = resolve_path(&path)?;
+    let bytes = tokio::fs::read(&full_path)
+        .await
+        .map_err(|_| AppError(format!("subtitle not found: {}", path)))?;
+    let text = String::from_utf8_lossy(&bytes);
+
+    let is_srt = full_path
+        .extension()
+        .map(|ext| ext.eq_ignore_ascii_case("srt"))
+        .unwrap_or(false);
+    let vtt = if is_srt {
+        srt_to_vtt(&text)
+    } else {
+        text.to_string()
+    };
+
+    Ok(([(header::CONTENT_TYPE, "text/vtt; charset=utf-8")], vtt))
+}
+
+/// Matches subtitle files named after the media file, e.g. "movie.srt" or
+/// "movie.en.vtt" for "movie.mkv", the part in between is taken as language.
+fn subtitle_track(stem: &str, name: &str, dir_path: &str) -> Option<SubtitleTrack> {
+    let (base, ext) = name.rsplit_once('.')?;
+    if !ext.eq_ignore_ascii_case("srt") && !ext.eq_ignore_ascii_case("vtt") {
+        return None;
+    }
+
+    let lang = if base == stem {
+        None
+    } else {
+        Some(base.strip_prefix(stem)?.strip_prefix('.')?.to_string())
+    };
+
+    Some(SubtitleTrack {
+        label: lang.clone().unwrap_or_else(|| name.to_string()),
+        lang,
+        url: format!("/api/subtitle{}/{}", dir_path, name),
+    })
+}
+
+fn srt_to_vtt(srt: &str) -> String {
+    let mut vtt = String::from("WEBVTT\n\n");
+    for line in srt.trim_start_matches('\u{feff}').lines() {
+        if line.contains("-->") {
+            // 00:00:01,000 --> 00:00:02,500  =>  00:00:01.000 --> 00:00:02.500
+            vtt.push_str(&line.replace(',', "."));
+        } else {
+            vtt.push_str(line);
+        }
+        vtt.push('\n');
+    }
+    vtt
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_srt_to_vtt() {
+        let srt = "\u{feff}1\r\n00:00:01,000 --> 00:00:02,500\r\nHello, world\r\n\r\n";
+        assert_eq!(
+            srt_to_vtt(srt),
+            "WEBVTT\n\n1\n00:00:01.000 --> 00:00:02.500\nHello, world\n\n"
+        );
+    }
+
+    #[test]
+    fn detects_subtitle_tracks() {
+        let track = subtitle_track("movie", "movie.en.srt", "/videos").unwrap();
+        assert_eq!(track.lang.as_deref(), Some("en"));
+        assert_eq!(track.label, "en");
+        assert_eq!(track.url, "/api/subtitle/videos/movie.en.srt");
+
+        let track = subtitle_track("movie", "movie.VTT", "/videos").unwrap();
+        assert_eq!(track.lang, None);
+        assert_eq!(track.label, "movie.VTT");
+
+        assert!(subtitle_track("movie", "movie.txt", "").is_none());
+        assert!(subtitle_track("movie", "other.en.srt", "").is_none());
+        assert!(subtitle_track("movie", "movies.srt", "").is_none());
+    }
+}
@@ -0,0 +1,260 @@
+use crate::limits::available_space;
+use crate::proxy;
+use axum::body::{boxed, Bytes, HttpBody};
+use axum::extract::MatchedPath;
+use axum::http::{header, Request};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use once_cell::sync::{Lazy, OnceCell};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Instant;
+
+/// Upper bounds of the request duration histogram buckets, in seconds.
+const BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Routes whose response bodies are counted as downloads.
+pub const DOWNLOAD_ROUTES: [&str; 4] = ["/api/static/", "/api/archive/", "/api/hls/", "/s/"];
+
+/// Routes whose requests are counted as uploads.
+pub const UPLOAD_ROUTES: [&str; 2] = ["/api/upload/", "/u/"];
+
+/// Suffix axum gives the routes of nested services.
+const NEST_TAIL: &str = "/*__private__axum_nest_tail_param";
+
+#[derive(Default)]
+struct Histogram {
+    buckets: [u64; BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+/// Request counts by method, route and status.
+#[allow(clippy::type_complexity)]
+static REQUESTS: Lazy<Mutex<BTreeMap<(String, String, u16), u64>>> = Lazy::new(Default::default);
+
+/// Request durations by route.
+static DURATIONS: Lazy<Mutex<BTreeMap<String, Histogram>>> = Lazy::new(Default::default);
+
+static UPLOADED_BYTES: AtomicU64 = AtomicU64::new(0);
+static DOWNLOADED_BYTES: AtomicU64 = AtomicU64::new(0);
+static ACTIVE_UPLOADS: AtomicU64 = AtomicU64::new(0);
+static ACTIVE_DOWNLOADS: AtomicU64 = AtomicU64::new(0);
+static FFPROBE_RUNS: AtomicU64 = AtomicU64::new(0);
+static FFPROBE_FAILURES: AtomicU64 = AtomicU64::new(0);
+
+/// Directories whose file systems' free space is reported.
+static ROOTS: OnceCell<Vec<PathBuf>> = OnceCell::new();
+
+pub fn init(roots: Vec<PathBuf>) {
+    ROOTS.set(roots).ok();
+}
+
+pub fn add_uploaded_bytes(len: u64) {
+    UPLOADED_BYTES.fetch_add(len, Ordering::Relaxed);
+}
+
+pub fn count_ffprobe(success: bool) {
+    FFPROBE_RUNS.fetch_add(1, Ordering::Relaxed);
+    if !success {
+        FFPROBE_FAILURES.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Counts a transfer as active until dropped.
+struct Transfer(&'static AtomicU64);
+
+impl Transfer {
+    fn start(gauge: &'static AtomicU64) -> Transfer {
+        gauge.fetch_add(1, Ordering::Relaxed);
+        Transfer(gauge)
+    }
+}
+
+impl Drop for Transfer {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Records the count and duration of requests by route, and the bytes and
+/// number of active uploads and downloads. The duration is measured until
+/// the response headers are ready, a download is active until its body has
+/// been sent or the client went away. Routes are labeled without the base
+/// path.
+pub async fn track<B>(req: Request<B>, next: Next<B>) -> Response {
+    let route = route(&req);
+    let method = req.method().to_string();
+    let is_upload =
+        method == "POST" && UPLOAD_ROUTES.iter().any(|prefix| route.starts_with(prefix));
+    let is_download = DOWNLOAD_ROUTES
+        .iter()
+        .any(|prefix| route.starts_with(prefix));
+
+    let start = Instant::now();
+    let upload = is_upload.then(|| Transfer::start(&ACTIVE_UPLOADS));
+    let response = next.run(req).await;
+    drop(upload);
+    let elapsed = start.elapsed().as_secs_f64();
+
+    let status = response.status().as_u16();
+    *REQUESTS
+        .lock()
+        .unwrap()
+        .entry((method, route.clone(), status))
+        .or_default() += 1;
+    let mut durations = DURATIONS.lock().unwrap();
+    let histogram = durations.entry(route).or_default();
+    for (bucket, le) in histogram.buckets.iter_mut().zip(BUCKETS) {
+        if elapsed <= le {
+            *bucket += 1;
+        }
+    }
+    histogram.sum += elapsed;
+    histogram.count += 1;
+    drop(durations);
+
+    if !is_download || !response.status().is_success() {
+        return response;
+    }
+    let transfer = Transfer::start(&ACTIVE_DOWNLOADS);
+    response.map(|body| {
+        boxed(body.map_data(move |data: Bytes| {
+            // keeps the download active as long as the body is alive
+            let _active = &transfer;
+            DOWNLOADED_BYTES.fetch_add(data.len() as u64, Ordering::Relaxed);
+            data
+        }))
+    })
+}
+
+/// The route a request matched, e.g. "/api/static/*path", without the base
+/// path.
+pub fn route<B>(req: &Request<B>) -> String {
+    match req.extensions().get::<MatchedPath>() {
+        Some(path) => {
+            let path = path.as_str();
+            let path = path.strip_prefix(proxy::base_path()).unwrap_or(path);
+            match path.strip_suffix(NEST_TAIL) {
+                Some(prefix) => format!("{}/*path", prefix),
+                None => path.to_string(),
+            }
+        }
+        None => "unmatched".to_string(),
+    }
+}
+
+/// Serves the metrics in the Prometheus text format.
+pub async fn metrics() -> impl IntoResponse {
+    let mut out = String::new();
+
+    out.push_str("# HELP fileserver_http_requests_total Requests by method, route and status.\n");
+    out.push_str("# TYPE fileserver_http_requests_total counter\n");
+    for ((method, route, status), count) in REQUESTS.lock().unwrap().iter() {
+        let _ = writeln!(
+            out,
+            "fileserver_http_requests_total{{method=\"{}\",route=\"{}\",status=\"{}\"}} {}",
+            method,
+            escape(route),
+            status,
+            count
+        );
+    }
+
+    out.push_str("# HELP fileserver_http_request_duration_seconds Time until the response headers are ready, by route.\n");
+    out.push_str("# TYPE fileserver_http_request_duration_seconds histogram\n");
+    for (route, histogram) in DURATIONS.lock().unwrap().iter() {
+        let route = escape(route);
+        for (le, count) in BUCKETS.iter().zip(histogram.buckets) {
+            let _ = writeln!(
+                out,
+                "fileserver_http_request_duration_seconds_bucket{{route=\"{}\",le=\"{}\"}} {}",
+                route, le, count
+            );
+        }
+        let _ = writeln!(
+            out,
+            "fileserver_http_request_duration_seconds_bucket{{route=\"{}\",le=\"+Inf\"}} {}",
+            route, histogram.count
+        );
+        let _ = writeln!(
+            out,
+            "fileserver_http_request_duration_seconds_sum{{route=\"{}\"}} {}",
+            route, histogram.sum
+        );
+        let _ = writeln!(
+            out,
+            "fileserver_http_request_duration_seconds_count{{route=\"{}\"}} {}",
+            route, histogram.count
+        );
+    }
+
+    let counters = [
+        (
+            "fileserver_uploaded_bytes_total",
+            "Bytes of uploaded files written.",
+            &UPLOADED_BYTES,
+        ),
+        (
+            "fileserver_downloaded_bytes_total",
+            "Bytes of downloads sent.",
+            &DOWNLOADED_BYTES,
+        ),
+        (
+            "fileserver_ffprobe_runs_total",
+            "Times ffprobe was run.",
+            &FFPROBE_RUNS,
+        ),
+        (
+            "fileserver_ffprobe_failures_total",
+            "Times ffprobe failed, timed out or wasn't found.",
+            &FFPROBE_FAILURES,
+        ),
+    ];
+    for (name, help, counter) in counters {
+        let _ = writeln!(out, "# HELP {} {}", name, help);
+        let _ = writeln!(out, "# TYPE {} counter", name);
+        let _ = writeln!(out, "{} {}", name, counter.load(Ordering::Relaxed));
+    }
+
+    out.push_str("# HELP fileserver_active_transfers Uploads and downloads in progress.\n");
+    out.push_str("# TYPE fileserver_active_transfers gauge\n");
+    for (direction, gauge) in [("upload", &ACTIVE_UPLOADS), ("download", &ACTIVE_DOWNLOADS)] {
+        let _ = writeln!(
+            out,
+            "fileserver_active_transfers{{direction=\"{}\"}} {}",
+            direction,
+            gauge.load(Ordering::Relaxed)
+        );
+    }
+
+    out.push_str(
+        "# HELP fileserver_free_bytes Free space on the file system of each served directory.\n",
+    );
+    out.push_str("# TYPE fileserver_free_bytes gauge\n");
+    for root in ROOTS.get().into_iter().flatten() {
+        if let Some(free) = available_space(root) {
+            let _ = writeln!(
+                out,
+                "fileserver_free_bytes{{root=\"{}\"}} {}",
+                escape(&root.to_string_lossy()),
+                free
+            );
+        }
+    }
+
+    ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], out)
+}
+
+/// Escapes a label value.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
@@ -0,0 +1,250 @@
+use axum::async_trait;
+use axum::extract::{ConnectInfo, FromRequest, RequestParts};
+use axum::http::{header, Extensions, HeaderMap};
+use once_cell::sync::OnceCell;
+use std::convert::Infallible;
+use std::net::{IpAddr, SocketAddr};
+
+const X_FORWARDED_FOR: &str = "x-forwarded-for";
+const X_FORWARDED_PROTO: &str = "x-forwarded-proto";
+const X_FORWARDED_HOST: &str = "x-forwarded-host";
+const X_FORWARDED_PREFIX: &str = "x-forwarded-prefix";
+
+pub struct ProxyConfig {
+    /// Peers whose X-Forwarded-For headers are honored
+    pub trusted_proxies: Vec<IpAddr>,
+    /// Header an authenticating reverse proxy puts the user name in
+    pub user_header: Option<String>,
+    /// Path all routes are served under, e.g. "/files", empty for the root
+    pub base_path: String,
+}
+
+static CONFIG: OnceCell<ProxyConfig> = OnceCell::new();
+
+pub fn init(config: ProxyConfig) {
+    if !config.trusted_proxies.is_empty() {
+        log::info!("trusted proxies: {:?}", config.trusted_proxies);
+    }
+    CONFIG.set(config).ok();
+}
+
+/// Path all routes are served under, see `ProxyConfig::base_path`.
+pub fn base_path() -> &'static str {
+    CONFIG
+        .get()
+        .map(|config| config.base_path.as_str())
+        .unwrap_or("")
+}
+
+fn is_trusted(ip: &IpAddr) -> bool {
+    CONFIG
+        .get()
+        .map(|config| config.trusted_proxies.contains(ip))
+        .unwrap_or(false)
+}
+
+/// The IP of the client a request came from. If the peer is a trusted proxy,
+/// X-Forwarded-For is followed from the right past trusted proxies to the
+/// first address that isn't one, so that clients can't spoof their address
+/// by sending the header themselves.
+pub fn client_ip(extensions: &Extensions, headers: &HeaderMap) -> Option<IpAddr> {
+    let ConnectInfo(peer) = extensions.get::<ConnectInfo<SocketAddr>>()?;
+    let mut ip = peer.ip();
+    if !is_trusted(&ip) {
+        return Some(ip);
+    }
+
+    let hops = headers
+        .get_all(X_FORWARDED_FOR)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .collect::<Vec<_>>();
+    for hop in hops.into_iter().rev() {
+        match hop.parse::<IpAddr>() {
+            Ok(hop) => ip = hop,
+            // a malformed entry can't be traced any further
+            Err(_) => break,
+        }
+        if !is_trusted(&ip) {
+            break;
+        }
+    }
+    Some(ip)
+}
+
+/// The first value of an X-Forwarded-* header, only if the peer is a trusted
+/// proxy.
+fn forwarded<'a>(extensions: &Extensions, headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    let ConnectInfo(peer) = extensions.get::<ConnectInfo<SocketAddr>>()?;
+    if !is_trusted(&peer.ip()) {
+        return None;
+    }
+    headers
+        .get(name)?
+        .to_str()
+        .ok()?
+        .split(',')
+        .map(str::trim)
+        .next()
+        .filter(|value| !value.is_empty())
+}
+
+/// The path clients reach the server under: the X-Forwarded-Prefix of a
+/// proxy that strips it, followed by the base path.
+pub fn public_base_path(extensions: &Extensions, headers: &HeaderMap) -> String {
+    let prefix = forwarded(extensions, headers, X_FORWARDED_PREFIX).unwrap_or("");
+    format!("{}{}", prefix.trim_end_matches('/'), base_path())
+}
+
+/// The URL clients reach the server at, e.g. "https://intranet/files", for
+/// building absolute links.
+pub fn public_url(extensions: &Extensions, headers: &HeaderMap) -> Option<String> {
+    let scheme = forwarded(extensions, headers, X_FORWARDED_PROTO).unwrap_or("http");
+    let host = forwarded(extensions, headers, X_FORWARDED_HOST).or_else(|| {
+        headers
+            .get(header::HOST)
+            .and_then(|value| value.to_str().ok())
+    })?;
+    Some(format!(
+        "{}://{}{}",
+        scheme,
+        host,
+        public_base_path(extensions, headers)
+    ))
+}
+
+/// Extracts the IP of the client, see `client_ip`.
+pub struct ClientIp(pub Option<IpAddr>);
+
+#[async_trait]
+impl<B: Send> FromRequest<B> for ClientIp {
+    type Rejection = Infallible;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        Ok(ClientIp(client_ip(req.extensions(), req.headers())))
+    }
+}
+
+/// Extracts the public base path, for handlers that generate links.
+pub struct BasePath(pub String);
+
+#[async_trait]
+impl<B: Send> FromRequest<B> for BasePath {
+    type Rejection = Infallible;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        Ok(BasePath(public_base_path(req.extensions(), req.headers())))
+    }
+}
+
+/// Extracts the public URL, see `public_url`.
+pub struct PublicUrl(pub Option<String>);
+
+#[async_trait]
+impl<B: Send> FromRequest<B> for PublicUrl {
+    type Rejection = Infallible;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        Ok(PublicUrl(public_url(req.extensions(), req.headers())))
+    }
+}
+
+/// The user name an authenticating reverse proxy passed, if configured and
+/// the peer is a trusted proxy, as anyone else could send the header too.
+pub fn user(extensions: &Extensions, headers: &HeaderMap) -> Option<String> {
+    let ConnectInfo(peer) = extensions.get::<ConnectInfo<SocketAddr>>()?;
+    if !is_trusted(&peer.ip()) {
+        return None;
+    }
+    CONFIG
+        .get()
+        .and_then(|config| config.user_header.as_deref())
+        .and_then(|name| headers.get(name))
+        .and_then(|value| value.to_str().ok())
+        .filter(|user| !user.is_empty())
+        .map(|user| user.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    const PROXY: &str = "10.0.0.1";
+
+    fn request(peer: &str, headers: &[(&'static str, &str)]) -> (Extensions, HeaderMap) {
+        CONFIG.get_or_init(|| ProxyConfig {
+            trusted_proxies: vec![PROXY.parse().unwrap(), "10.0.0.2".parse().unwrap()],
+            user_header: Some("x-remote-user".to_string()),
+            // shared by the tests of all modules
+            base_path: String::new(),
+        });
+        let mut extensions = Extensions::new();
+        let peer = SocketAddr::new(peer.parse().unwrap(), 40000);
+        extensions.insert(ConnectInfo(peer));
+        let mut map = HeaderMap::new();
+        for (name, value) in headers {
+            map.append(*name, HeaderValue::from_str(value).unwrap());
+        }
+        (extensions, map)
+    }
+
+    fn ip(ip: &str) -> Option<IpAddr> {
+        Some(ip.parse().unwrap())
+    }
+
+    #[test]
+    fn follows_forwarded_for_of_trusted_proxies() {
+        let forwarded = [(X_FORWARDED_FOR, "192.0.2.9, 198.51.100.7, 10.0.0.2")];
+        let (extensions, headers) = request(PROXY, &forwarded);
+        assert_eq!(client_ip(&extensions, &headers), ip("198.51.100.7"));
+
+        let (extensions, headers) = request(PROXY, &[(X_FORWARDED_FOR, "bogus, 198.51.100.7")]);
+        assert_eq!(client_ip(&extensions, &headers), ip("198.51.100.7"));
+
+        let (extensions, headers) = request(PROXY, &[]);
+        assert_eq!(client_ip(&extensions, &headers), ip(PROXY));
+    }
+
+    #[test]
+    fn ignores_headers_of_untrusted_peers() {
+        let spoofed = [
+            (X_FORWARDED_FOR, "10.0.0.2"),
+            (X_FORWARDED_PREFIX, "/evil"),
+            (X_FORWARDED_HOST, "evil.example"),
+            ("x-remote-user", "admin"),
+            ("host", "files.example"),
+        ];
+        let (extensions, headers) = request("192.0.2.9", &spoofed);
+        assert_eq!(client_ip(&extensions, &headers), ip("192.0.2.9"));
+        assert_eq!(user(&extensions, &headers), None);
+        assert_eq!(public_base_path(&extensions, &headers), "");
+        assert_eq!(
+            public_url(&extensions, &headers).as_deref(),
+            Some("http://files.example")
+        );
+    }
+
+    #[test]
+    fn honors_headers_of_trusted_proxies() {
+        let forwarded = [
+            (X_FORWARDED_PROTO, "https"),
+            (X_FORWARDED_HOST, "intranet, other"),
+            (X_FORWARDED_PREFIX, "/share/"),
+            ("x-remote-user", "alice"),
+        ];
+        let (extensions, headers) = request(PROXY, &forwarded);
+        assert_eq!(user(&extensions, &headers).as_deref(), Some("alice"));
+        assert_eq!(public_base_path(&extensions, &headers), "/share");
+        assert_eq!(
+            public_url(&extensions, &headers).as_deref(),
+            Some("https://intranet/share")
+        );
+
+        let (extensions, headers) = request(PROXY, &[("x-remote-user", "")]);
+        assert_eq!(user(&extensions, &headers), None);
+        assert_eq!(user(&Extensions::new(), &headers), None);
+    }
+}
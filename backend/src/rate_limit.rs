@@ -0,0 +1,338 @@
+use crate::{metrics, proxy};
+use axum::body::{boxed, Body, Bytes, HttpBody};
+use axum::http::{header, Method, Request, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::{BoxError, Json};
+use common::JsonResponse;
+use once_cell::sync::{Lazy, OnceCell};
+use percent_encoding::percent_decode_str;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// Failures further apart than this don't add up to a ban.
+const FAILURE_WINDOW: Duration = Duration::from_secs(15 * 60);
+
+/// Seconds clients are asked to wait when all transfer slots are taken.
+const TRANSFER_RETRY_AFTER: Duration = Duration::from_secs(5);
+
+/// Idle buckets and stale failures are dropped once there are more than this
+/// many.
+const MAX_ENTRIES: usize = 10_000;
+
+/// Routes rate limits are configured for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, clap::ValueEnum)]
+pub enum RouteClass {
+    Listing,
+    Download,
+    Upload,
+    /// Unlocking password protected share links
+    Login,
+}
+
+/// Requests allowed per period, e.g. 60 per minute.
+#[derive(Clone, Copy, Debug)]
+pub struct Rate {
+    pub requests: u32,
+    pub period: Duration,
+}
+
+pub struct RateLimits {
+    pub rates: Vec<(RouteClass, Rate)>,
+    /// Bytes per second of a single download
+    pub download_rate: Option<u64>,
+    /// Bytes per second of a single upload
+    pub upload_rate: Option<u64>,
+    /// Uploads and downloads that may run at the same time
+    pub max_transfers: Option<usize>,
+    /// Failed logins and path traversal attempts an IP is banned after, 0 to
+    /// never ban
+    pub ban_after: u32,
+    pub ban_duration: Duration,
+}
+
+/// Requests a client may still make to a class of routes.
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+struct Failures {
+    count: u32,
+    first: Instant,
+    banned_until: Option<Instant>,
+}
+
+static LIMITS: OnceCell<RateLimits> = OnceCell::new();
+
+static TRANSFERS: OnceCell<Arc<Semaphore>> = OnceCell::new();
+
+/// Buckets by route class and client, which is an IP or "user:<name>".
+static BUCKETS: Lazy<Mutex<HashMap<(RouteClass, String), Bucket>>> = Lazy::new(Default::default);
+
+static FAILURES: Lazy<Mutex<HashMap<IpAddr, Failures>>> = Lazy::new(Default::default);
+
+pub fn init(limits: RateLimits) {
+    for (class, rate) in &limits.rates {
+        log::info!(
+            "rate limit of {:?}: {} requests per {:?}",
+            class,
+            rate.requests,
+            rate.period
+        );
+    }
+    if let Some(max_transfers) = limits.max_transfers {
+        TRANSFERS.set(Arc::new(Semaphore::new(max_transfers))).ok();
+    }
+    LIMITS.set(limits).ok();
+}
+
+/// Records a failed login or another suspicious request of `ip`, banning it
+/// once there were too many.
+pub fn failed(ip: Option<IpAddr>, reason: &str) {
+    let (ip, limits) = match (ip, LIMITS.get()) {
+        (Some(ip), Some(limits)) if limits.ban_after > 0 => (ip, limits),
+        _ => {
+            log::warn!("{}", reason);
+            return;
+        }
+    };
+    let now = Instant::now();
+    let mut failures = FAILURES.lock().unwrap();
+    if failures.len() > MAX_ENTRIES {
+        failures.retain(|_, failures| {
+            now.duration_since(failures.first) < FAILURE_WINDOW
+                || matches!(failures.banned_until, Some(until) if until > now)
+        });
+    }
+    let failures = failures.entry(ip).or_insert(Failures {
+        count: 0,
+        first: now,
+        banned_until: None,
+    });
+    if now.duration_since(failures.first) >= FAILURE_WINDOW {
+        failures.count = 0;
+        failures.first = now;
+    }
+    failures.count += 1;
+    log::warn!("{} from {} ({} failures)", reason, ip, failures.count);
+    if failures.count >= limits.ban_after {
+        log::warn!("banning {} for {:?}", ip, limits.ban_duration);
+        failures.count = 0;
+        failures.banned_until = Some(now + limits.ban_duration);
+    }
+}
+
+/// Time left of the ban of `ip`, if it is banned.
+fn banned(ip: IpAddr) -> Option<Duration> {
+    let until = FAILURES.lock().unwrap().get(&ip)?.banned_until?;
+    until.checked_duration_since(Instant::now())
+}
+
+/// Takes a request from the bucket of `client`, returns the time until the
+/// next one is allowed if it is empty.
+fn take(class: RouteClass, rate: Rate, client: String) -> Result<(), Duration> {
+    let now = Instant::now();
+    let capacity = rate.requests as f64;
+    let per_second = capacity / rate.period.as_secs_f64();
+    let mut buckets = BUCKETS.lock().unwrap();
+    if buckets.len() > MAX_ENTRIES {
+        // a bucket that has been refilled is as good as a new one
+        buckets.retain(|_, bucket| now.duration_since(bucket.updated) < rate.period);
+    }
+    let bucket = buckets.entry((class, client)).or_insert(Bucket {
+        tokens: capacity,
+        updated: now,
+    });
+    bucket.tokens = (bucket.tokens + now.duration_since(bucket.updated).as_secs_f64() * per_second)
+        .min(capacity);
+    bucket.updated = now;
+    if bucket.tokens >= 1.0 {
+        bucket.tokens -= 1.0;
+        Ok(())
+    } else {
+        Err(Duration::from_secs_f64((1.0 - bucket.tokens) / per_second))
+    }
+}
+
+fn route_class(method: &Method, route: &str) -> Option<RouteClass> {
+    let has_prefix = |prefixes: &[&str]| prefixes.iter().any(|prefix| route.starts_with(prefix));
+    if route.starts_with("/api/listing") {
+        Some(RouteClass::Listing)
+    } else if method == Method::POST && route == "/s/:token" {
+        Some(RouteClass::Login)
+    } else if method == Method::POST && has_prefix(&metrics::UPLOAD_ROUTES) {
+        Some(RouteClass::Upload)
+    } else if has_prefix(&metrics::DOWNLOAD_ROUTES) {
+        Some(RouteClass::Download)
+    } else {
+        None
+    }
+}
+
+/// Whether a path tries to get out of the directory it is resolved in.
+fn is_traversal(path: &str) -> bool {
+    percent_decode_str(path)
+        .decode_utf8_lossy()
+        .split(['/', '\\'])
+        .any(|segment| segment == "..")
+}
+
+/// Refuses requests of banned IPs and requests exceeding the rate limit of
+/// their route class, throttles uploads and downloads and caps how many of
+/// them run at the same time.
+pub async fn limit(req: Request<Body>, next: Next<Body>) -> Response {
+    let limits = match LIMITS.get() {
+        Some(limits) => limits,
+        None => return next.run(req).await,
+    };
+
+    let ip = proxy::client_ip(req.extensions(), req.headers());
+    if let Some(left) = ip.and_then(banned) {
+        return too_many_requests(left, "too many failed attempts, try again later");
+    }
+    if is_traversal(req.uri().path()) {
+        // rejected by the handlers, which can't tell who sent it
+        failed(ip, &format!("path traversal attempt {}", req.uri().path()));
+    }
+
+    let class = match route_class(req.method(), &metrics::route(&req)) {
+        Some(class) => class,
+        None => return next.run(req).await,
+    };
+    if let Some((_, rate)) = limits.rates.iter().find(|(c, _)| *c == class) {
+        let user = proxy::user(req.extensions(), req.headers());
+        let clients = ip
+            .map(|ip| ip.to_string())
+            .into_iter()
+            .chain(user.map(|user| format!("user:{}", user)));
+        for client in clients {
+            if let Err(wait) = take(class, *rate, client) {
+                return too_many_requests(wait, "too many requests, try again later");
+            }
+        }
+    }
+
+    let transfer = matches!(class, RouteClass::Upload | RouteClass::Download);
+    let permit = match TRANSFERS.get() {
+        Some(transfers) if transfer => match transfers.clone().try_acquire_owned() {
+            Ok(permit) => Some(permit),
+            Err(_) => {
+                return too_many_requests(
+                    TRANSFER_RETRY_AFTER,
+                    "too many transfers in progress, try again later",
+                )
+            }
+        },
+        _ => None,
+    };
+
+    if class == RouteClass::Upload {
+        let req = match limits.upload_rate {
+            Some(rate) => req.map(|body| throttle(body, Some(rate), None)),
+            None => req,
+        };
+        let response = next.run(req).await;
+        drop(permit);
+        return response;
+    }
+
+    let response = next.run(req).await;
+    if class != RouteClass::Download
+        || !response.status().is_success()
+        || (permit.is_none() && limits.download_rate.is_none())
+    {
+        return response;
+    }
+    let rate = limits.download_rate;
+    response.map(|body| boxed(throttle(body, rate, permit)))
+}
+
+/// Paces `body` to `rate` bytes per second, holding `permit` until it has
+/// been sent or the client went away.
+fn throttle<B>(body: B, rate: Option<u64>, permit: Option<OwnedSemaphorePermit>) -> Body
+where
+    B: HttpBody<Data = Bytes> + Send + Unpin + 'static,
+    B::Error: Into<BoxError>,
+{
+    let start = Instant::now();
+    let stream = futures::stream::unfold(
+        (body, 0u64, permit),
+        move |(mut body, mut sent, permit)| async move {
+            let data = body.data().await?.map_err(Into::<BoxError>::into);
+            if let (Ok(data), Some(rate)) = (&data, rate) {
+                sent += data.len() as u64;
+                let due = Duration::from_secs_f64(sent as f64 / rate as f64);
+                if let Some(ahead) = due.checked_sub(start.elapsed()) {
+                    tokio::time::sleep(ahead).await;
+                }
+            }
+            Some((data, (body, sent, permit)))
+        },
+    );
+    Body::wrap_stream(stream)
+}
+
+/// Answers with 429 and the usual `JsonResponse::Failed` body.
+fn too_many_requests(retry_after: Duration, msg: &str) -> Response {
+    let seconds = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+    let json_resp = Json(JsonResponse::Failed {
+        msg: Some(msg.to_string()),
+    });
+    (
+        StatusCode::TOO_MANY_REQUESTS,
+        [(header::RETRY_AFTER, seconds.max(1).to_string())],
+        json_resp,
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_traversal() {
+        assert!(is_traversal("/api/static/../etc/passwd"));
+        assert!(is_traversal("/api/static/%2e%2e/etc/passwd"));
+        assert!(is_traversal("/api/static/a%2F..%2Fb"));
+        assert!(is_traversal("/api/static/..\\secret"));
+        assert!(is_traversal("/.."));
+        assert!(!is_traversal("/api/static/..hidden/a..b"));
+        assert!(!is_traversal("/api/static/./a"));
+    }
+
+    #[test]
+    fn takes_requests_from_buckets() {
+        let rate = Rate {
+            requests: 2,
+            period: Duration::from_secs(60),
+        };
+        let client = || "user:bucket-test".to_string();
+        assert!(take(RouteClass::Listing, rate, client()).is_ok());
+        assert!(take(RouteClass::Listing, rate, client()).is_ok());
+        let wait = take(RouteClass::Listing, rate, client()).unwrap_err();
+        // a request every 30 seconds is refilled
+        assert!(wait > Duration::from_secs(29) && wait <= Duration::from_secs(30));
+
+        // classes and clients have buckets of their own
+        assert!(take(RouteClass::Download, rate, client()).is_ok());
+        assert!(take(RouteClass::Listing, rate, "user:other".to_string()).is_ok());
+    }
+
+    #[test]
+    fn refills_buckets() {
+        let rate = Rate {
+            requests: 1,
+            period: Duration::from_millis(50),
+        };
+        let client = || "user:refill-test".to_string();
+        assert!(take(RouteClass::Upload, rate, client()).is_ok());
+        assert!(take(RouteClass::Upload, rate, client()).is_err());
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(take(RouteClass::Upload, rate, client()).is_ok());
+    }
+}
@@ -0,0 +1,527 @@
+use crate::document::escape;
+use crate::proxy::{BasePath, ClientIp};
+use crate::store::{format_time, load_json, not_found, now, page, random_hex, save_json};
+use crate::{audit, rate_limit, resolve_path, AppError};
+use axum::body::StreamBody;
+use axum::extract::{Form, Path};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Redirect, Response};
+use axum::Json;
+use common::{CreateShare, JsonResponse, ShareInfo};
+use once_cell::sync::{Lazy, OnceCell};
+use path_absolutize::Absolutize;
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use tokio_util::io::ReaderStream;
+
+/// Rounds of SHA-256 passwords are hashed with, to slow down guessing them
+/// from a leaked shares file.
+const PASSWORD_ROUNDS: u32 = 10_000;
+
+#[derive(Serialize, Deserialize, Clone)]
+struct Share {
+    token: String,
+    /// Request path of the shared file or directory, e.g. "/dir/a.txt"
+    path: String,
+    /// Unix timestamps
+    created: i64,
+    expires: Option<i64>,
+    max_downloads: Option<u64>,
+    downloads: u64,
+    /// "<salt>$<hash>" in hex
+    password: Option<String>,
+}
+
+impl Share {
+    fn is_valid(&self, now: i64) -> bool {
+        self.expires.map(|expires| now < expires).unwrap_or(true)
+            && self
+                .max_downloads
+                .map(|max| self.downloads < max)
+                .unwrap_or(true)
+    }
+
+    fn info(&self) -> ShareInfo {
+        ShareInfo {
+            token: self.token.clone(),
+            path: self.path.clone(),
+            created: format_time(self.created),
+            expires: self.expires.map(format_time),
+            max_downloads: self.max_downloads,
+            downloads: self.downloads,
+            has_password: self.password.is_some(),
+        }
+    }
+}
+
+static SHARES: Lazy<Mutex<HashMap<String, Share>>> = Lazy::new(Default::default);
+
+/// File the shares are persisted in, shares only live in memory without it.
+static SHARES_FILE: OnceCell<Option<PathBuf>> = OnceCell::new();
+
+pub fn init(shares_file: Option<PathBuf>) {
+    if let Some(file) = &shares_file {
+        let shares = load_json::<Share>(file);
+        log::info!("loaded {} share link(s) from {:?}", shares.len(), file);
+        let mut all = SHARES.lock().unwrap();
+        for share in shares {
+            all.insert(share.token.clone(), share);
+        }
+    }
+    SHARES_FILE.set(shares_file).ok();
+}
+
+fn persist(shares: &HashMap<String, Share>) {
+    if let Some(Some(file)) = SHARES_FILE.get() {
+        save_json(file, &shares.values().collect::<Vec<_>>());
+    }
+}
+
+fn hash_password(salt: &str, password: &str) -> String {
+    let mut digest = Sha256::digest(format!("{}{}", salt, password).as_bytes());
+    for _ in 0..PASSWORD_ROUNDS {
+        let mut hasher = Sha256::new();
+        hasher.update(salt.as_bytes());
+        hasher.update(digest);
+        digest = hasher.finalize();
+    }
+    format!("{:x}", digest)
+}
+
+fn check_password(stored: &str, password: &str) -> bool {
+    match stored.split_once('$') {
+        Some((salt, hash)) => constant_time_eq(hash, &hash_password(salt, password)),
+        None => false,
+    }
+}
+
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+/// Value of the cookie that proves the password of a share was entered,
+/// derived from the share so that it is void once the share is revoked.
+fn access_proof(share: &Share) -> String {
+    let digest = Sha256::digest(
+        format!(
+            "{}:{}",
+            share.token,
+            share.password.as_deref().unwrap_or_default()
+        )
+        .as_bytes(),
+    );
+    format!("{:x}", digest)
+}
+
+fn cookie_name(token: &str) -> String {
+    format!("share_{}", token)
+}
+
+fn has_access(share: &Share, headers: &HeaderMap) -> bool {
+    if share.password.is_none() {
+        return true;
+    }
+    let name = cookie_name(&share.token);
+    let proof = access_proof(share);
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|cookies| cookies.split(';'))
+        .filter_map(|cookie| cookie.trim().split_once('='))
+        .any(|(key, value)| key == name && constant_time_eq(value, &proof))
+}
+
+/// Creates a share link for a file or directory.
+pub async fn create(
+    client: audit::Client,
+    Json(req): Json<CreateShare>,
+) -> Result<Json<ShareInfo>, AppError> {
+    let result = create_share(&req);
+    client.record(audit::Operation::Share, &req.path, None, &result);
+    result
+}
+
+fn create_share(req: &CreateShare) -> Result<Json<ShareInfo>, AppError> {
+    let full_path = resolve_path(&req.path)?;
+    if !full_path.exists() {
+        return Err(AppError(format!("file not found: {}", req.path)));
+    }
+
+    let created = now();
+    let password = match req
+        .password
+        .as_deref()
+        .filter(|password| !password.is_empty())
+    {
+        Some(password) => {
+            let salt = random_hex(16)?;
+            Some(format!("{}${}", salt, hash_password(&salt, password)))
+        }
+        None => None,
+    };
+    let share = Share {
+        token: random_hex(16)?,
+        path: format!("/{}", req.path.trim_matches('/')),
+        created,
+        expires: req
+            .expires_in_hours
+            .map(|hours| created + hours as i64 * 3600),
+        max_downloads: req.max_downloads,
+        downloads: 0,
+        password,
+    };
+    log::info!("created share link for {}", share.path);
+
+    let info = share.info();
+    let mut shares = SHARES.lock().unwrap();
+    shares.insert(share.token.clone(), share);
+    persist(&shares);
+    Ok(Json(info))
+}
+
+/// Lists the share links that are still valid, newest first. Expired and
+/// used up links are dropped.
+pub async fn list() -> Json<Vec<ShareInfo>> {
+    let now = now();
+    let mut shares = SHARES.lock().unwrap();
+    let count = shares.len();
+    shares.retain(|_, share| share.is_valid(now));
+    if shares.len() != count {
+        persist(&shares);
+    }
+
+    let mut infos = shares.values().collect::<Vec<_>>();
+    infos.sort_by_key(|share| std::cmp::Reverse(share.created));
+    Json(infos.into_iter().map(Share::info).collect())
+}
+
+pub async fn revoke(
+    client: audit::Client,
+    Path(token): Path<String>,
+) -> Result<Json<JsonResponse>, AppError> {
+    let share = {
+        let mut shares = SHARES.lock().unwrap();
+        let share = shares.remove(&token);
+        if share.is_some() {
+            persist(&shares);
+        }
+        share
+    };
+    match share {
+        Some(share) => {
+            log::info!("revoked share link for {}", share.path);
+            let resp = Json(JsonResponse::Succeeded { msg: None });
+            client.record(audit::Operation::Unshare, &share.path, None, &resp);
+            Ok(resp)
+        }
+        None => {
+            let result = Err(AppError("no such share link".to_string()));
+            let path = format!("/s/{}", token);
+            client.record(audit::Operation::Unshare, &path, None, &result);
+            result
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct PasswordForm {
+    password: String,
+}
+
+/// Serves a shared file, or the listing of a shared directory.
+pub async fn serve_root(
+    BasePath(base_path): BasePath,
+    Path(token): Path<String>,
+    headers: HeaderMap,
+) -> Response {
+    serve_share(&base_path, &token, "", &headers).await
+}
+
+/// Serves a file or directory inside a shared directory.
+pub async fn serve(
+    BasePath(base_path): BasePath,
+    Path((token, path)): Path<(String, String)>,
+    headers: HeaderMap,
+) -> Response {
+    serve_share(&base_path, &token, &path, &headers).await
+}
+
+/// Path of the share link as seen by clients.
+fn share_root(base_path: &str, token: &str) -> String {
+    format!("{}/s/{}", base_path, token)
+}
+
+/// Checks the password of a share, setting a cookie that grants access to it
+/// for the browser session.
+pub async fn unlock(
+    BasePath(base_path): BasePath,
+    ClientIp(ip): ClientIp,
+    Path(token): Path<String>,
+    Form(form): Form<PasswordForm>,
+) -> Response {
+    let root = share_root(&base_path, &token);
+    let share = match SHARES.lock().unwrap().get(&token).cloned() {
+        Some(share) if share.is_valid(now()) => share,
+        _ => return not_found(),
+    };
+    match &share.password {
+        Some(stored) if !check_password(stored, &form.password) => {
+            rate_limit::failed(
+                ip,
+                &format!("wrong password for share link of {}", share.path),
+            );
+            password_page(&root, true)
+        }
+        _ => {
+            let cookie = format!(
+                "{}={}; Path={}; HttpOnly; SameSite=Lax",
+                cookie_name(&token),
+                access_proof(&share),
+                root
+            );
+            ([(header::SET_COOKIE, cookie)], Redirect::to(&root)).into_response()
+        }
+    }
+}
+
+async fn serve_share(base_path: &str, token: &str, path: &str, headers: &HeaderMap) -> Response {
+    let root = share_root(base_path, token);
+    let share = match SHARES.lock().unwrap().get(token).cloned() {
+        Some(share) if share.is_valid(now()) => share,
+        _ => return not_found(),
+    };
+    if !has_access(&share, headers) {
+        return password_page(&root, false);
+    }
+
+    let path = path.trim_matches('/');
+    let full_path = match resolve_shared(&share, path) {
+        Some(full_path) => full_path,
+        None => return not_found(),
+    };
+    if full_path.is_dir() {
+        return listing_page(&root, &share, path, &full_path);
+    }
+
+    let file = match tokio::fs::File::open(&full_path).await {
+        Ok(file) => file,
+        Err(_) => return not_found(),
+    };
+    let size = file.metadata().await.map(|m| m.len()).unwrap_or_default();
+
+    // counted when the download starts, a failed download counts too
+    {
+        let mut shares = SHARES.lock().unwrap();
+        match shares.get_mut(token) {
+            Some(share) if share.is_valid(now()) => share.downloads += 1,
+            _ => return not_found(),
+        }
+        persist(&shares);
+    }
+    log::info!("share link download of {:?}", full_path);
+
+    let file_name = full_path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let content_type = mime_guess::from_path(&full_path)
+        .first_or_octet_stream()
+        .to_string();
+    let disposition = format!(
+        "attachment; filename*=UTF-8''{}",
+        utf8_percent_encode(&file_name, NON_ALPHANUMERIC)
+    );
+    (
+        [
+            (header::CONTENT_TYPE, content_type),
+            (header::CONTENT_LENGTH, size.to_string()),
+            (header::CONTENT_DISPOSITION, disposition),
+        ],
+        StreamBody::new(ReaderStream::new(file)),
+    )
+        .into_response()
+}
+
+/// Maps a path inside a share to the full path, `None` if it escapes the
+/// shared directory or the share is a file and `path` isn't empty.
+fn resolve_shared(share: &Share, path: &str) -> Option<PathBuf> {
+    let root = resolve_path(&share.path).ok()?;
+    if path.is_empty() {
+        return root.exists().then_some(root);
+    }
+    if !root.is_dir() {
+        return None;
+    }
+    let full_path = resolve_path(&format!("{}/{}", share.path, path)).ok()?;
+    let inside = match (full_path.absolutize(), root.absolutize()) {
+        (Ok(full), Ok(root)) => full.starts_with(&root),
+        _ => false,
+    };
+    (inside && full_path.exists()).then_some(full_path)
+}
+
+/// A form posting the password to `root`, the path of the share link.
+fn password_page(root: &str, wrong: bool) -> Response {
+    let error = if wrong {
+        "<p style=\"color: #c00\">Wrong password.</p>"
+    } else {
+        ""
+    };
+    let body = format!(
+        r#"{error}<form method="post" action="{root}">
+<input type="password" name="password" autofocus>
+<button>Open</button>
+</form>"#,
+        error = error,
+        root = escape(root)
+    );
+    page(StatusCode::UNAUTHORIZED, "Password required", &body)
+}
+
+fn listing_page(root: &str, share: &Share, path: &str, full_path: &std::path::Path) -> Response {
+    let mut entries = match std::fs::read_dir(full_path) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| {
+                let is_dir = entry.file_type().map(|t| t.is_dir()).unwrap_or(false);
+                (entry.file_name().to_string_lossy().to_string(), is_dir)
+            })
+            .collect::<Vec<_>>(),
+        Err(_) => return not_found(),
+    };
+    entries.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+
+    let link = |path: &str| {
+        let encoded = path
+            .split('/')
+            .filter(|segment| !segment.is_empty())
+            .map(|segment| utf8_percent_encode(segment, NON_ALPHANUMERIC).to_string())
+            .collect::<Vec<_>>()
+            .join("/");
+        if encoded.is_empty() {
+            root.to_string()
+        } else {
+            format!("{}/{}", root, encoded)
+        }
+    };
+    let mut body = String::from("<ul>\n");
+    if !path.is_empty() {
+        let parent = path
+            .rsplit_once('/')
+            .map(|(parent, _)| parent)
+            .unwrap_or("");
+        body.push_str(&format!(
+            "<li><a href=\"{}\">◄ Parent Directory</a></li>\n",
+            escape(&link(parent))
+        ));
+    }
+    for (name, is_dir) in entries {
+        let icon = if is_dir { "📁" } else { "📝" };
+        body.push_str(&format!(
+            "<li><a href=\"{}\">{} {}</a></li>\n",
+            escape(&link(&format!("{}/{}", path, name))),
+            icon,
+            escape(&name)
+        ));
+    }
+    body.push_str("</ul>");
+
+    let title = format!("{}/{}", share.path, path);
+    page(StatusCode::OK, title.trim_end_matches('/'), &body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    fn share(expires: Option<i64>, max_downloads: Option<u64>, password: Option<&str>) -> Share {
+        Share {
+            token: "0123abcd".to_string(),
+            path: "/dir/a.txt".to_string(),
+            created: 1_000,
+            expires,
+            max_downloads,
+            downloads: 0,
+            password: password.map(|password| format!("salt${}", hash_password("salt", password))),
+        }
+    }
+
+    fn cookies(cookies: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::COOKIE, HeaderValue::from_str(cookies).unwrap());
+        headers
+    }
+
+    #[test]
+    fn expires_shares() {
+        let expiring = share(Some(2_000), None, None);
+        assert!(expiring.is_valid(1_999));
+        assert!(!expiring.is_valid(2_000));
+        assert!(!expiring.is_valid(3_000));
+        assert!(share(None, None, None).is_valid(i64::MAX));
+    }
+
+    #[test]
+    fn limits_downloads() {
+        let mut share = share(None, Some(2), None);
+        assert!(share.is_valid(1_000));
+        share.downloads = 1;
+        assert!(share.is_valid(1_000));
+        share.downloads = 2;
+        assert!(!share.is_valid(1_000));
+    }
+
+    #[test]
+    fn checks_passwords() {
+        let stored = format!("salt${}", hash_password("salt", "secret"));
+        assert!(check_password(&stored, "secret"));
+        assert!(!check_password(&stored, "Secret"));
+        assert!(!check_password(&stored, ""));
+        // the salt is part of the hash
+        assert_ne!(
+            hash_password("salt", "secret"),
+            hash_password("pepper", "secret")
+        );
+        assert!(!check_password(
+            &format!("pepper${}", hash_password("salt", "secret")),
+            "secret"
+        ));
+        assert!(!check_password("no separator", "secret"));
+    }
+
+    #[test]
+    fn grants_access_with_the_cookie_of_the_share() {
+        let open = share(None, None, None);
+        assert!(has_access(&open, &HeaderMap::new()));
+
+        let protected = share(None, None, Some("secret"));
+        let proof = access_proof(&protected);
+        assert!(!has_access(&protected, &HeaderMap::new()));
+        assert!(has_access(
+            &protected,
+            &cookies(&format!("a=b; share_0123abcd={}", proof))
+        ));
+        assert!(!has_access(
+            &protected,
+            &cookies(&format!("share_other={}", proof))
+        ));
+        assert!(!has_access(&protected, &cookies("share_0123abcd=forged")));
+
+        // a new password voids the cookies handed out for the old one
+        let changed = share(None, None, Some("other"));
+        assert!(!has_access(
+            &changed,
+            &cookies(&format!("share_0123abcd={}", proof))
+        ));
+    }
+}
@@ -0,0 +1,67 @@
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tokio_util::sync::CancellationToken;
+
+/// Partially written files of uploads that are still in progress, removed on
+/// shutdown if their transfers did not finish within the grace period.
+static PARTIAL_FILES: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+/// Cancels `token` on the first SIGINT or SIGTERM.
+pub fn listen_for_signals(token: CancellationToken) {
+    tokio::spawn(async move {
+        let ctrl_c = tokio::signal::ctrl_c();
+
+        let mut term = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("failed to install SIGTERM handler");
+
+        tokio::select! {
+            _ = ctrl_c => log::info!("received SIGINT"),
+            _ = term.recv() => log::info!("received SIGTERM"),
+        }
+        token.cancel();
+    });
+}
+
+/// A file being written by an upload. Data goes to a hidden `.<name>.part`
+/// sibling which is renamed to its final name by `persist`; if dropped before
+/// that (client aborted, write error, shutdown), the partial file is removed.
+pub struct PartialFile {
+    path: PathBuf,
+}
+
+impl PartialFile {
+    pub fn new(final_path: &Path) -> Self {
+        let file_name = final_path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let path = final_path.with_file_name(format!(".{}.part", file_name));
+        PARTIAL_FILES.lock().unwrap().push(path.clone());
+        PartialFile { path }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub async fn persist(self, final_path: &Path) -> std::io::Result<()> {
+        tokio::fs::rename(&self.path, final_path).await
+    }
+}
+
+impl Drop for PartialFile {
+    fn drop(&mut self) {
+        PARTIAL_FILES.lock().unwrap().retain(|p| p != &self.path);
+        // no-op if the file has been persisted already
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Removes partial files left by transfers that were still running when the
+/// shutdown grace period expired.
+pub fn remove_partial_files() {
+    for path in PARTIAL_FILES.lock().unwrap().drain(..) {
+        log::info!("removing partial file: {:?}", path);
+        let _ = std::fs::remove_file(path);
+    }
+}
@@ -0,0 +1,96 @@
+use crate::document::escape;
+use crate::AppError;
+use axum::http::StatusCode;
+use axum::response::{Html, IntoResponse, Response};
+use chrono::{Local, TimeZone};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+/// Reads a list of links or other records from `file`, nothing if it
+/// doesn't exist yet.
+pub fn load_json<T: DeserializeOwned>(file: &std::path::Path) -> Vec<T> {
+    match std::fs::read(file) {
+        Ok(json) => match serde_json::from_slice(&json) {
+            Ok(values) => values,
+            Err(e) => {
+                log::error!("failed to parse {:?}: {}", file, e);
+                vec![]
+            }
+        },
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => vec![],
+        Err(e) => {
+            log::error!("failed to read {:?}: {}", file, e);
+            vec![]
+        }
+    }
+}
+
+/// Replaces the contents of `file` with `values`.
+pub fn save_json<T: Serialize>(file: &std::path::Path, values: &[T]) {
+    let result = serde_json::to_vec_pretty(values)
+        .map_err(|e| e.to_string())
+        .and_then(|json| {
+            // replaced atomically so that a crash can't leave half a file
+            let tmp_file = file.with_extension("tmp");
+            std::fs::write(&tmp_file, json)
+                .and_then(|_| std::fs::rename(&tmp_file, file))
+                .map_err(|e| e.to_string())
+        });
+    if let Err(e) = result {
+        log::error!("failed to save {:?}: {}", file, e);
+    }
+}
+
+pub fn now() -> i64 {
+    Local::now().timestamp()
+}
+
+pub fn format_time(timestamp: i64) -> String {
+    Local
+        .timestamp_opt(timestamp, 0)
+        .single()
+        .map(|time| time.format("%Y-%m-%d %H:%M:%S").to_string())
+        .unwrap_or_default()
+}
+
+/// A random token of `len` bytes, hex encoded.
+pub fn random_hex(len: usize) -> Result<String, AppError> {
+    let mut bytes = vec![0; len];
+    getrandom::getrandom(&mut bytes)
+        .map_err(|e| AppError(format!("failed to generate a token: {}", e)))?;
+    Ok(bytes.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+/// A minimal standalone page, for links opened without the frontend.
+pub fn page(status: StatusCode, title: &str, body: &str) -> Response {
+    let html = format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>{title}</title>
+<style>
+body {{ font-family: sans-serif; margin: 20px; }}
+li {{ margin: 6px 0; }}
+</style>
+</head>
+<body>
+<h3>{title}</h3>
+{body}
+</body>
+</html>"#,
+        title = escape(title),
+        body = body
+    );
+    (status, Html(html)).into_response()
+}
+
+/// The page of links that are invalid, expired or revoked.
+pub fn not_found() -> Response {
+    page(
+        StatusCode::NOT_FOUND,
+        "Link not available",
+        "<p>This link is invalid, has expired or was revoked.</p>",
+    )
+}
@@ -0,0 +1,123 @@
+use sd_notify::NotifyState;
+use std::env;
+use std::net::TcpListener;
+use std::os::unix::io::{FromRawFd, RawFd};
+use std::time::Duration;
+
+/// First fd of the sockets passed by systemd, see sd_listen_fds(3).
+const SD_LISTEN_FDS_START: RawFd = 3;
+
+/// Returns the listener to serve on, in order of preference: an fd passed
+/// explicitly with `--listen-fd`, the first socket handed over by systemd
+/// socket activation (`LISTEN_FDS`), or `None` to bind `addr:port` ourselves.
+pub fn take_listener(listen_fd: Option<RawFd>) -> Option<TcpListener> {
+    let fd = listen_fd.or_else(|| {
+        let listen_pid = env::var("LISTEN_PID").ok();
+        let listen_fds = env::var("LISTEN_FDS").ok();
+        // not meant for the processes we spawn
+        env::remove_var("LISTEN_PID");
+        env::remove_var("LISTEN_FDS");
+        match activation_fd(listen_pid.as_deref(), listen_fds.as_deref()) {
+            Ok(fd) => fd,
+            Err(e) => {
+                log::warn!("ignoring invalid socket activation variables: {}", e);
+                None
+            }
+        }
+    })?;
+
+    log::info!("using inherited socket, fd: {}", fd);
+    // SAFETY: the fd was handed to us by the parent process (systemd or the
+    // caller of `--listen-fd`) and is not owned by anything else in-process.
+    // It is closed on exec so that ffmpeg and other children don't keep it.
+    unsafe {
+        let flags = libc::fcntl(fd, libc::F_GETFD);
+        if flags >= 0 {
+            libc::fcntl(fd, libc::F_SETFD, flags | libc::FD_CLOEXEC);
+        }
+        Some(TcpListener::from_raw_fd(fd))
+    }
+}
+
+/// The first socket passed by systemd, given the values of `LISTEN_PID` and
+/// `LISTEN_FDS`. Sockets passed to another process, e.g. a parent that
+/// started us without clearing the variables, are not ours to take.
+fn activation_fd(
+    listen_pid: Option<&str>,
+    listen_fds: Option<&str>,
+) -> Result<Option<RawFd>, String> {
+    let (listen_pid, listen_fds) = match (listen_pid, listen_fds) {
+        (Some(listen_pid), Some(listen_fds)) => (listen_pid, listen_fds),
+        _ => return Ok(None),
+    };
+    let pid = listen_pid
+        .parse::<u32>()
+        .map_err(|_| format!("invalid LISTEN_PID: {}", listen_pid))?;
+    if pid != std::process::id() {
+        return Ok(None);
+    }
+    let count = listen_fds
+        .parse::<u32>()
+        .map_err(|_| format!("invalid LISTEN_FDS: {}", listen_fds))?;
+    Ok((count > 0).then_some(SD_LISTEN_FDS_START))
+}
+
+pub fn notify_ready() {
+    notify(&[NotifyState::Ready]);
+
+    let mut usec = 0;
+    if sd_notify::watchdog_enabled(false, &mut usec) {
+        // ping at half the configured interval, as recommended by sd_watchdog_enabled(3)
+        let interval = Duration::from_micros(usec / 2);
+        log::info!("systemd watchdog enabled, interval: {:?}", interval);
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                notify(&[NotifyState::Watchdog]);
+            }
+        });
+    }
+}
+
+pub fn notify_stopping() {
+    notify(&[NotifyState::Stopping]);
+}
+
+fn notify(state: &[NotifyState]) {
+    if let Err(e) = sd_notify::notify(false, state) {
+        log::warn!("sd_notify failed: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::io::IntoRawFd;
+
+    #[test]
+    fn takes_the_given_fd() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let taken = take_listener(Some(listener.into_raw_fd())).unwrap();
+        assert_eq!(taken.local_addr().unwrap(), addr);
+    }
+
+    #[test]
+    fn takes_the_first_activated_socket() {
+        let pid = std::process::id().to_string();
+        assert_eq!(activation_fd(Some(&pid), Some("1")), Ok(Some(3)));
+        assert_eq!(activation_fd(Some(&pid), Some("2")), Ok(Some(3)));
+        assert_eq!(activation_fd(Some(&pid), Some("0")), Ok(None));
+        assert_eq!(activation_fd(None, None), Ok(None));
+        assert!(activation_fd(Some(&pid), Some("x")).is_err());
+        assert!(activation_fd(Some("x"), Some("1")).is_err());
+    }
+
+    #[test]
+    fn ignores_sockets_of_other_processes() {
+        let pid = (std::process::id() + 1).to_string();
+        assert_eq!(activation_fd(Some(&pid), Some("1")), Ok(None));
+        assert_eq!(activation_fd(Some(&pid), None), Ok(None));
+    }
+}
@@ -0,0 +1,147 @@
+use crate::{resolve_path, AppError};
+use axum::extract::{Path, Query};
+use axum::Json;
+use chardetng::EncodingDetector;
+use common::TextChunk;
+use encoding_rs::Encoding;
+use serde::Deserialize;
+use std::io::SeekFrom;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+const DEFAULT_LIMIT: u64 = 256 * 1024;
+const MAX_LIMIT: u64 = 1024 * 1024;
+
+#[derive(Deserialize)]
+pub struct TextParams {
+    offset: Option<u64>,
+    limit: Option<u64>,
+    /// Read the last `limit` bytes of the file instead of starting at `offset`
+    tail: Option<bool>,
+    /// Encoding label returned with an earlier chunk, so that all chunks of a
+    /// file are decoded the same way
+    encoding: Option<String>,
+}
+
+/// Serves a chunk of a text file decoded to UTF-8, at most `limit` bytes
+/// starting at `offset`. Chunks end on a line boundary where possible, so that
+/// the next chunk can be requested from `end` without splitting lines or
+/// characters, which is also how growing log files are followed.
+pub async fn text(
+    Path(path): Path<String>,
+    Query(params): Query<TextParams>,
+) -> Result<Json<TextChunk>, AppError> {
+    let full_path = resolve_path(&path)?;
+    let mut file = tokio::fs::File::open(&full_path)
+        .await
+        .map_err(|_| AppError(format!("file not found: {}", path)))?;
+    let metadata = file
+        .metadata()
+        .await
+        .map_err(|e| AppError(format!("failed to read file: {}", e)))?;
+    if !metadata.is_file() {
+        return Err(AppError(format!("not a file: {}", path)));
+    }
+    let size = metadata.len();
+
+    let mut head = Vec::with_capacity(3);
+    (&mut file)
+        .take(3)
+        .read_to_end(&mut head)
+        .await
+        .map_err(|e| AppError(format!("failed to read file: {}", e)))?;
+    let bom = Encoding::for_bom(&head);
+    let requested = params
+        .encoding
+        .as_deref()
+        .and_then(|label| Encoding::for_label(label.as_bytes()));
+
+    let limit = params.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+    let tail = params.tail.unwrap_or(false);
+    let mut offset = if tail {
+        size.saturating_sub(limit)
+    } else {
+        params.offset.unwrap_or(0).min(size)
+    };
+    // skip the BOM, and keep UTF-16 reads aligned to code units
+    if let Some((encoding, bom_len)) = bom {
+        offset = offset.max(bom_len as u64);
+        if !encoding.is_ascii_compatible() {
+            offset += offset % 2;
+        }
+    }
+
+    file.seek(SeekFrom::Start(offset))
+        .await
+        .map_err(|e| AppError(format!("failed to read file: {}", e)))?;
+    let mut buf = Vec::with_capacity(limit as usize);
+    (&mut file)
+        .take(limit)
+        .read_to_end(&mut buf)
+        .await
+        .map_err(|e| AppError(format!("failed to read file: {}", e)))?;
+
+    let encoding = requested
+        .or_else(|| bom.map(|(encoding, _)| encoding))
+        .unwrap_or_else(|| {
+            // a read from the middle of the file may start mid-character
+            let skip = match buf.iter().position(|b| *b == b'\n') {
+                Some(pos) if offset > 0 => pos + 1,
+                _ => 0,
+            };
+            detect_encoding(&buf[skip..])
+        });
+    if encoding.is_ascii_compatible() && looks_binary(&buf) {
+        return Err(AppError(format!("not a text file: {}", path)));
+    }
+
+    let (start, end) = if encoding.is_ascii_compatible() {
+        // start on a new line when jumping into the middle of the file
+        let start = match buf.iter().position(|b| *b == b'\n') {
+            Some(pos) if tail && offset > 0 => pos + 1,
+            _ => 0,
+        };
+        // a chunk without any line break (a huge line, or the incomplete
+        // last line of the file) is returned as is
+        let end = match buf.iter().rposition(|b| *b == b'\n') {
+            Some(pos) if pos >= start => pos + 1,
+            _ => buf.len(),
+        };
+        (start, end)
+    } else {
+        (0, buf.len() - buf.len() % 2)
+    };
+
+    let (text, _) = encoding.decode_without_bom_handling(&buf[start..end]);
+
+    Ok(Json(TextChunk {
+        path: format!("/{}", path.trim_start_matches('/')),
+        encoding: encoding.name().to_string(),
+        offset: offset + start as u64,
+        end: offset + end as u64,
+        size,
+        text: text.into_owned(),
+    }))
+}
+
+/// Prefers UTF-8 when the bytes are valid UTF-8 (possibly cut off in the
+/// middle of a character at the end), and lets chardetng guess otherwise.
+fn detect_encoding(buf: &[u8]) -> &'static Encoding {
+    match std::str::from_utf8(buf) {
+        Ok(_) => return encoding_rs::UTF_8,
+        Err(e) if e.error_len().is_none() => return encoding_rs::UTF_8,
+        Err(_) => {}
+    }
+    let mut detector = EncodingDetector::new();
+    detector.feed(buf, true);
+    detector.guess(None, true)
+}
+
+/// Any NUL byte, or more than a few control characters other than the usual
+/// whitespace and ANSI escapes in colored logs, suggest a binary file.
+fn looks_binary(buf: &[u8]) -> bool {
+    let controls = buf
+        .iter()
+        .filter(|b| **b < 0x20 && !matches!(**b, b'\t' | b'\n' | b'\r' | 0x0c | 0x1b))
+        .count();
+    buf.contains(&0) || controls * 100 > buf.len()
+}
@@ -0,0 +1,204 @@
+use crate::{resolve_path, AppError};
+use axum::extract::{Path, Query};
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use common::{is_image, media_kind, MediaKind};
+use image::ImageFormat;
+use once_cell::sync::OnceCell;
+use serde::Deserialize;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::io::Cursor;
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::time::{Duration, UNIX_EPOCH};
+use tokio::sync::Semaphore;
+
+const DEFAULT_SIZE: u32 = 256;
+const MAX_SIZE: u32 = 1024;
+
+/// Offset into a video to grab the poster frame from, skipping black intros.
+const POSTER_OFFSET: &str = "5";
+const FFMPEG_TIMEOUT: Duration = Duration::from_secs(20);
+
+struct ThumbnailConfig {
+    cache_dir: PathBuf,
+    cache_size: u64,
+}
+
+static CONFIG: OnceCell<ThumbnailConfig> = OnceCell::new();
+
+/// Decoding and resizing is CPU bound, limit how many run at once.
+static WORKERS: Semaphore = Semaphore::const_new(4);
+
+#[derive(Deserialize)]
+pub struct ThumbnailParams {
+    size: Option<u32>,
+    format: Option<String>,
+}
+
+pub fn init(cache_dir: PathBuf, cache_size: u64) {
+    if let Err(e) = std::fs::create_dir_all(&cache_dir) {
+        log::error!("failed to create thumbnail cache dir {:?}: {}", cache_dir, e);
+    }
+    log::info!(
+        "thumbnail cache: {:?}, size limit: {} bytes",
+        cache_dir,
+        cache_size
+    );
+    CONFIG
+        .set(ThumbnailConfig {
+            cache_dir,
+            cache_size,
+        })
+        .ok();
+
+    tokio::spawn(async {
+        let mut ticker = tokio::time::interval(Duration::from_secs(60));
+        loop {
+            ticker.tick().await;
+            let _ = tokio::task::spawn_blocking(evict_cache).await;
+        }
+    });
+}
+
+/// Serves a resized JPEG (or WebP with `format=webp`) preview of an image, or
+/// of a poster frame for videos when ffmpeg is available, previews are cached
+/// on disk by path, mtime and size of the source file.
+pub async fn thumbnail(
+    Path(path): Path<String>,
+    Query(params): Query<ThumbnailParams>,
+) -> Result<Response, AppError> {
+    let config = CONFIG
+        .get()
+        .ok_or_else(|| AppError("thumbnails are disabled".to_string()))?;
+    let full_path = resolve_path(&path)?;
+    if !full_path.is_file() {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    }
+
+    let size = params.size.unwrap_or(DEFAULT_SIZE).clamp(16, MAX_SIZE);
+    let format = match params.format.as_deref() {
+        Some("webp") => ImageFormat::WebP,
+        _ => ImageFormat::Jpeg,
+    };
+    let content_type = format.to_mime_type();
+
+    let cache_path = config.cache_dir.join(cache_key(&full_path, size, format));
+    if let Ok(bytes) = tokio::fs::read(&cache_path).await {
+        return Ok(([(header::CONTENT_TYPE, content_type)], bytes).into_response());
+    }
+
+    let file_name = full_path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let source = if is_image(&file_name) {
+        tokio::fs::read(&full_path)
+            .await
+            .map_err(|e| AppError(format!("failed to read image: {}", e)))?
+    } else if media_kind(&file_name) == Some(MediaKind::Video) {
+        match poster_frame(&full_path).await {
+            Some(frame) => frame,
+            None => return Ok(StatusCode::NOT_FOUND.into_response()),
+        }
+    } else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
+
+    let _permit = WORKERS.acquire().await.unwrap();
+    let bytes = tokio::task::spawn_blocking(move || resize(&source, size, format))
+        .await
+        .map_err(|e| AppError(format!("failed to create thumbnail: {}", e)))?
+        .map_err(|e| AppError(format!("failed to create thumbnail: {}", e)))?;
+
+    if let Err(e) = tokio::fs::write(&cache_path, &bytes).await {
+        log::warn!("failed to cache thumbnail {:?}: {}", cache_path, e);
+    }
+
+    Ok(([(header::CONTENT_TYPE, content_type)], bytes).into_response())
+}
+
+fn resize(source: &[u8], size: u32, format: ImageFormat) -> image::ImageResult<Vec<u8>> {
+    let img = image::load_from_memory(source)?.thumbnail(size, size);
+    // JPEG has no alpha channel
+    let img = match format {
+        ImageFormat::Jpeg => image::DynamicImage::ImageRgb8(img.to_rgb8()),
+        _ => img,
+    };
+    let mut bytes = Cursor::new(vec![]);
+    img.write_to(&mut bytes, format)?;
+    Ok(bytes.into_inner())
+}
+
+/// Grabs a single JPEG frame from a video with ffmpeg, falling back to the
+/// first frame for videos shorter than the poster offset.
+async fn poster_frame(full_path: &std::path::Path) -> Option<Vec<u8>> {
+    for offset in [POSTER_OFFSET, "0"] {
+        let command = tokio::process::Command::new("ffmpeg")
+            .args(["-nostdin", "-v", "error", "-ss", offset, "-i"])
+            .arg(full_path)
+            .args(["-frames:v", "1", "-f", "image2", "-c:v", "mjpeg", "pipe:1"])
+            .stdin(Stdio::null())
+            .kill_on_drop(true)
+            .output();
+        match tokio::time::timeout(FFMPEG_TIMEOUT, command).await {
+            Ok(Ok(output)) if output.status.success() && !output.stdout.is_empty() => {
+                return Some(output.stdout)
+            }
+            Ok(Err(e)) => {
+                log::debug!("failed to run ffmpeg: {}", e);
+                return None;
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+fn cache_key(full_path: &std::path::Path, size: u32, format: ImageFormat) -> String {
+    let mut hasher = DefaultHasher::new();
+    full_path.hash(&mut hasher);
+    if let Ok(metadata) = full_path.metadata() {
+        metadata.len().hash(&mut hasher);
+        if let Ok(modified) = metadata.modified() {
+            modified
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .hash(&mut hasher);
+        }
+    }
+    let ext = format.extensions_str()[0];
+    format!("{:016x}-{}.{}", hasher.finish(), size, ext)
+}
+
+/// Removes the oldest thumbnails until the cache fits in its size limit.
+fn evict_cache() {
+    let config = match CONFIG.get() {
+        Some(config) => config,
+        None => return,
+    };
+    let entries = match std::fs::read_dir(&config.cache_dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+
+    let mut entries = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let metadata = entry.metadata().ok()?;
+            Some((entry.path(), metadata.len(), metadata.modified().ok()?))
+        })
+        .collect::<Vec<_>>();
+
+    let mut total = entries.iter().map(|e| e.1).sum::<u64>();
+    entries.sort_by_key(|e| e.2);
+    for (path, size, _) in entries {
+        if total <= config.cache_size {
+            break;
+        }
+        if std::fs::remove_file(&path).is_ok() {
+            total -= size;
+        }
+    }
+}
@@ -19,6 +19,9 @@ pub struct DirEntry {
 pub struct DirDesc {
     pub dir_name: String,
     pub descendants: Vec<DirEntry>,
+    /// The directory is inside an archive and can't be modified
+    #[serde(default)]
+    pub in_archive: bool,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -31,3 +34,382 @@ pub enum JsonResponse {
     Succeeded { msg: Option<String> },
     Failed { msg: Option<String> },
 }
+
+/// Server settings the frontend needs to know about, served at `/api/config`.
+#[derive(Serialize, Deserialize, Default, Clone, PartialEq)]
+pub struct ServerConfig {
+    /// `<name>.local` host name the server is advertised as over mDNS, if enabled
+    pub mdns_host: Option<String>,
+    /// URL clients reach the server at, e.g. `https://intranet/files` behind
+    /// a reverse proxy, filled in for every request
+    pub public_url: Option<String>,
+    #[serde(default)]
+    pub upload_limits: UploadLimits,
+}
+
+/// Limits on uploads in bytes, `None` if unlimited. Quotas and free disk
+/// space are only checked by the server.
+#[derive(Serialize, Deserialize, Default, Clone, PartialEq)]
+pub struct UploadLimits {
+    pub max_file_size: Option<u64>,
+    pub max_request_size: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum MediaKind {
+    Audio,
+    Video,
+}
+
+/// Guesses from the file extension whether `file_name` is a media file that
+/// can be played in the browser's player page.
+pub fn media_kind(file_name: &str) -> Option<MediaKind> {
+    let ext = file_name.rsplit_once('.')?.1.to_ascii_lowercase();
+    match ext.as_str() {
+        "mp3" | "m4a" | "aac" | "flac" | "wav" | "ogg" | "oga" | "opus" => Some(MediaKind::Audio),
+        "mp4" | "m4v" | "webm" | "ogv" | "mov" | "mkv" => Some(MediaKind::Video),
+        _ => None,
+    }
+}
+
+/// Guesses from the file extension whether `file_name` is an image browsers
+/// can display, and the server can create thumbnails for.
+pub fn is_image(file_name: &str) -> bool {
+    let ext = match file_name.rsplit_once('.') {
+        Some((_, ext)) => ext.to_ascii_lowercase(),
+        None => return false,
+    };
+    matches!(
+        ext.as_str(),
+        "jpg" | "jpeg" | "png" | "gif" | "webp" | "bmp"
+    )
+}
+
+const TEXT_EXTENSIONS: &[&str] = &[
+    "txt", "log", "md", "markdown", "rst", "csv", "tsv", "json", "yaml", "yml", "toml", "ini",
+    "conf", "cfg", "xml", "css", "js", "ts", "jsx", "tsx", "rs", "go", "c", "h", "cc", "cpp",
+    "hpp", "java", "kt", "swift", "py", "rb", "php", "pl", "lua", "sh", "bash", "zsh", "fish",
+    "sql", "diff", "patch", "srt", "vtt", "lock", "gradle",
+];
+
+/// Guesses from the file extension (or the name for extensionless files like
+/// "Makefile") whether `file_name` is a text file to open in the text viewer.
+pub fn is_text(file_name: &str) -> bool {
+    match file_name.rsplit_once('.') {
+        Some((_, ext)) => TEXT_EXTENSIONS.contains(&ext.to_ascii_lowercase().as_str()),
+        None => matches!(
+            file_name,
+            "Makefile" | "Dockerfile" | "LICENSE" | "README" | "CHANGELOG"
+        ),
+    }
+}
+
+/// Whether `file_name` is a markdown file, shown rendered in the markdown viewer.
+pub fn is_markdown(file_name: &str) -> bool {
+    match file_name.rsplit_once('.') {
+        Some((_, ext)) => ext.eq_ignore_ascii_case("md") || ext.eq_ignore_ascii_case("markdown"),
+        None => false,
+    }
+}
+
+/// Prefix of the markdown viewer route, `/_markdown/dir/notes.md` shows
+/// `/dir/notes.md`. The server rewrites links between markdown files to it.
+pub const MARKDOWN_ROUTE: &str = "/_markdown";
+
+/// A markdown file rendered to sanitized HTML, served at `/api/markdown`.
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct MarkdownDoc {
+    pub path: String,
+    pub html: String,
+}
+
+/// Whether `file_name` is a document that can be previewed in the document
+/// viewer: PDFs, word processor documents, spreadsheets and CSV files.
+pub fn is_document(file_name: &str) -> bool {
+    let ext = match file_name.rsplit_once('.') {
+        Some((_, ext)) => ext.to_ascii_lowercase(),
+        None => return false,
+    };
+    matches!(
+        ext.as_str(),
+        "pdf" | "docx" | "odt" | "xlsx" | "ods" | "csv" | "tsv"
+    )
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct Sheet {
+    pub name: String,
+    pub rows: Vec<Vec<String>>,
+    /// Rows past the preview limit were left out
+    pub truncated: bool,
+}
+
+/// Preview of a document, served at `/api/document`.
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub enum DocumentPreview {
+    /// Shown by the browser's own PDF viewer
+    Pdf {
+        url: String,
+    },
+    /// Text and tables of a word processor document as simple HTML
+    Html {
+        html: String,
+    },
+    Sheets {
+        sheets: Vec<Sheet>,
+    },
+}
+
+/// Checksum of a file, served at `/api/hash`.
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct FileChecksum {
+    pub path: String,
+    /// "sha256" or "blake3"
+    pub algo: String,
+    /// Lowercase hex
+    pub checksum: String,
+    pub size: u64,
+}
+
+/// Request to create a share link at `/api/shares`.
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct CreateShare {
+    /// The shared file or directory
+    pub path: String,
+    /// The link never expires if not set
+    pub expires_in_hours: Option<u64>,
+    pub max_downloads: Option<u64>,
+    pub password: Option<String>,
+}
+
+/// A share link, served at `/s/<token>` to anyone who has it.
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct ShareInfo {
+    pub token: String,
+    pub path: String,
+    pub created: String,
+    pub expires: Option<String>,
+    pub max_downloads: Option<u64>,
+    pub downloads: u64,
+    pub has_password: bool,
+}
+
+/// Request to create a drop box link at `/api/dropboxes`.
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct CreateDropBox {
+    /// The directory files are uploaded into
+    pub path: String,
+    /// The link never expires if not set
+    pub expires_in_hours: Option<u64>,
+    /// In bytes
+    pub max_file_size: Option<u64>,
+    /// Total size of the files that may be uploaded through the link
+    pub max_total_size: Option<u64>,
+}
+
+/// An upload-only link to a directory, served at `/u/<token>` to anyone who
+/// has it. Uploaders can't see the files in the directory.
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct DropBoxInfo {
+    pub token: String,
+    pub path: String,
+    pub created: String,
+    pub expires: Option<String>,
+    pub max_file_size: Option<u64>,
+    pub max_total_size: Option<u64>,
+    /// Bytes uploaded through the link so far
+    pub uploaded: u64,
+}
+
+/// A line of the audit log, served at `/api/audit`.
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct AuditEntry {
+    /// RFC 3339
+    pub time: String,
+    pub ip: Option<String>,
+    /// As reported by an authenticating reverse proxy
+    pub user: Option<String>,
+    pub operation: String,
+    pub path: String,
+    pub size: Option<u64>,
+    pub success: bool,
+    pub error: Option<String>,
+}
+
+/// Whether `file_name` is an archive that can be browsed like a directory
+/// and extracted on the server.
+pub fn is_archive(file_name: &str) -> bool {
+    let name = file_name.to_ascii_lowercase();
+    [".zip", ".tar", ".tar.gz", ".tgz"]
+        .iter()
+        .any(|ext| name.ends_with(ext))
+}
+
+/// Progress of extracting an archive, served at `/api/extract`.
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct ExtractStatus {
+    /// Path of the directory the archive is extracted into
+    pub target: String,
+    pub running: bool,
+    pub error: Option<String>,
+    /// Number of entries extracted so far
+    pub entries: u64,
+}
+
+/// A text file opened in the editor, served at `/api/edit` along with its
+/// version in the `ETag` header.
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct EditableFile {
+    pub path: String,
+    pub content: String,
+    /// The ETag to send back in `If-Match` when saving. The header can't be
+    /// relied on for that, it is weakened when the response is compressed.
+    pub etag: String,
+}
+
+/// A slice of a text file decoded to UTF-8, served at `/api/text` for the
+/// text viewer. Offsets are byte offsets into the file on disk.
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct TextChunk {
+    pub path: String,
+    /// Name of the detected (or requested) encoding, e.g. "UTF-8"
+    pub encoding: String,
+    pub offset: u64,
+    /// Offset to request the following chunk from
+    pub end: u64,
+    /// Size of the whole file at the time of the request
+    pub size: u64,
+    pub text: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct SubtitleTrack {
+    pub label: String,
+    pub lang: Option<String>,
+    /// WebVTT url of the track, SRT files are converted by the server
+    pub url: String,
+}
+
+/// Everything the player page needs to know about a media file.
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct MediaDesc {
+    /// path of the file relative to the served directory, starting with '/'
+    pub path: String,
+    pub kind: MediaKind,
+    /// duration in seconds, if ffprobe is available
+    pub duration: Option<f64>,
+    /// one line per stream, e.g. "video: h264 1920x1080"
+    pub codecs: Vec<String>,
+    pub subtitles: Vec<SubtitleTrack>,
+    /// HLS playlist to play instead of the file itself, for videos browsers
+    /// can't play directly (e.g. MKV/HEVC), segments are produced by ffmpeg
+    pub hls_url: Option<String>,
+    /// previous/next media file in the same directory
+    pub prev: Option<String>,
+    pub next: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum StreamKind {
+    Video,
+    Audio,
+    Subtitle,
+    Other,
+}
+
+/// A stream of a media file, as reported by ffprobe.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct StreamInfo {
+    pub index: u32,
+    pub kind: StreamKind,
+    pub codec_name: Option<String>,
+    pub codec_long_name: Option<String>,
+    pub profile: Option<String>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub frame_rate: Option<f64>,
+    pub channels: Option<u32>,
+    pub sample_rate: Option<u32>,
+    pub bit_rate: Option<u64>,
+    pub language: Option<String>,
+    pub title: Option<String>,
+}
+
+impl StreamInfo {
+    /// One line summary, e.g. "video: h264 (High) 1920x1080 23.98fps" or
+    /// "audio: aac 2ch 48000Hz (eng)".
+    pub fn summary(&self) -> String {
+        let kind = match self.kind {
+            StreamKind::Video => "video",
+            StreamKind::Audio => "audio",
+            StreamKind::Subtitle => "subtitle",
+            StreamKind::Other => "other",
+        };
+        let mut line = format!(
+            "{}: {}",
+            kind,
+            self.codec_name.as_deref().unwrap_or("unknown")
+        );
+        if let Some(profile) = &self.profile {
+            line.push_str(&format!(" ({})", profile));
+        }
+        if let (Some(w), Some(h)) = (self.width, self.height) {
+            line.push_str(&format!(" {}x{}", w, h));
+        }
+        if let Some(fps) = self.frame_rate {
+            line.push_str(&format!(" {:.2}fps", fps));
+        }
+        if let Some(channels) = self.channels {
+            line.push_str(&format!(" {}ch", channels));
+        }
+        if let Some(sample_rate) = self.sample_rate {
+            line.push_str(&format!(" {}Hz", sample_rate));
+        }
+        if let Some(lang) = &self.language {
+            line.push_str(&format!(" ({})", lang));
+        }
+        line
+    }
+}
+
+/// Container level information of a media file, as reported by ffprobe.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct MediaInfo {
+    pub format_name: String,
+    pub format_long_name: Option<String>,
+    /// duration in seconds
+    pub duration: Option<f64>,
+    /// bits per second
+    pub bit_rate: Option<u64>,
+    pub size: Option<u64>,
+    pub streams: Vec<StreamInfo>,
+}
+
+impl MediaInfo {
+    pub fn first_stream(&self, kind: StreamKind) -> Option<&StreamInfo> {
+        self.streams.iter().find(|stream| stream.kind == kind)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct GpsPosition {
+    pub latitude: f64,
+    pub longitude: f64,
+    /// meters above sea level
+    pub altitude: Option<f64>,
+}
+
+/// EXIF metadata of an image, values are formatted for display.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug, Default)]
+pub struct ExifInfo {
+    pub camera: Option<String>,
+    pub lens: Option<String>,
+    pub date_taken: Option<String>,
+    pub exposure_time: Option<String>,
+    pub f_number: Option<String>,
+    pub iso: Option<String>,
+    pub focal_length: Option<String>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub gps: Option<GpsPosition>,
+}
@@ -0,0 +1,109 @@
+use crate::{format_bytes, with_base_path};
+use common::{AuditEntry, JsonResponse};
+use dioxus::{events::FormEvent, prelude::*};
+use gloo_net::http::Request;
+
+/// Searches the audit log by user, path and time.
+pub fn AuditPage(cx: Scope) -> Element {
+    let route = dioxus_router::use_route(&cx);
+    let base = route.url().clone();
+    let home = with_base_path("/");
+    // query string of the current search
+    let query = use_state(&cx, String::new);
+    let fut = use_future(&cx, (query.get(),), |(query,)| fetch_entries(query));
+
+    let onsubmit = move |ev: FormEvent| {
+        let mut url = base.clone();
+        url.query_pairs_mut().clear().extend_pairs(
+            ["user", "path", "since", "until"]
+                .iter()
+                .filter_map(|name| Some((*name, ev.values.get(*name)?.trim())))
+                .filter(|(_, value)| !value.is_empty()),
+        );
+        query.set(url.query().unwrap_or_default().to_string());
+    };
+
+    let body = match fut.value() {
+        Some(Ok(entries)) if entries.is_empty() => rsx!(p {
+            class: "raw_message",
+            "No matching entries."
+        }),
+        Some(Ok(entries)) => rsx!(table {
+            thead {
+                tr {
+                    td { "Time" }
+                    td { "IP" }
+                    td { "User" }
+                    td { "Operation" }
+                    td { "Path" }
+                    td { "Size" }
+                    td { "Result" }
+                }
+            }
+            entries.iter().enumerate().map(|(index, entry)| {
+                let time = entry.time.get(..19).unwrap_or(&entry.time).replace('T', " ");
+                let ip = entry.ip.clone().unwrap_or_default();
+                let user = entry.user.clone().unwrap_or_default();
+                let size = entry.size.map(format_bytes).unwrap_or_default();
+                let (style, result) = match &entry.error {
+                    Some(err) => ("color: red;", format!("Failed: {}", err)),
+                    None => ("", "OK".to_string()),
+                };
+                rsx!(tr {
+                    key: "{index}",
+                    td { "{time}" }
+                    td { "{ip}" }
+                    td { "{user}" }
+                    td { "{entry.operation}" }
+                    td { "{entry.path}" }
+                    td { "{size}" }
+                    td { style: "{style}", "{result}" }
+                })
+            })
+        }),
+        Some(Err(err)) => rsx!(p {
+            class: "raw_message",
+            "Error: {err}"
+        }),
+        None => rsx!(p {
+            class: "raw_message",
+            "Loading..."
+        }),
+    };
+
+    cx.render(rsx! {
+        div {
+            class: "title",
+            a { href: "{home}", "◄ " }
+            "Audit Log"
+        }
+        form {
+            class: "audit_filter",
+            prevent_default: "onsubmit",
+            onsubmit: onsubmit,
+            label { "User " input { r#type: "text", name: "user" } }
+            label { "Path " input { r#type: "text", name: "path" } }
+            label { "Since " input { r#type: "datetime-local", name: "since" } }
+            label { "Until " input { r#type: "datetime-local", name: "until" } }
+            button { "Search" }
+        }
+        body
+    })
+}
+
+async fn fetch_entries(query: String) -> Result<Vec<AuditEntry>, String> {
+    let text = Request::get(&with_base_path(&format!("/api/audit?{}", query)))
+        .send()
+        .await
+        .map_err(|e| e.to_string())?
+        .text()
+        .await
+        .map_err(|e| e.to_string())?;
+    match serde_json::from_str::<Vec<AuditEntry>>(&text) {
+        Ok(entries) => Ok(entries),
+        Err(_) => match serde_json::from_str::<JsonResponse>(&text) {
+            Ok(JsonResponse::Failed { msg: Some(msg) }) => Err(msg),
+            _ => Err("Not Available!".to_string()),
+        },
+    }
+}
@@ -0,0 +1,131 @@
+use crate::with_base_path;
+use common::{DocumentPreview, JsonResponse};
+use dioxus::prelude::*;
+use gloo_net::http::Request;
+
+/// Previews a document without downloading it: PDFs in the browser's own
+/// viewer, word processor documents as HTML and spreadsheets as tables, one
+/// sheet at a time.
+#[inline_props]
+pub fn DocumentViewer(cx: Scope, path: String) -> Element {
+    let fut = use_future(&cx, (path,), |(path,)| fetch_preview(path));
+    let sheet_index = use_state(&cx, || 0_usize);
+
+    let dir = match path.rfind('/') {
+        Some(idx) if idx > 0 => &path[..idx],
+        _ => "/",
+    };
+    let dir = with_base_path(dir);
+    let download = with_base_path(&format!("/api/static{}", path));
+
+    let sheet_count = match fut.value() {
+        Some(Ok(DocumentPreview::Sheets { sheets })) => sheets.len(),
+        _ => 0,
+    };
+    let index = (*sheet_index.get()).min(sheet_count.saturating_sub(1));
+    let truncated_note = match fut.value() {
+        Some(Ok(DocumentPreview::Sheets { sheets })) => sheets
+            .get(index)
+            .filter(|sheet| sheet.truncated)
+            .map(|sheet| format!("Only the first {} rows are shown.", sheet.rows.len())),
+        _ => None,
+    };
+
+    let body = match fut.value() {
+        Some(Ok(DocumentPreview::Pdf { url })) => {
+            let url = with_base_path(url);
+            rsx!(iframe {
+                class: "document_pdf",
+                src: "{url}",
+            })
+        }
+        Some(Ok(DocumentPreview::Html { html })) => rsx!(div {
+            class: "document",
+            dangerous_inner_html: "{html}",
+        }),
+        Some(Ok(DocumentPreview::Sheets { sheets })) => {
+            let tabs = (sheets.len() > 1).then(|| {
+                rsx!(div {
+                    class: "sheet_tabs",
+                    sheets.iter().enumerate().map(|(i, sheet)| {
+                        let class = if i == index { "sheet_tab selected" } else { "sheet_tab" };
+                        rsx!(button {
+                            key: "{i}",
+                            class: "{class}",
+                            prevent_default: "onclick",
+                            r#type: "button",
+                            onclick: move |_| sheet_index.set(i),
+                            "{sheet.name}"
+                        })
+                    })
+                })
+            });
+
+            match sheets.get(index) {
+                Some(sheet) => rsx!(
+                    tabs
+                    div {
+                        class: "sheet",
+                        table {
+                            sheet.rows.iter().enumerate().map(|(i, row)| rsx!(
+                                tr {
+                                    key: "{i}",
+                                    row.iter().enumerate().map(|(j, cell)| rsx!(
+                                        td { key: "{j}", "{cell}" }
+                                    ))
+                                }
+                            ))
+                        }
+                    }
+                    truncated_note.as_ref().map(|note| rsx!(
+                        p { class: "text_summary", "{note}" }
+                    ))
+                ),
+                None => rsx!(p {
+                    class: "raw_message",
+                    "Empty document"
+                }),
+            }
+        }
+        Some(Err(err)) => rsx!(p {
+            class: "raw_message",
+            "Error: {err}"
+        }),
+        None => rsx!(p {
+            class: "raw_message",
+            "Loading..."
+        }),
+    };
+
+    cx.render(rsx! {
+        div {
+            class: "title",
+            a { href: "{dir}", "◄ " }
+            "{path}"
+        }
+
+        div {
+            class: "text_toolbar",
+            a { href: "{download}", "Download" }
+        }
+
+        body
+    })
+}
+
+async fn fetch_preview(path: String) -> Result<DocumentPreview, String> {
+    let text = Request::get(&with_base_path(&format!("/api/document{}", path)))
+        .send()
+        .await
+        .map_err(|e| e.to_string())?
+        .text()
+        .await
+        .map_err(|e| e.to_string())?;
+    match serde_json::from_str::<DocumentPreview>(&text) {
+        Ok(preview) => Ok(preview),
+        Err(_) => match serde_json::from_str::<JsonResponse>(&text) {
+            Ok(JsonResponse::Failed { msg: Some(msg) }) => Err(msg),
+            _ => Err("Not Available!".to_string()),
+        },
+    }
+}
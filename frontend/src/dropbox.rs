@@ -0,0 +1,239 @@
+use crate::{format_bytes, qr_svg, with_base_path};
+use common::{CreateDropBox, DropBoxInfo, JsonResponse};
+use dioxus::{events::FormEvent, prelude::*};
+use gloo_net::http::Request;
+
+/// Default lifetime of a new drop box link.
+const DEFAULT_EXPIRES_IN_HOURS: &str = "168";
+
+/// Creates a drop box link that lets anyone who has it upload files into
+/// the directory at `path` without seeing its contents.
+#[inline_props]
+pub fn DropBoxDialog<'a>(
+    cx: Scope,
+    url_base: String,
+    path: String,
+    dialog_state: &'a UseState<bool>,
+) -> Element {
+    let created = use_state(&cx, || None as Option<DropBoxInfo>);
+    let error = use_state(&cx, || None as Option<String>);
+
+    let onsubmit = move |ev: FormEvent| {
+        let number = |name: &str| {
+            ev.values
+                .get(name)
+                .and_then(|value| value.trim().parse::<u64>().ok())
+                .filter(|value| *value > 0)
+        };
+        let req = CreateDropBox {
+            path: path.clone(),
+            expires_in_hours: number("expires_in_hours"),
+            max_file_size: number("max_file_size").map(|size| size * 1024 * 1024),
+            max_total_size: number("max_total_size").map(|size| size * 1024 * 1024),
+        };
+        let (created, error) = (created.clone(), error.clone());
+        cx.spawn(async move {
+            match create_drop_box(&req).await {
+                Ok(info) => created.set(Some(info)),
+                Err(err) => error.set(Some(err)),
+            }
+        });
+    };
+
+    let body = match created.get() {
+        Some(info) => {
+            let link = format!("{}/u/{}", url_base, info.token);
+            let svg = qr_svg(&link);
+            rsx!(
+                input {
+                    class: "share_link",
+                    r#type: "text",
+                    readonly: "true",
+                    value: "{link}",
+                }
+                div { class: "share_qrcode", dangerous_inner_html: "{svg}" }
+            )
+        }
+        None => rsx!(
+            form {
+                prevent_default: "onsubmit",
+                onsubmit: onsubmit,
+                label {
+                    "Expires in hours (empty for never) "
+                    input {
+                        r#type: "number",
+                        name: "expires_in_hours",
+                        min: "1",
+                        value: "{DEFAULT_EXPIRES_IN_HOURS}",
+                    }
+                }
+                label {
+                    "Max file size in MB (empty for unlimited) "
+                    input {
+                        r#type: "number",
+                        name: "max_file_size",
+                        min: "1",
+                    }
+                }
+                label {
+                    "Max total size in MB (empty for unlimited) "
+                    input {
+                        r#type: "number",
+                        name: "max_total_size",
+                        min: "1",
+                    }
+                }
+                button { "Create Link" }
+            }
+            error.get().as_ref().map(|err| rsx!(
+                div { class: "card_error", "{err}" }
+            ))
+        ),
+    };
+
+    cx.render(rsx! {
+        div {
+            class: "share_dialog",
+            div { class: "share_title", "Drop box for {path}" }
+            body
+            button {
+                prevent_default: "onclick",
+                r#type: "button",
+                onclick: move |_| dialog_state.set(false),
+                "Close"
+            }
+        }
+    })
+}
+
+/// Lists the drop box links that haven't expired, with buttons to revoke
+/// them.
+#[inline_props]
+pub fn DropBoxList(cx: Scope, url_base: String) -> Element {
+    let fut = use_future(&cx, (), |_| fetch_drop_boxes());
+    let error = use_state(&cx, || None as Option<String>);
+
+    let update_state = use_state(&cx, || false);
+    if *update_state.get() {
+        update_state.set(false);
+        fut.restart();
+    }
+
+    let revoke = move |token: String| {
+        let (error, update_state) = (error.clone(), update_state.clone());
+        cx.spawn(async move {
+            let result = Request::delete(&with_base_path(&format!("/api/dropboxes/{}", token)))
+                .send()
+                .await
+                .map_err(|e| e.to_string());
+            let result = match result {
+                Ok(resp) => match resp.json::<JsonResponse>().await {
+                    Ok(JsonResponse::Failed { msg }) => Err(msg.unwrap_or_default()),
+                    _ => Ok(()),
+                },
+                Err(err) => Err(err),
+            };
+            match result {
+                Ok(()) => update_state.set(true),
+                Err(err) => error.set(Some(err)),
+            }
+        });
+    };
+
+    let body = match fut.value() {
+        Some(Ok(drop_boxes)) if drop_boxes.is_empty() => rsx!(p {
+            class: "raw_message",
+            "There are no drop box links."
+        }),
+        Some(Ok(drop_boxes)) => rsx!(table {
+            thead {
+                tr {
+                    td { "Directory" }
+                    td { "Link" }
+                    td { "Created" }
+                    td { "Expires" }
+                    td { "Max File Size" }
+                    td { "Uploaded" }
+                    td { "Operation" }
+                }
+            }
+            drop_boxes.iter().map(|drop_box| {
+                let link = format!("{}/u/{}", url_base, drop_box.token);
+                let dir = with_base_path(&drop_box.path);
+                let expires = drop_box.expires.clone().unwrap_or_else(|| "Never".to_string());
+                let max_file_size = drop_box
+                    .max_file_size
+                    .map(format_bytes)
+                    .unwrap_or_else(|| "Unlimited".to_string());
+                let uploaded = match drop_box.max_total_size {
+                    Some(max) => format!("{}/{}", format_bytes(drop_box.uploaded), format_bytes(max)),
+                    None => format_bytes(drop_box.uploaded),
+                };
+                let token = drop_box.token.clone();
+                rsx!(tr {
+                    key: "{drop_box.token}",
+                    td { a { href: "{dir}", "{drop_box.path}" } }
+                    td { a { href: "{link}", "{link}" } }
+                    td { "{drop_box.created}" }
+                    td { "{expires}" }
+                    td { "{max_file_size}" }
+                    td { "{uploaded}" }
+                    td {
+                        button {
+                            style: "color: red;",
+                            prevent_default: "onclick",
+                            r#type: "button",
+                            onclick: move |_| revoke(token.clone()),
+                            "Revoke"
+                        }
+                    }
+                })
+            })
+        }),
+        Some(Err(err)) => rsx!(p {
+            class: "raw_message",
+            "Error: {err}"
+        }),
+        None => rsx!(p {
+            class: "raw_message",
+            "Loading..."
+        }),
+    };
+
+    cx.render(rsx! {
+        div { class: "title", "Drop Box Links" }
+        error.get().as_ref().map(|err| rsx!(
+            div { class: "card_error", "{err}" }
+        ))
+        body
+    })
+}
+
+async fn create_drop_box(req: &CreateDropBox) -> Result<DropBoxInfo, String> {
+    let text = Request::post(&with_base_path("/api/dropboxes"))
+        .json(req)
+        .map_err(|e| e.to_string())?
+        .send()
+        .await
+        .map_err(|e| e.to_string())?
+        .text()
+        .await
+        .map_err(|e| e.to_string())?;
+    match serde_json::from_str::<DropBoxInfo>(&text) {
+        Ok(info) => Ok(info),
+        Err(_) => match serde_json::from_str::<JsonResponse>(&text) {
+            Ok(JsonResponse::Failed { msg: Some(msg) }) => Err(msg),
+            _ => Err("Not Available!".to_string()),
+        },
+    }
+}
+
+async fn fetch_drop_boxes() -> Result<Vec<DropBoxInfo>, String> {
+    Request::get(&with_base_path("/api/dropboxes"))
+        .send()
+        .await
+        .map_err(|e| e.to_string())?
+        .json::<Vec<DropBoxInfo>>()
+        .await
+        .map_err(|e| e.to_string())
+}
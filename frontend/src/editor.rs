@@ -0,0 +1,181 @@
+use crate::{with_base_path, TEXT_ROUTE};
+use common::{EditableFile, JsonResponse};
+use dioxus::{events::FormEvent, prelude::*};
+use gloo_net::http::Request;
+
+/// Edits a text file in a textarea. Saves send the ETag the file was loaded
+/// with, if someone else saved the file in the meantime the save is rejected
+/// and the user can reload their version or overwrite it.
+#[inline_props]
+pub fn Editor(cx: Scope, path: String) -> Element {
+    let content = use_ref(&cx, String::new);
+    let etag = use_ref(&cx, || None as Option<String>);
+    let dirty = use_state(&cx, || false);
+    let conflict = use_state(&cx, || false);
+    let status = use_state(&cx, || None as Option<String>);
+
+    let (load_content, load_etag) = (content.clone(), etag.clone());
+    let fut = use_future(&cx, (path,), move |(path,)| async move {
+        let resp = Request::get(&with_base_path(&format!("/api/edit{}", path)))
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+        let text = resp.text().await.map_err(|e| e.to_string())?;
+        match serde_json::from_str::<EditableFile>(&text) {
+            Ok(file) => {
+                load_content.set(file.content);
+                load_etag.set(Some(file.etag));
+                Ok(())
+            }
+            Err(_) => match serde_json::from_str::<JsonResponse>(&text) {
+                Ok(JsonResponse::Failed { msg: Some(msg) }) => Err(msg),
+                _ => Err("Not Available!".to_string()),
+            },
+        }
+    });
+
+    let save = move |overwrite: bool| {
+        let if_match = if overwrite {
+            Some("*".to_string())
+        } else {
+            etag.read().clone()
+        };
+        let if_match = match if_match {
+            Some(if_match) => if_match,
+            None => return,
+        };
+        let body = content.read().clone();
+        let url = with_base_path(&format!("/api/edit{}", path));
+        let (etag, dirty, conflict, status) = (
+            etag.clone(),
+            dirty.clone(),
+            conflict.clone(),
+            status.clone(),
+        );
+        status.set(Some("Saving...".to_string()));
+        cx.spawn(async move {
+            let resp = Request::put(url.as_str())
+                .header("If-Match", &if_match)
+                .body(body)
+                .send()
+                .await;
+            let resp = match resp {
+                Ok(resp) => resp,
+                Err(err) => {
+                    status.set(Some(format!("Failed to save: {}", err)));
+                    return;
+                }
+            };
+            let tag = resp.headers().get("etag");
+            let code = resp.status();
+            let msg = match resp.json::<JsonResponse>().await {
+                Ok(JsonResponse::Failed { msg: Some(msg) }) => Some(msg),
+                Ok(JsonResponse::Failed { msg: None }) => Some("failed".to_string()),
+                _ => None,
+            };
+            match (code, msg) {
+                (412, msg) => {
+                    conflict.set(true);
+                    status.set(msg);
+                }
+                (200, None) => {
+                    etag.set(tag);
+                    dirty.set(false);
+                    conflict.set(false);
+                    status.set(Some("Saved".to_string()));
+                }
+                (_, msg) => {
+                    let msg = msg.unwrap_or_else(|| format!("HTTP {}", code));
+                    status.set(Some(format!("Failed to save: {}", msg)));
+                }
+            }
+        });
+    };
+
+    let reload = move |_| {
+        dirty.set(false);
+        conflict.set(false);
+        status.set(None);
+        fut.restart();
+    };
+
+    let oninput = move |evt: FormEvent| {
+        *content.write_silent() = evt.value.clone();
+        if !*dirty.get() {
+            dirty.set(true);
+            status.set(None);
+        }
+    };
+
+    let dir = match path.rfind('/') {
+        Some(idx) if idx > 0 => &path[..idx],
+        _ => "/",
+    };
+    let dir = with_base_path(dir);
+    let view = with_base_path(&format!("{}{}", TEXT_ROUTE, path));
+    let title = if *dirty.get() {
+        format!("{} *", path)
+    } else {
+        path.to_string()
+    };
+    let save_disabled = !*dirty.get();
+    let text = content.read().clone();
+
+    cx.render(match fut.value() {
+        Some(Ok(())) => rsx!(
+            div {
+                class: "title",
+                a { href: "{dir}", "◄ " }
+                "{title}"
+            }
+
+            div {
+                class: "text_toolbar",
+                a { href: "{view}", "View" }
+                button {
+                    prevent_default: "onclick",
+                    r#type: "button",
+                    disabled: "{save_disabled}",
+                    onclick: move |_| save(false),
+                    "Save"
+                }
+                conflict.get().then(|| rsx!(
+                    button {
+                        prevent_default: "onclick",
+                        r#type: "button",
+                        onclick: move |_| save(true),
+                        "Overwrite"
+                    }
+                    button {
+                        prevent_default: "onclick",
+                        r#type: "button",
+                        onclick: reload,
+                        "Discard Changes and Reload"
+                    }
+                ))
+                status.get().as_ref().map(|status| rsx!(
+                    span { class: "text_summary", "{status}" }
+                ))
+            }
+
+            textarea {
+                class: "editor",
+                spellcheck: "false",
+                value: "{text}",
+                oninput: oninput,
+            }
+        ),
+        Some(Err(err)) => rsx!(
+            p {
+                class: "raw_message",
+                "Error: {err}"
+            }
+        ),
+        None => rsx!(
+            p {
+                class: "raw_message",
+                "Loading..."
+            }
+        ),
+    })
+}
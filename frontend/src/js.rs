@@ -0,0 +1,319 @@
+use wasm_bindgen::prelude::*;
+
+#[wasm_bindgen(inline_js = r##"
+export function base_path() {
+    const meta = document.querySelector('meta[name="base-path"]');
+    return meta ? meta.content : '';
+}
+
+export function attach_hls(id, url) {
+    const attach = (retries) => {
+        const video = document.getElementById(id);
+        if (!video) {
+            if (retries > 0) requestAnimationFrame(() => attach(retries - 1));
+            return;
+        }
+        if (video.canPlayType('application/vnd.apple.mpegurl')) {
+            video.src = url;
+        } else if (window.Hls && window.Hls.isSupported()) {
+            if (video.hls) video.hls.destroy();
+            video.hls = new window.Hls();
+            video.hls.loadSource(url);
+            video.hls.attachMedia(video);
+        }
+    };
+    attach(60);
+}
+
+export function init_lightbox(id) {
+    const init = (retries) => {
+        const el = document.getElementById(id);
+        if (!el) {
+            if (retries > 0) requestAnimationFrame(() => init(retries - 1));
+            return;
+        }
+        el.focus();
+        let startX = null;
+        el.addEventListener('touchstart', (e) => {
+            startX = e.touches[0].clientX;
+        }, { passive: true });
+        el.addEventListener('touchend', (e) => {
+            if (startX === null) return;
+            const dx = e.changedTouches[0].clientX - startX;
+            startX = null;
+            if (Math.abs(dx) > 50) {
+                const key = dx > 0 ? 'ArrowLeft' : 'ArrowRight';
+                el.dispatchEvent(new KeyboardEvent('keydown', { key, bubbles: true }));
+            }
+        });
+    };
+    init(60);
+}
+
+export function render_text(id, text, language, anchors, scroll_to_end) {
+    const render = (retries) => {
+        const code = document.getElementById(id);
+        const gutter = document.getElementById(id + '_lines');
+        if (!code || !gutter) {
+            if (retries > 0) requestAnimationFrame(() => render(retries - 1));
+            return;
+        }
+        let lines = text.split('\n').length;
+        if (text.endsWith('\n')) lines -= 1;
+        const numbers = [];
+        for (let n = 1; n <= lines; n++) {
+            numbers.push(anchors ? `<a id="L${n}" href="#L${n}">${n}</a>` : `${n}`);
+        }
+        gutter.innerHTML = numbers.join('\n');
+
+        code.className = '';
+        code.textContent = text;
+        delete code.dataset.highlighted;
+        if (language && window.hljs && text.length <= 512 * 1024) {
+            code.classList.add('language-' + language);
+            window.hljs.highlightElement(code);
+        }
+
+        if (scroll_to_end) {
+            window.scrollTo(0, document.body.scrollHeight);
+        } else if (anchors && location.hash.startsWith('#L')) {
+            const line = document.getElementById(location.hash.slice(1));
+            if (line) line.scrollIntoView({ block: 'center' });
+        }
+    };
+    render(60);
+}
+const MAX_PARALLEL_UPLOADS = 2;
+const uploader = { items: [], nextId: 1, onUpdate: null, listening: false, maxSize: 0 };
+
+function notify_uploads(finished) {
+    if (!uploader.onUpdate) return;
+    const items = uploader.items.map(({ id, name, size, loaded, status, error }) =>
+        ({ id, name, size, loaded, status, error }));
+    try {
+        uploader.onUpdate(JSON.stringify({ items, finished }));
+    } catch (e) {
+        // the listing that registered the callback is gone
+        uploader.onUpdate = null;
+    }
+}
+
+function enqueue_uploads(dir, files) {
+    for (const { file, path } of files) {
+        const item = {
+            id: uploader.nextId++, dir, file, name: path, size: file.size,
+            loaded: 0, status: 'Queued', error: null, xhr: null,
+        };
+        // rejected here rather than by the server after sending it all
+        if (uploader.maxSize > 0 && file.size > uploader.maxSize) {
+            const limit = (uploader.maxSize / (1024 * 1024)).toFixed(2);
+            item.status = 'Failed';
+            item.error = `larger than the upload limit of ${limit}M`;
+            item.file = null;
+        }
+        uploader.items.push(item);
+    }
+    pump_uploads();
+    notify_uploads(false);
+}
+
+function pump_uploads() {
+    let running = uploader.items.filter((item) => item.status === 'Uploading').length;
+    for (const item of uploader.items) {
+        if (running >= MAX_PARALLEL_UPLOADS) break;
+        if (item.status !== 'Queued') continue;
+        start_upload(item);
+        running++;
+    }
+}
+
+function start_upload(item) {
+    // the path relative to the directory goes first, the server creates
+    // the intermediate directories of folder uploads
+    const form = new FormData();
+    form.append('path', item.name);
+    form.append('filename', item.file, item.file.name);
+
+    const xhr = new XMLHttpRequest();
+    item.xhr = xhr;
+    item.status = 'Uploading';
+    xhr.upload.onprogress = (e) => {
+        item.loaded = e.loaded;
+        notify_uploads(false);
+    };
+    xhr.onload = () => {
+        let resp = null;
+        try { resp = JSON.parse(xhr.responseText); } catch (e) {}
+        if (xhr.status === 200 && resp && resp.Succeeded) {
+            item.status = 'Done';
+            item.loaded = item.size;
+        } else {
+            item.status = 'Failed';
+            item.error = (resp && resp.Failed && resp.Failed.msg) || `HTTP ${xhr.status}`;
+        }
+        finish_upload(item);
+    };
+    xhr.onerror = () => {
+        item.status = 'Failed';
+        item.error = 'network error';
+        finish_upload(item);
+    };
+    xhr.onabort = () => {
+        item.status = 'Cancelled';
+        finish_upload(item);
+    };
+    const dir = item.dir.split('/').map(encodeURIComponent).join('/');
+    xhr.open('POST', base_path() + '/api/upload' + (dir.startsWith('/') ? dir : '/' + dir));
+    xhr.send(form);
+}
+
+function finish_upload(item) {
+    item.xhr = null;
+    item.file = null;
+    pump_uploads();
+    const finished = !uploader.items.some((item) =>
+        item.status === 'Queued' || item.status === 'Uploading');
+    notify_uploads(finished);
+}
+
+function walk_entry(entry, prefix) {
+    const path = prefix + entry.name;
+    if (entry.isFile) {
+        return new Promise((resolve) =>
+            entry.file((file) => resolve([{ file, path }]), () => resolve([])));
+    }
+    return new Promise((resolve) => {
+        const reader = entry.createReader();
+        const children = [];
+        // readEntries returns the entries in batches until an empty one
+        const read = () => reader.readEntries(async (batch) => {
+            if (batch.length > 0) {
+                children.push(...batch);
+                read();
+                return;
+            }
+            const files = await Promise.all(children.map((child) => walk_entry(child, path + '/')));
+            resolve(files.flat());
+        }, () => resolve([]));
+        read();
+    });
+}
+
+function upload_zone(target) {
+    const zone = target && target.closest ? target.closest('[data-upload-dir]') : null;
+    return zone && zone.dataset.uploadDir ? zone : null;
+}
+
+export function init_uploader(on_update) {
+    uploader.onUpdate = on_update;
+    if (!uploader.listening) {
+        uploader.listening = true;
+        document.addEventListener('dragover', (e) => {
+            const zone = upload_zone(e.target);
+            if (!zone || !e.dataTransfer.types.includes('Files')) return;
+            e.preventDefault();
+            zone.classList.add('drag_over');
+        });
+        document.addEventListener('dragleave', (e) => {
+            const zone = upload_zone(e.target);
+            if (zone && !zone.contains(e.relatedTarget)) zone.classList.remove('drag_over');
+        });
+        document.addEventListener('drop', async (e) => {
+            const zone = upload_zone(e.target);
+            if (!zone) return;
+            e.preventDefault();
+            zone.classList.remove('drag_over');
+            // the items are only accessible until the handler yields
+            const entries = [...e.dataTransfer.items]
+                .map((item) => item.webkitGetAsEntry ? item.webkitGetAsEntry() : null);
+            const plain = [...e.dataTransfer.files].map((file) => ({ file, path: file.name }));
+            const files = entries.length > 0 && entries.every(Boolean)
+                ? (await Promise.all(entries.map((entry) => walk_entry(entry, '')))).flat()
+                : plain;
+            enqueue_uploads(zone.dataset.uploadDir, files);
+        });
+        document.addEventListener('change', (e) => {
+            const input = e.target;
+            if (!input.classList || !input.classList.contains('upload_input')) return;
+            const zone = upload_zone(input);
+            if (!zone) return;
+            const files = [...input.files]
+                .map((file) => ({ file, path: file.webkitRelativePath || file.name }));
+            input.value = '';
+            enqueue_uploads(zone.dataset.uploadDir, files);
+        });
+    }
+    notify_uploads(false);
+}
+
+export function set_upload_limit(max_size) {
+    uploader.maxSize = max_size;
+}
+
+export function cancel_upload(id) {
+    const item = uploader.items.find((item) => item.id === id);
+    if (!item) return;
+    if (item.xhr) {
+        item.xhr.abort();
+    } else if (item.status === 'Queued') {
+        item.status = 'Cancelled';
+        finish_upload(item);
+    }
+}
+
+export function cancel_uploads() {
+    for (const item of uploader.items) {
+        if (item.status === 'Queued') item.status = 'Cancelled';
+    }
+    for (const item of uploader.items) {
+        if (item.xhr) item.xhr.abort();
+    }
+    notify_uploads(true);
+}
+
+export function clear_uploads() {
+    uploader.items = uploader.items.filter((item) =>
+        item.status === 'Queued' || item.status === 'Uploading');
+    notify_uploads(false);
+}
+"##)]
+extern "C" {
+    /// The path the server is reachable under, which it puts into index.html,
+    /// e.g. "/files" behind a reverse proxy, empty if it is served at the root.
+    pub fn base_path() -> String;
+
+    /// Plays an HLS playlist in the video element with the given id, natively
+    /// where supported (Safari) and through hls.js otherwise. The element may
+    /// not be rendered yet, it is looked up again on the next frames.
+    pub fn attach_hls(id: &str, url: &str);
+
+    /// Focuses the lightbox overlay so that it receives key presses, and
+    /// translates horizontal swipes into ArrowLeft/ArrowRight key presses.
+    pub fn init_lightbox(id: &str);
+
+    /// Shows `text` in the code element with the given id, with line numbers
+    /// in the `<id>_lines` element, highlighted with highlight.js unless the
+    /// text is too large for it. With `anchors` the line numbers link to
+    /// `#L<n>`, and the line in the URL is scrolled into view.
+    pub fn render_text(id: &str, text: &str, language: &str, anchors: bool, scroll_to_end: bool);
+
+    /// Handles uploads: files dropped onto or picked in a file input
+    /// (`.upload_input`) inside an element with a non-empty `data-upload-dir`
+    /// are uploaded into that directory, folders keep their relative paths.
+    /// `on_update` receives the state of all uploads as an `UploadUpdate`
+    /// JSON, it replaces the callback of an earlier call.
+    pub fn init_uploader(on_update: &Closure<dyn FnMut(String)>);
+
+    /// Files larger than `max_size` bytes are rejected without uploading
+    /// them, 0 for no limit.
+    pub fn set_upload_limit(max_size: f64);
+
+    /// Cancels a queued or running upload.
+    pub fn cancel_upload(id: u32);
+
+    /// Cancels all queued and running uploads.
+    pub fn cancel_uploads();
+
+    /// Removes finished, failed and cancelled uploads from the list.
+    pub fn clear_uploads();
+}
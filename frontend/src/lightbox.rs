@@ -0,0 +1,206 @@
+use crate::js::init_lightbox;
+use crate::with_base_path;
+use common::{is_image, DirDesc, ExifInfo, FileType};
+use dioxus::{events::KeyboardEvent, prelude::*};
+use gloo_net::http::Request;
+use gloo_timers::future::TimeoutFuture;
+
+const SLIDESHOW_INTERVAL_MS: u32 = 3000;
+const ZOOM_STEP: f64 = 1.5;
+const MAX_ZOOM: f64 = 8.0;
+
+/// Overlay showing the images of the current directory one at a time, with
+/// keyboard (←/→, +/-, space, esc) and swipe navigation, zoom, a slideshow
+/// mode and the EXIF metadata of the image.
+pub fn Lightbox<'a>(cx: Scope<'a, LightboxProps<'a>>) -> Element {
+    let state = cx.props.lightbox_state;
+    let images = cx
+        .props
+        .dir_desc
+        .descendants
+        .iter()
+        .filter(|entry| entry.file_type != FileType::Directory && is_image(&entry.file_name))
+        .map(|entry| entry.file_name.as_str())
+        .collect::<Vec<_>>();
+    let count = images.len().max(1);
+    let index = images
+        .iter()
+        .position(|name| *name == cx.props.file_name)
+        .unwrap_or(0);
+    let prev = images
+        .get((index + count - 1) % count)
+        .unwrap_or(&"")
+        .to_string();
+    let next = images.get((index + 1) % count).unwrap_or(&"").to_string();
+
+    let zoom = use_state(&cx, || 1.0_f64);
+    let playing = use_state(&cx, || false);
+    let show_exif = use_state(&cx, || false);
+
+    use_future(&cx, (), |_| async { init_lightbox("lightbox") });
+
+    let cur_path = cx.props.cur_path.to_string();
+    let exif = use_future(&cx, (&cx.props.file_name,), move |(name,)| async move {
+        let resp = Request::get(&with_base_path(&format!("/api/exif{}/{}", cur_path, name)))
+            .send()
+            .await
+            .ok()?;
+        resp.json::<ExifInfo>().await.ok()
+    });
+
+    let slideshow_state = state.clone();
+    let slideshow_next = next.clone();
+    use_future(
+        &cx,
+        (playing.get(), &cx.props.file_name),
+        move |(playing, _)| async move {
+            if playing {
+                TimeoutFuture::new(SLIDESHOW_INTERVAL_MS).await;
+                slideshow_state.set(Some(slideshow_next));
+            }
+        },
+    );
+
+    let (key_prev, key_next) = (prev.clone(), next.clone());
+    let onkeydown = move |evt: KeyboardEvent| match evt.key.as_str() {
+        "ArrowLeft" => {
+            zoom.set(1.0);
+            state.set(Some(key_prev.clone()));
+        }
+        "ArrowRight" => {
+            zoom.set(1.0);
+            state.set(Some(key_next.clone()));
+        }
+        "+" | "=" => zoom.set((*zoom.get() * ZOOM_STEP).min(MAX_ZOOM)),
+        "-" => zoom.set((*zoom.get() / ZOOM_STEP).max(1.0)),
+        " " => playing.set(!*playing.get()),
+        "Escape" => state.set(None),
+        _ => {}
+    };
+
+    let src = with_base_path(&format!("/api/static{}/{}", cx.props.cur_path, cx.props.file_name));
+    let position = format!("{} / {}", index + 1, images.len());
+    let zoom_value = *zoom.get();
+    let play_label = if *playing.get() { "Pause" } else { "Slideshow" };
+    let exif_panel = match exif.value() {
+        Some(Some(info)) => rsx!(ExifTable { info: info }),
+        Some(None) => rsx!("No EXIF metadata"),
+        None => rsx!("Loading..."),
+    };
+
+    cx.render(rsx!(div {
+        id: "lightbox",
+        class: "lightbox",
+        tabindex: "0",
+        onkeydown: onkeydown,
+
+        div {
+            class: "lightbox_toolbar",
+            span { class: "lightbox_title", "{position}  {cx.props.file_name}" }
+            button {
+                onclick: move |_| zoom.set((*zoom.get() * ZOOM_STEP).min(MAX_ZOOM)),
+                "Zoom +"
+            }
+            button {
+                onclick: move |_| zoom.set((*zoom.get() / ZOOM_STEP).max(1.0)),
+                "Zoom -"
+            }
+            button {
+                onclick: move |_| playing.set(!*playing.get()),
+                "{play_label}"
+            }
+            button {
+                onclick: move |_| show_exif.set(!*show_exif.get()),
+                "Info"
+            }
+            button {
+                onclick: move |_| state.set(None),
+                "Close"
+            }
+        }
+
+        div {
+            class: "lightbox_stage",
+            button {
+                class: "lightbox_nav",
+                onclick: move |_| {
+                    zoom.set(1.0);
+                    state.set(Some(prev.clone()));
+                },
+                "◄"
+            }
+            div {
+                class: "lightbox_viewport",
+                img {
+                    class: "lightbox_image",
+                    src: "{src}",
+                    style: "transform: scale({zoom_value});",
+                    ondblclick: move |_| zoom.set(if *zoom.get() > 1.0 { 1.0 } else { 2.0 }),
+                }
+            }
+            button {
+                class: "lightbox_nav",
+                onclick: move |_| {
+                    zoom.set(1.0);
+                    state.set(Some(next.clone()));
+                },
+                "►"
+            }
+        }
+
+        show_exif.get().then(|| rsx!(div {
+            class: "lightbox_exif",
+            exif_panel
+        }))
+    }))
+}
+
+#[inline_props]
+fn ExifTable<'a>(cx: Scope<'a>, info: &'a ExifInfo) -> Element {
+    let dimensions = match (info.width, info.height) {
+        (Some(w), Some(h)) => Some(format!("{}x{}", w, h)),
+        _ => None,
+    };
+    let rows = [
+        ("Camera", info.camera.clone()),
+        ("Lens", info.lens.clone()),
+        ("Date Taken", info.date_taken.clone()),
+        ("Exposure", info.exposure_time.clone()),
+        ("Aperture", info.f_number.clone()),
+        ("ISO", info.iso.clone()),
+        ("Focal Length", info.focal_length.clone()),
+        ("Dimensions", dimensions),
+    ];
+
+    cx.render(rsx!(table {
+        rows.into_iter().filter_map(|(name, value)| value.map(|value| rsx!(tr {
+            key: "{name}",
+            td { "{name}" }
+            td { "{value}" }
+        })))
+
+        info.gps.as_ref().map(|gps| {
+            let lat = gps.latitude;
+            let lon = gps.longitude;
+            let coordinates = format!("{:.6}, {:.6}", lat, lon);
+            rsx!(tr {
+                td { "GPS" }
+                td {
+                    a {
+                        href: "https://www.openstreetmap.org/?mlat={lat}&mlon={lon}#map=15/{lat}/{lon}",
+                        target: "_blank",
+                        "{coordinates}"
+                    }
+                }
+            })
+        })
+    }))
+}
+
+#[derive(Props)]
+pub struct LightboxProps<'a> {
+    cur_path: &'a str,
+    dir_desc: &'a DirDesc,
+    file_name: String,
+    lightbox_state: &'a UseState<Option<String>>,
+}
@@ -1,15 +1,79 @@
 #![allow(non_snake_case)]
 
-use common::{DirDesc, DirEntry, JsonRequest};
-use dioxus::{events::FormEvent, prelude::*};
+use common::{
+    is_archive, is_document, is_image, is_markdown, is_text, media_kind, DirDesc, DirEntry, ExtractStatus, FileChecksum, JsonRequest, JsonResponse,
+    MediaDesc, MediaInfo, MediaKind, ServerConfig, MARKDOWN_ROUTE,
+};
+use dioxus::{
+    events::{FormEvent, MediaEvent},
+    prelude::*,
+};
 use dioxus_router::{use_router, Route, Router};
 use fast_qr::{
     convert::svg::{Shape, SvgBuilder},
     QRBuilder, Version, ECL,
 };
 use gloo_net::http::Request;
+use gloo_timers::future::TimeoutFuture;
 use log::{error, info};
 use reqwest::Url;
+use std::rc::Rc;
+
+mod audit;
+mod document;
+mod dropbox;
+mod editor;
+mod js;
+mod lightbox;
+mod markdown;
+mod share;
+mod text_viewer;
+mod uploads;
+
+use audit::AuditPage;
+use document::DocumentViewer;
+use dropbox::DropBoxDialog;
+use editor::Editor;
+use js::{attach_hls, base_path};
+use lightbox::Lightbox;
+use markdown::{MarkdownViewer, Readme};
+use share::{ShareDialog, SharesPage};
+use text_viewer::TextViewer;
+use uploads::Uploads;
+
+/// Prefix of the player page route, `/_media/dir/movie.mp4` plays `/dir/movie.mp4`
+const MEDIA_ROUTE: &str = "/_media";
+/// Prefix of the document viewer route, `/_document/dir/report.pdf` previews
+/// `/dir/report.pdf`
+const DOCUMENT_ROUTE: &str = "/_document";
+/// Prefix of the text viewer route, `/_text/dir/app.log` shows `/dir/app.log`
+const TEXT_ROUTE: &str = "/_text";
+/// Prefix of the editor route, `/_edit/dir/app.conf` edits `/dir/app.conf`
+const EDIT_ROUTE: &str = "/_edit";
+/// Route of the page listing the share links
+const SHARES_ROUTE: &str = "/_shares";
+/// Route of the page searching the audit log
+const AUDIT_ROUTE: &str = "/_audit";
+
+thread_local! {
+    static BASE_PATH: String = base_path();
+}
+
+/// Prefixes a path of the server, e.g. "/api/listing/dir" or a route of the
+/// frontend, with the path the server is reachable under.
+fn with_base_path(path: &str) -> String {
+    BASE_PATH.with(|base_path| format!("{}{}", base_path, path))
+}
+
+/// The path of `url` without the base path, "/dir" of "https://intranet/files/dir".
+fn app_path(url: &Url) -> &str {
+    let path = url.path();
+    let len = BASE_PATH.with(|base_path| match path.strip_prefix(base_path.as_str()) {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') => base_path.len(),
+        _ => 0,
+    });
+    &path[len..]
+}
 
 fn main() {
     dioxus::web::launch(app);
@@ -20,42 +84,118 @@ fn app(cx: Scope) -> Element {
 
     cx.render(rsx! {
         Router {
-            Route { to: "", Listing {} }
+            Route { to: "", Page {} }
         }
     })
 }
 
+/// Every path is a directory listing, except for the viewer pages which are
+/// distinguished by their path prefix.
+fn Page(cx: Scope) -> Element {
+    let route = dioxus_router::use_route(&cx);
+    let path = app_path(route.url());
+
+    cx.render(if let Some(file_path) = path.strip_prefix(MEDIA_ROUTE) {
+        rsx!(MediaPlayer {
+            path: file_path.to_string()
+        })
+    } else if let Some(file_path) = path.strip_prefix(DOCUMENT_ROUTE) {
+        rsx!(DocumentViewer {
+            path: file_path.to_string()
+        })
+    } else if let Some(file_path) = path.strip_prefix(MARKDOWN_ROUTE) {
+        rsx!(MarkdownViewer {
+            path: file_path.to_string()
+        })
+    } else if let Some(file_path) = path.strip_prefix(EDIT_ROUTE) {
+        rsx!(Editor {
+            path: file_path.to_string()
+        })
+    } else if let Some(file_path) = path.strip_prefix(TEXT_ROUTE) {
+        rsx!(TextViewer {
+            path: file_path.to_string()
+        })
+    } else if path == SHARES_ROUTE {
+        rsx!(SharesPage {})
+    } else if path == AUDIT_ROUTE {
+        rsx!(AuditPage {})
+    } else {
+        rsx!(Listing {})
+    })
+}
+
+/// Fetches the server's configuration, falling back to the defaults if it
+/// can't be retrieved.
+async fn fetch_server_config() -> ServerConfig {
+    match Request::get(&with_base_path("/api/config")).send().await {
+        Ok(resp) => resp.json::<ServerConfig>().await.unwrap_or_default(),
+        Err(_) => ServerConfig::default(),
+    }
+}
+
 fn Listing(cx: Scope) -> Element {
     let route = dioxus_router::use_route(&cx);
     let url = route.url();
-    let url_base = get_url_base(url);
 
-    let mut path = url.path().to_string();
+    let config_fut = use_future(&cx, (), |_| fetch_server_config());
+    let url_base = get_url_base(url, config_fut.value());
+
+    let mut path = app_path(url).to_string();
     if path.ends_with('/') {
         path = path.trim_end_matches('/').to_string();
     }
 
-    let fut = use_future(&cx, (), |_| async move {
-        Request::get(format!("/api/listing{}", path).as_str())
-            .send()
-            .await
-            .unwrap()
-            .json::<DirDesc>()
-            .await
-    });
+    // the listing shown and its ETag, refreshing an unchanged directory keeps
+    // it as it is instead of downloading, parsing and rendering it again
+    let listing = use_state(&cx, || None as Option<Result<Rc<DirDesc>, String>>);
+    let listing_etag = use_ref(&cx, || None as Option<String>);
+    let load = {
+        let (listing, listing_etag) = (listing.clone(), listing_etag.clone());
+        move || {
+            let (listing, listing_etag) = (listing.clone(), listing_etag.clone());
+            let path = path.clone();
+            cx.spawn(async move {
+                let mut request = Request::get(&with_base_path(&format!("/api/listing{}", path)));
+                let etag = listing_etag.read().clone();
+                if let Some(etag) = &etag {
+                    request = request.header("If-None-Match", etag);
+                }
+                let resp = match request.send().await {
+                    Ok(resp) => resp,
+                    Err(err) => return listing.set(Some(Err(err.to_string()))),
+                };
+                if resp.status() == 304 {
+                    return;
+                }
+
+                let etag = resp.headers().get("etag");
+                match resp.json::<DirDesc>().await {
+                    Ok(dir_desc) => {
+                        *listing_etag.write_silent() = etag;
+                        listing.set(Some(Ok(Rc::new(dir_desc))));
+                    }
+                    Err(err) => {
+                        *listing_etag.write_silent() = None;
+                        listing.set(Some(Err(err.to_string())));
+                    }
+                }
+            });
+        }
+    };
+    cx.use_hook(|_| load());
 
     let update_state = use_state(&cx, || false);
     if *update_state.get() {
         update_state.set(false);
-        fut.restart();
+        load();
     }
 
     let router_state = use_router(&cx);
     let create_dir_state = use_state(&cx, || None as Option<String>);
     if let Some(dir_path) = create_dir_state.get() {
         create_dir_state.set(None);
-        fut.restart();
-        router_state.replace_route(dir_path.as_str(), None, None);
+        load();
+        router_state.replace_route(&with_base_path(dir_path), None, None);
 
         return cx.render(rsx! {
             Listing {}
@@ -63,24 +203,74 @@ fn Listing(cx: Scope) -> Element {
     }
 
     let info_state = use_state(&cx, || None as Option<String>);
+    let grid_view = use_state(&cx, load_grid_view);
+    let lightbox_state = use_state(&cx, || None as Option<String>);
+    let cur_path = app_path(url).trim_end_matches('/');
+    let shares_href = with_base_path(SHARES_ROUTE);
+    let audit_href = with_base_path(AUDIT_ROUTE);
+    let toggle_label = if *grid_view.get() {
+        "Table View"
+    } else {
+        "Grid View"
+    };
 
-    cx.render(match fut.value() {
-        Some(Ok(dir_desc)) => rsx!(
-            div {
-                class: "title",
-                a { href: "{url_base}", "{url_base}" }
-                "{dir_desc.dir_name}"
-            }
+    cx.render(match listing.get() {
+        Some(Ok(dir_desc)) => {
+            let dir_desc: &DirDesc = dir_desc;
+            // files dropped anywhere on the listing are uploaded into it
+            let upload_dir = if dir_desc.in_archive { "" } else { dir_desc.dir_name.as_str() };
+            rsx!(div {
+                class: "drop_zone",
+                "data-upload-dir": "{upload_dir}",
 
-            CreateDirectory {
-                parent_dir: dir_desc.dir_name.clone(),
-                create_dir_state: create_dir_state.clone(),
-            }
+                div {
+                    class: "title",
+                    a { href: "{url_base}", "{url_base}" }
+                    "{dir_desc.dir_name}"
+                }
 
-            ListingTable{ dir_desc: dir_desc, cur_url: &url, update_state: update_state, info_state: info_state },
+                (!dir_desc.in_archive).then(|| rsx!(CreateDirectory {
+                    url_base: url_base.clone(),
+                    parent_dir: dir_desc.dir_name.clone(),
+                    create_dir_state: create_dir_state.clone(),
+                }))
 
-            InfoDialog { info_state: info_state }
-        ),
+                Uploads { server_config: config_fut.value(), update_state: update_state }
+
+                div {
+                    class: "view_toggle",
+                    a { href: "{shares_href}", "Share Links" }
+                    a { href: "{audit_href}", "Audit Log" }
+                    button {
+                        prevent_default: "onclick",
+                        r#type: "button",
+                        onclick: move |_| {
+                            let grid = !*grid_view.get();
+                            save_grid_view(grid);
+                            grid_view.set(grid);
+                        },
+                        "{toggle_label}"
+                    }
+                }
+
+                if *grid_view.get() {
+                    rsx!(ListingGrid{ dir_desc: dir_desc, cur_url: &url, server_config: config_fut.value(), update_state: update_state, info_state: info_state, lightbox_state: lightbox_state })
+                } else {
+                    rsx!(ListingTable{ dir_desc: dir_desc, cur_url: &url, server_config: config_fut.value(), update_state: update_state, info_state: info_state, lightbox_state: lightbox_state })
+                }
+
+                Readme { cur_path: cur_path, dir_desc: dir_desc }
+
+                lightbox_state.get().as_ref().map(|file_name| rsx!(Lightbox {
+                    cur_path: cur_path,
+                    dir_desc: dir_desc,
+                    file_name: file_name.clone(),
+                    lightbox_state: lightbox_state,
+                }))
+
+                InfoDialog { info_state: info_state }
+            })
+        }
         Some(Err(err)) => rsx!(
             p {
                 class: "raw_message",
@@ -99,6 +289,7 @@ fn Listing(cx: Scope) -> Element {
 #[inline_props]
 fn CreateDirectory(
     cx: Scope,
+    url_base: String,
     parent_dir: String,
     create_dir_state: UseState<Option<String>>,
 ) -> Element {
@@ -116,7 +307,7 @@ fn CreateDirectory(
                     dir_name: dir_name.clone(),
                 };
 
-                let resp = Request::post(format!("/api/listing{}", parent_dir).as_str())
+                let resp = Request::post(&with_base_path(&format!("/api/listing{}", parent_dir)))
                     .json(&json_req)
                     .unwrap()
                     .send()
@@ -125,11 +316,7 @@ fn CreateDirectory(
                 match resp {
                     Ok(resp) => {
                         info!("created directory: {:?}", resp);
-                        create_dir_state.set(Some(if parent_dir == "/" {
-                            dir_name
-                        } else {
-                            format!("{}/{}", parent_dir, dir_name)
-                        }));
+                        create_dir_state.set(Some(format!("{}/{}", parent_dir.trim_end_matches('/'), dir_name)));
                     }
                     Err(err) => {
                         info!("failed to create directory: {}", err);
@@ -139,11 +326,46 @@ fn CreateDirectory(
         }
     };
 
-    let _handle_upload_files = move |ev: FormEvent| {
-        info!("upload: {:?}", ev.values);
-    };
+    let drop_box_state = use_state(&cx, || false);
 
-    let action = format!("/api/upload{}", parent_dir);
+    let router = use_router(&cx);
+    let create_file_error = use_state(&cx, || None as Option<String>);
+    let handle_create_file = move |ev: FormEvent| {
+        if let Some(file_name) = ev.values.get("file_name") {
+            let file_name = file_name.trim().to_string();
+            if file_name.is_empty() {
+                return;
+            }
+
+            let file_path = format!("{}/{}", parent_dir.trim_end_matches('/'), file_name);
+            let create_file_error = create_file_error.to_owned();
+            let router = router.clone();
+            cx.spawn(async move {
+                // If-None-Match: * only creates the file if it doesn't exist yet
+                let resp = Request::put(&with_base_path(&format!("/api/edit{}", file_path)))
+                    .header("If-None-Match", "*")
+                    .send()
+                    .await;
+
+                match resp {
+                    Ok(resp) if resp.ok() => {
+                        info!("created file: {}", file_path);
+                        router.push_route(&with_base_path(&format!("{}{}", EDIT_ROUTE, file_path)), None, None);
+                    }
+                    Ok(resp) => {
+                        let msg = match resp.json::<JsonResponse>().await {
+                            Ok(JsonResponse::Failed { msg: Some(msg) }) => msg,
+                            _ => format!("HTTP {}", resp.status()),
+                        };
+                        create_file_error.set(Some(msg));
+                    }
+                    Err(err) => {
+                        create_file_error.set(Some(err.to_string()));
+                    }
+                }
+            });
+        }
+    };
 
     cx.render(rsx! {
         div {
@@ -167,30 +389,65 @@ fn CreateDirectory(
 
             div {
                 class: "card",
-                div { "Select files to upload to current directory" }
-
+                div { "Create a new file under current directory and edit it" }
                 form {
-                    action: "{action}",
                     prevent_default: "onsubmit",
-                    // onsubmit: handle_upload_files,
+                    onsubmit: handle_create_file,
                     method: "post",
-                    enctype: "multipart/form-data",
                     input {
+                        r#type: "text",
+                        name: "file_name"
+                    }
+                    button {
+                        "Create File"
+                    }
+                }
+                create_file_error.get().as_ref().map(|err| rsx!(
+                    div { class: "card_error", "{err}" }
+                ))
+            }
+
+            div {
+                class: "card",
+                div { "Select files or a folder to upload to current directory, or drop them onto the page" }
+
+                // picked files are uploaded by the uploader in js.rs
+                div {
+                    "Files: "
+                    input {
+                        class: "upload_input",
                         r#type: "file",
-                        name: "filename",
                         multiple: "true",
                     }
-                    button {
-                        "Upload"
+                }
+                div {
+                    "Folder: "
+                    input {
+                        class: "upload_input",
+                        r#type: "file",
+                        "webkitdirectory": "true",
                     }
                 }
+                button {
+                    prevent_default: "onclick",
+                    r#type: "button",
+                    style: "width: auto",
+                    onclick: move |_| drop_box_state.set(true),
+                    "Create Drop Box Link"
+                }
             }
         }
+
+        (*drop_box_state.get()).then(|| rsx!(DropBoxDialog {
+            url_base: url_base.clone(),
+            path: parent_dir.clone(),
+            dialog_state: drop_box_state,
+        }))
     })
 }
 
 fn ListingTable<'a>(cx: Scope<'a, DirDescProps<'a>>) -> Element {
-    let mut cur_path = cx.props.cur_url.path();
+    let mut cur_path = app_path(cx.props.cur_url);
     cur_path = cur_path.trim_end_matches(|c| c == '/');
     let mut parent = "/";
     if let Some(idx) = cur_path.rfind('/') {
@@ -198,14 +455,18 @@ fn ListingTable<'a>(cx: Scope<'a, DirDescProps<'a>>) -> Element {
             parent = &cur_path[..idx];
         }
     }
+    let parent = with_base_path(parent);
 
-    let qrcode_state: &UseState<Option<String>> = use_state(&cx, || None);
-    let qrcode = match qrcode_state.get() {
-        Some(url) => Some(rsx!(QRCode { data: url.as_str() })),
-        _ => None,
-    };
-
-    let url_base = use_state(&cx, || get_url_base(cx.props.cur_url));
+    let share_state: &UseState<Option<String>> = use_state(&cx, || None);
+    let url_base = get_url_base(cx.props.cur_url, cx.props.server_config);
+    let share_dialog = share_state.get().as_ref().map(|path| {
+        rsx!(ShareDialog {
+            key: "{path}",
+            url_base: url_base.clone(),
+            path: path.clone(),
+            share_state: share_state,
+        })
+    });
 
     cx.render(rsx! {
 
@@ -250,48 +511,44 @@ fn ListingTable<'a>(cx: Scope<'a, DirDescProps<'a>>) -> Element {
                 TableRow {
                     index: index + 1,
                     key: "{cur_path}/{entry.file_name}",
-                    url_base: url_base.as_str(),
                     entry: entry,
                     cur_path: cur_path,
                     update_state: cx.props.update_state,
                     info_state: cx.props.info_state,
-                    qrcode_state: qrcode_state,
+                    share_state: share_state,
+                    lightbox_state: cx.props.lightbox_state,
+                    in_archive: cx.props.dir_desc.in_archive,
                 }))
 
         }
 
-        qrcode,
+        share_dialog,
     })
 }
 
 fn TableRow<'a>(cx: Scope<'a, DirEntryProps<'a>>) -> Element {
-    let url_base = if cx.props.url_base.ends_with('/') {
-        cx.props.url_base.trim_end_matches('/')
-    } else {
-        cx.props.url_base
-    };
-
     let entry = cx.props.entry;
-    let api_link = if entry.file_type == common::FileType::Directory {
-        format!("{}/{}", cx.props.cur_path, entry.file_name)
-    } else {
-        format!("/api/static{}/{}", cx.props.cur_path, entry.file_name)
-    };
-
-    let url = format!("{}/{}", url_base, api_link.trim_start_matches('/'));
+    let in_archive = cx.props.in_archive;
+    let icon = entry_icon(entry);
+    let href = entry_href(cx.props.cur_path, entry, in_archive);
+    let is_file = entry.file_type == common::FileType::File;
     let formatted_bytes = format_bytes(entry.file_size);
     cx.render(rsx! {
         tr {
             rsx!(th {
                 "{cx.props.index}. "
-                a {
-                    href: "{api_link}",
-                    if entry.file_type == common::FileType::Directory {
-                        rsx!("📁 ")
-                    } else {
-                        rsx!("📝 ")
-                    }
-                    "{entry.file_name}"
+                if opens_in_lightbox(entry, in_archive) {
+                    rsx!(a {
+                        href: "{href}",
+                        prevent_default: "onclick",
+                        onclick: move |_| cx.props.lightbox_state.set(Some(entry.file_name.clone())),
+                        "{icon} {entry.file_name}"
+                    })
+                } else {
+                    rsx!(a {
+                        href: "{href}",
+                        "{icon} {entry.file_name}"
+                    })
                 }
             })
 
@@ -299,13 +556,13 @@ fn TableRow<'a>(cx: Scope<'a, DirEntryProps<'a>>) -> Element {
             td { "{entry.last_accessed}" }
                 td {
 
-                    (entry.file_type == common::FileType::File).then(|| rsx!(
+                    (is_file && !in_archive).then(|| rsx!(
                         button {
                             prevent_default: "onclick",
                             r#type: "button",
                             style: "margin-right: 8px",
                             onclick: move |_| {
-                                let path = format!("/api/ffprobe{}/{}", cx.props.cur_path, entry.file_name);
+                                let path = with_base_path(&format!("/api/ffprobe{}/{}", cx.props.cur_path, entry.file_name));
                                 let info_state = cx.props.info_state.clone();
                                 cx.spawn(async move {
                                     let resp = Request::get(path.as_str())
@@ -314,14 +571,15 @@ fn TableRow<'a>(cx: Scope<'a, DirEntryProps<'a>>) -> Element {
 
                                     match resp {
                                         Ok(resp) => {
-                                            let text = resp.text().await.unwrap_or("".to_string());
-                                            if text.is_empty() || text == "\"{\\n\\n}\\n\"" {
-                                                info_state.set(Some("Not Available!".to_string()));
-                                            } else {
-                                                let json_resp: serde_json::Value = serde_json::from_str(text.as_str()).unwrap_or(serde_json::Value::default());
-                                                let json_str = json_resp.to_string().replace("\\n", "\n").replace("\\", "");
-                                                info_state.set(Some(json_str));
-                                            }
+                                            let text = resp.text().await.unwrap_or_default();
+                                            let info = match serde_json::from_str::<MediaInfo>(&text) {
+                                                Ok(media_info) => format_media_info(&media_info),
+                                                Err(_) => match serde_json::from_str::<JsonResponse>(&text) {
+                                                    Ok(JsonResponse::Failed { msg: Some(msg) }) => msg,
+                                                    _ => "Not Available!".to_string(),
+                                                },
+                                            };
+                                            info_state.set(Some(info));
                                         }
                                         Err(err) => {
                                             error!("failed: {}", err);
@@ -333,23 +591,74 @@ fn TableRow<'a>(cx: Scope<'a, DirEntryProps<'a>>) -> Element {
                         }
                     )),
 
-                    button {
+                    (is_file && !in_archive).then(|| rsx!(
+                        button {
+                            prevent_default: "onclick",
+                            r#type: "button",
+                            style: "margin-right: 8px",
+                            onclick: move |_| {
+                                let path = with_base_path(&format!("/api/hash{}/{}?algo=sha256", cx.props.cur_path, entry.file_name));
+                                let info_state = cx.props.info_state.clone();
+                                info_state.set(Some(format!("Computing the checksum of {}...", entry.file_name)));
+                                cx.spawn(async move {
+                                    let info = match Request::get(path.as_str()).send().await {
+                                        Ok(resp) => {
+                                            let text = resp.text().await.unwrap_or_default();
+                                            match serde_json::from_str::<FileChecksum>(&text) {
+                                                Ok(checksum) => format!("SHA-256 of {}:\n{}", checksum.path, checksum.checksum),
+                                                Err(_) => match serde_json::from_str::<JsonResponse>(&text) {
+                                                    Ok(JsonResponse::Failed { msg: Some(msg) }) => msg,
+                                                    _ => "Not Available!".to_string(),
+                                                },
+                                            }
+                                        }
+                                        Err(err) => err.to_string(),
+                                    };
+                                    info_state.set(Some(info));
+                                });
+                            },
+                            "Checksum",
+                        }
+                    )),
+
+                    (is_file && !in_archive && is_archive(&entry.file_name)).then(|| rsx!(
+                        button {
+                            prevent_default: "onclick",
+                            r#type: "button",
+                            style: "margin-right: 8px",
+                            onclick: move |_| {
+                                let path = format!("{}/{}", cx.props.cur_path, entry.file_name);
+                                let info_state = cx.props.info_state.clone();
+                                let update_state = cx.props.update_state.clone();
+                                cx.spawn(async move {
+                                    let msg = extract_archive(&path, &info_state)
+                                        .await
+                                        .unwrap_or_else(|err| format!("Failed to extract {}: {}", path, err));
+                                    info_state.set(Some(msg));
+                                    update_state.set(true);
+                                });
+                            },
+                            "Extract Here",
+                        }
+                    )),
+
+                    (!in_archive).then(|| rsx!(button {
                         prevent_default: "onclick",
                         r#type: "button",
                         style: "margin-right: 8px",
-                        onclick: move |_| cx.props.qrcode_state.set(Some(url.clone())),
-                        onmouseout: move |_| {
-                            cx.props.qrcode_state.set(None)
+                        onclick: move |_| {
+                            let path = format!("{}/{}", cx.props.cur_path, entry.file_name);
+                            cx.props.share_state.set(Some(path));
                         },
-                        "QRCode"
-                    }
+                        "Share"
+                    })),
 
-                    button {
+                    (!in_archive).then(|| rsx!(button {
                         style: "color: red;",
                         prevent_default: "onclick",
                         r#type: "button",
                         onclick: move |_| {
-                            let path = format!("/api/delete{}/{}", cx.props.cur_path, entry.file_name);
+                            let path = with_base_path(&format!("/api/delete{}/{}", cx.props.cur_path, entry.file_name));
                             let update_state = cx.props.update_state.clone();
                             cx.spawn(async move {
                                 let resp = Request::post(path.as_str())
@@ -368,48 +677,344 @@ fn TableRow<'a>(cx: Scope<'a, DirEntryProps<'a>>) -> Element {
                             });
                         },
                         "Delete"
-                    }
+                    }))
                 }
             }
     })
 }
 
+/// Where clicking an entry leads to: sub-directories and archives are
+/// listed, media files open in the player page, other files are downloaded.
+/// Files inside archives are always downloaded.
+fn entry_href(cur_path: &str, entry: &DirEntry, in_archive: bool) -> String {
+    with_base_path(&entry_path(cur_path, entry, in_archive))
+}
+
+fn entry_path(cur_path: &str, entry: &DirEntry, in_archive: bool) -> String {
+    if entry.file_type == common::FileType::Directory || (!in_archive && is_archive(&entry.file_name)) {
+        format!("{}/{}", cur_path, entry.file_name)
+    } else if in_archive {
+        format!("/api/archive{}/{}", cur_path, entry.file_name)
+    } else if media_kind(&entry.file_name).is_some() {
+        format!("{}{}/{}", MEDIA_ROUTE, cur_path, entry.file_name)
+    } else if is_document(&entry.file_name) {
+        format!("{}{}/{}", DOCUMENT_ROUTE, cur_path, entry.file_name)
+    } else if is_markdown(&entry.file_name) {
+        format!("{}{}/{}", MARKDOWN_ROUTE, cur_path, entry.file_name)
+    } else if is_text(&entry.file_name) {
+        format!("{}{}/{}", TEXT_ROUTE, cur_path, entry.file_name)
+    } else {
+        format!("/api/static{}/{}", cur_path, entry.file_name)
+    }
+}
+
+fn ListingGrid<'a>(cx: Scope<'a, DirDescProps<'a>>) -> Element {
+    let cur_path = app_path(cx.props.cur_url).trim_end_matches(|c| c == '/');
+    let mut parent = "/";
+    if let Some(idx) = cur_path.rfind('/') {
+        if idx > 0 {
+            parent = &cur_path[..idx];
+        }
+    }
+    let parent = with_base_path(parent);
+    let in_archive = cx.props.dir_desc.in_archive;
+
+    cx.render(rsx! {
+        div {
+            class: "grid",
+
+            (!cur_path.is_empty()).then(|| rsx!(
+                a {
+                    class: "grid_cell",
+                    href: "{parent}",
+                    div { class: "grid_icon", "◄" }
+                    div { class: "grid_name", "Parent Directory" }
+                }
+            ))
+
+            cx.props.dir_desc.descendants.iter().map(|entry| {
+                let href = entry_href(cur_path, entry, in_archive);
+                let has_thumbnail = !in_archive
+                    && entry.file_type != common::FileType::Directory
+                    && (is_image(&entry.file_name)
+                        || media_kind(&entry.file_name) == Some(MediaKind::Video));
+                let icon = entry_icon(entry);
+                let thumbnail = with_base_path(&format!("/api/thumbnail{}/{}?size=256", cur_path, entry.file_name));
+                let cell = rsx!(
+                    if has_thumbnail {
+                        rsx!(img {
+                            class: "grid_thumbnail",
+                            src: "{thumbnail}",
+                            "loading": "lazy",
+                            alt: "{icon}",
+                        })
+                    } else {
+                        rsx!(div { class: "grid_icon", "{icon}" })
+                    }
+                    div { class: "grid_name", "{entry.file_name}" }
+                );
+
+                if opens_in_lightbox(entry, in_archive) {
+                    rsx!(a {
+                        key: "{cur_path}/{entry.file_name}",
+                        class: "grid_cell",
+                        href: "{href}",
+                        title: "{entry.file_name}",
+                        prevent_default: "onclick",
+                        onclick: move |_| cx.props.lightbox_state.set(Some(entry.file_name.clone())),
+                        cell
+                    })
+                } else {
+                    rsx!(a {
+                        key: "{cur_path}/{entry.file_name}",
+                        class: "grid_cell",
+                        href: "{href}",
+                        title: "{entry.file_name}",
+                        cell
+                    })
+                }
+            })
+        }
+    })
+}
+
+fn entry_icon(entry: &DirEntry) -> &'static str {
+    if entry.file_type == common::FileType::Directory {
+        "📁"
+    } else if is_archive(&entry.file_name) {
+        "🗜️"
+    } else if is_image(&entry.file_name) {
+        "🖼️"
+    } else {
+        match media_kind(&entry.file_name) {
+            Some(MediaKind::Video) => "🎬",
+            Some(MediaKind::Audio) => "🎵",
+            None => "📝",
+        }
+    }
+}
+
+/// Images are shown in the lightbox instead of navigating to the raw file,
+/// except for images inside archives which can only be downloaded.
+fn opens_in_lightbox(entry: &DirEntry, in_archive: bool) -> bool {
+    !in_archive && entry.file_type != common::FileType::Directory && is_image(&entry.file_name)
+}
+
+const EXTRACT_POLL_INTERVAL_MS: u32 = 1000;
+
+/// Starts extracting an archive on the server and waits for it to finish,
+/// showing the progress in the info dialog.
+async fn extract_archive(path: &str, info_state: &UseState<Option<String>>) -> Result<String, String> {
+    let url = with_base_path(&format!("/api/extract{}", path));
+    let resp = Request::post(url.as_str())
+        .send()
+        .await
+        .map_err(|e| e.to_string())?
+        .json::<JsonResponse>()
+        .await
+        .map_err(|e| e.to_string())?;
+    let target = match resp {
+        JsonResponse::Succeeded { msg } => msg.unwrap_or_default(),
+        JsonResponse::Failed { msg } => return Err(msg.unwrap_or_else(|| "Not Available!".to_string())),
+    };
+
+    loop {
+        info_state.set(Some(format!("Extracting {} into {}...", path, target)));
+        TimeoutFuture::new(EXTRACT_POLL_INTERVAL_MS).await;
+        let text = Request::get(url.as_str())
+            .send()
+            .await
+            .map_err(|e| e.to_string())?
+            .text()
+            .await
+            .map_err(|e| e.to_string())?;
+        let status = match serde_json::from_str::<ExtractStatus>(&text) {
+            Ok(status) => status,
+            Err(_) => match serde_json::from_str::<JsonResponse>(&text) {
+                Ok(JsonResponse::Failed { msg: Some(msg) }) => return Err(msg),
+                _ => return Err("Not Available!".to_string()),
+            },
+        };
+        match status {
+            ExtractStatus { running: true, .. } => continue,
+            ExtractStatus { error: Some(err), .. } => return Err(err),
+            ExtractStatus { entries, target, .. } => {
+                return Ok(format!("Extracted {} entries into {}", entries, target))
+            }
+        }
+    }
+}
+
+const VIEW_MODE_KEY: &str = "view_mode";
+
+/// Whether the listing is shown as a thumbnail grid, remembered across
+/// directories in local storage.
+fn load_grid_view() -> bool {
+    web_sys::window()
+        .and_then(|window| window.local_storage().ok().flatten())
+        .and_then(|storage| storage.get_item(VIEW_MODE_KEY).ok().flatten())
+        .map(|mode| mode == "grid")
+        .unwrap_or(false)
+}
+
+fn save_grid_view(grid_view: bool) {
+    if let Some(storage) = web_sys::window().and_then(|window| window.local_storage().ok().flatten())
+    {
+        let mode = if grid_view { "grid" } else { "table" };
+        let _ = storage.set_item(VIEW_MODE_KEY, mode);
+    }
+}
+
 #[inline_props]
-fn QRCode<'a>(cx: Scope, data: &'a str) -> Element {
+fn MediaPlayer(cx: Scope, path: String) -> Element {
+    let fut = use_future(&cx, (path,), |(path,)| async move {
+        let media = Request::get(&with_base_path(&format!("/api/media{}", path)))
+            .send()
+            .await
+            .unwrap()
+            .json::<MediaDesc>()
+            .await;
+        if let Ok(MediaDesc {
+            hls_url: Some(hls_url),
+            ..
+        }) = &media
+        {
+            attach_hls("media_player", &with_base_path(hls_url));
+        }
+        media
+    });
+
+    let router = use_router(&cx);
+
+    cx.render(match fut.value() {
+        Some(Ok(media)) => {
+            let src = with_base_path(&format!("/api/static{}", media.path));
+            let dir = match media.path.rfind('/') {
+                Some(idx) if idx > 0 => &media.path[..idx],
+                _ => "/",
+            };
+            let dir = with_base_path(dir);
+            let duration = media.duration.map(format_duration).unwrap_or_default();
+            let next = media.next.clone();
+            let play_next = move |_: MediaEvent| {
+                if let Some(next) = &next {
+                    router.push_route(&with_base_path(&format!("{}{}", MEDIA_ROUTE, next)), None, None);
+                }
+            };
+            let tracks = media.subtitles.iter().map(|subtitle| {
+                let lang = subtitle.lang.as_deref().unwrap_or("");
+                let src = with_base_path(&subtitle.url);
+                rsx!(track {
+                    key: "{subtitle.url}",
+                    kind: "subtitles",
+                    src: "{src}",
+                    srclang: "{lang}",
+                    label: "{subtitle.label}",
+                })
+            });
+
+            rsx!(
+                div {
+                    class: "title",
+                    a { href: "{dir}", "◄ " }
+                    "{media.path}"
+                }
+
+                if media.hls_url.is_some() {
+                    rsx!(video {
+                        id: "media_player",
+                        class: "media_player",
+                        controls: "true",
+                        autoplay: "true",
+                        onended: play_next,
+                        tracks
+                    })
+                } else if media.kind == MediaKind::Video {
+                    rsx!(video {
+                        class: "media_player",
+                        src: "{src}",
+                        controls: "true",
+                        autoplay: "true",
+                        onended: play_next,
+                        tracks
+                    })
+                } else {
+                    rsx!(audio {
+                        class: "media_player",
+                        src: "{src}",
+                        controls: "true",
+                        autoplay: "true",
+                        onended: play_next,
+                    })
+                }
+
+                div {
+                    class: "media_info",
+                    div { "Duration: {duration}" }
+                    media.codecs.iter().map(|codec| rsx!(div { key: "{codec}", "{codec}" }))
+                }
+
+                div {
+                    class: "media_nav",
+                    media.prev.as_ref().map(|prev| {
+                        let href = with_base_path(&format!("{}{}", MEDIA_ROUTE, prev));
+                        rsx!(a { href: "{href}", "◄ Previous" })
+                    })
+                    media.next.as_ref().map(|next| {
+                        let href = with_base_path(&format!("{}{}", MEDIA_ROUTE, next));
+                        rsx!(a { href: "{href}", "Next ►" })
+                    })
+                }
+            )
+        }
+        Some(Err(err)) => rsx!(
+            p {
+                class: "raw_message",
+                "Error: {err}"
+            }
+        ),
+        _ => rsx!(
+            p {
+                class: "raw_message",
+                "Loading..."
+            }
+        ),
+    })
+}
+
+/// SVG of a QR code encoding `data`.
+fn qr_svg(data: &str) -> String {
     let qrcode = QRBuilder::new(data.to_string())
         .ecl(ECL::L)
         .version(Version::V09)
         .build();
 
-    let svg = SvgBuilder::default()
+    SvgBuilder::default()
         .shape(Shape::RoundedSquare)
-        .to_str(&qrcode.unwrap());
-
-    cx.render(rsx!(div {
-        style: "
-            position: fixed;
-            width: 360px;
-            height: 360px;
-            left: 50%;
-            top: 50%;
-            margin-left: -180px;
-            margin-top: -180px;
-            z-index: 100;
-            border: dotted 2px #000;
-        ",
-        dangerous_inner_html: "{svg}",
-    }))
+        .to_str(&qrcode.unwrap())
 }
 
-fn get_url_base(url: &Url) -> String {
-    match url.host_str() {
+/// Returns the scheme://host[:port] part of `url` followed by the base path,
+/// with the host replaced by the server's mDNS host name (e.g.
+/// `fileserver.local`) when it is advertised, so that links shared via QR
+/// codes work on other devices on the LAN. Otherwise the public URL the server
+/// derived from the headers of a reverse proxy is used if known.
+fn get_url_base(url: &Url, config: Option<&ServerConfig>) -> String {
+    let mdns_host = config.and_then(|config| config.mdns_host.as_deref());
+    if mdns_host.is_none() {
+        if let Some(public_url) = config.and_then(|config| config.public_url.clone()) {
+            return public_url;
+        }
+    }
+    match mdns_host.or_else(|| url.host_str()) {
         Some(host_str) => {
             let scheme = url.scheme();
             let port = url.port().unwrap_or_default();
+            let base_path = BASE_PATH.with(|base_path| base_path.clone());
             if port > 0 {
-                format!("{}://{}:{}", scheme, host_str, port)
+                format!("{}://{}:{}{}", scheme, host_str, port, base_path)
             } else {
-                format!("{}://{}", scheme, host_str)
+                format!("{}://{}{}", scheme, host_str, base_path)
             }
         }
         None => "".to_string(),
@@ -470,6 +1075,44 @@ fn InfoDialog<'a>(cx: Scope<'a>, info_state: &'a UseState<Option<String>>) -> El
     })
 }
 
+fn format_media_info(info: &MediaInfo) -> String {
+    let mut lines = vec![match &info.format_long_name {
+        Some(long_name) => format!("Format: {} ({})", long_name, info.format_name),
+        None => format!("Format: {}", info.format_name),
+    }];
+    if let Some(duration) = info.duration {
+        lines.push(format!("Duration: {}", format_duration(duration)));
+    }
+    if let Some(bit_rate) = info.bit_rate {
+        lines.push(format!("Bit rate: {:.0} kb/s", bit_rate as f64 / 1000.0));
+    }
+    if let Some(size) = info.size {
+        lines.push(format!("Size: {}", format_bytes(size)));
+    }
+    lines.push(String::new());
+    lines.push("Streams:".to_string());
+    for stream in &info.streams {
+        let mut line = format!("  #{} {}", stream.index, stream.summary());
+        if let Some(bit_rate) = stream.bit_rate {
+            line.push_str(&format!(" {:.0} kb/s", bit_rate as f64 / 1000.0));
+        }
+        if let Some(title) = &stream.title {
+            line.push_str(&format!(" \"{}\"", title));
+        }
+        lines.push(line);
+    }
+    lines.join("\n")
+}
+
+fn format_duration(secs: f64) -> String {
+    let secs = secs as u64;
+    if secs >= 3600 {
+        format!("{}:{:02}:{:02}", secs / 3600, secs % 3600 / 60, secs % 60)
+    } else {
+        format!("{}:{:02}", secs / 60, secs % 60)
+    }
+}
+
 fn format_bytes(bytes: u64) -> String {
     if bytes >= 1024 * 1024 * 1024 {
         format!("{:.2}G", bytes as f64 / (1024.0 * 1024.0 * 1024.0))
@@ -529,18 +1172,23 @@ fn format_bytes(bytes: u64) -> String {
 #[derive(Props)]
 pub struct DirDescProps<'a> {
     cur_url: &'a Url,
+    // not known until the config has been loaded
+    #[props(!optional)]
+    server_config: Option<&'a ServerConfig>,
     dir_desc: &'a DirDesc,
     update_state: &'a UseState<bool>,
     info_state: &'a UseState<Option<String>>,
+    lightbox_state: &'a UseState<Option<String>>,
 }
 
 #[derive(Props)]
 struct DirEntryProps<'a> {
     index: usize,
-    url_base: &'a str,
     entry: &'a DirEntry,
     cur_path: &'a str,
     update_state: &'a UseState<bool>,
-    qrcode_state: &'a UseState<Option<String>>,
+    share_state: &'a UseState<Option<String>>,
     info_state: &'a UseState<Option<String>>,
+    lightbox_state: &'a UseState<Option<String>>,
+    in_archive: bool,
 }
@@ -0,0 +1,107 @@
+use crate::{with_base_path, TEXT_ROUTE};
+use common::{DirDesc, FileType, JsonResponse, MarkdownDoc, MARKDOWN_ROUTE};
+use dioxus::prelude::*;
+use gloo_net::http::Request;
+
+/// Names of the files shown below a directory listing, in order of preference.
+const README_NAMES: [&str; 2] = ["readme.md", "index.md"];
+
+/// Shows a markdown file rendered by the server, with a link to its source in
+/// the text viewer.
+#[inline_props]
+pub fn MarkdownViewer(cx: Scope, path: String) -> Element {
+    let fut = use_future(&cx, (path,), |(path,)| fetch_markdown(path));
+
+    let dir = match path.rfind('/') {
+        Some(idx) if idx > 0 => &path[..idx],
+        _ => "/",
+    };
+    let dir = with_base_path(dir);
+    let source = with_base_path(&format!("{}{}", TEXT_ROUTE, path));
+    let raw = with_base_path(&format!("/api/static{}", path));
+
+    let body = match fut.value() {
+        Some(Ok(doc)) => rsx!(div {
+            class: "markdown",
+            dangerous_inner_html: "{doc.html}",
+        }),
+        Some(Err(err)) => rsx!(p {
+            class: "raw_message",
+            "Error: {err}"
+        }),
+        None => rsx!(p {
+            class: "raw_message",
+            "Loading..."
+        }),
+    };
+
+    cx.render(rsx! {
+        div {
+            class: "title",
+            a { href: "{dir}", "◄ " }
+            "{path}"
+        }
+
+        div {
+            class: "text_toolbar",
+            a { href: "{source}", "Source" }
+            a { href: "{raw}", "Raw" }
+        }
+
+        body
+    })
+}
+
+/// The README.md (or index.md) of a directory rendered below its listing,
+/// nothing if the directory has none.
+#[inline_props]
+pub fn Readme<'a>(cx: Scope, cur_path: &'a str, dir_desc: &'a DirDesc) -> Element {
+    let readme = README_NAMES.iter().find_map(|readme| {
+        dir_desc
+            .descendants
+            .iter()
+            .find(|entry| {
+                entry.file_type != FileType::Directory
+                    && entry.file_name.eq_ignore_ascii_case(readme)
+            })
+            .map(|entry| format!("{}/{}", cur_path, entry.file_name))
+    });
+    let fut = use_future(&cx, (&readme,), |(readme,)| async move {
+        match readme {
+            Some(path) => fetch_markdown(path).await.ok(),
+            None => None,
+        }
+    });
+
+    match fut.value() {
+        Some(Some(doc)) => {
+            let href = with_base_path(&format!("{}{}", MARKDOWN_ROUTE, doc.path));
+            cx.render(rsx!(div {
+                class: "markdown readme",
+                div {
+                    class: "readme_title",
+                    a { href: "{href}", "{doc.path}" }
+                }
+                div { dangerous_inner_html: "{doc.html}" }
+            }))
+        }
+        _ => None,
+    }
+}
+
+async fn fetch_markdown(path: String) -> Result<MarkdownDoc, String> {
+    let text = Request::get(&with_base_path(&format!("/api/markdown{}", path)))
+        .send()
+        .await
+        .map_err(|e| e.to_string())?
+        .text()
+        .await
+        .map_err(|e| e.to_string())?;
+    match serde_json::from_str::<MarkdownDoc>(&text) {
+        Ok(doc) => Ok(doc),
+        Err(_) => match serde_json::from_str::<JsonResponse>(&text) {
+            Ok(JsonResponse::Failed { msg: Some(msg) }) => Err(msg),
+            _ => Err("Not Available!".to_string()),
+        },
+    }
+}
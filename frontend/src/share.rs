@@ -0,0 +1,253 @@
+use crate::dropbox::DropBoxList;
+use crate::{fetch_server_config, get_url_base, qr_svg, with_base_path};
+use common::{CreateShare, JsonResponse, ShareInfo};
+use dioxus::{events::FormEvent, prelude::*};
+use gloo_net::http::Request;
+
+/// Default lifetime of a new share link.
+const DEFAULT_EXPIRES_IN_HOURS: &str = "24";
+
+/// Creates a share link for a file or directory with an optional expiry,
+/// download limit and password, and shows the link with its QR code.
+#[inline_props]
+pub fn ShareDialog<'a>(
+    cx: Scope,
+    url_base: String,
+    path: String,
+    share_state: &'a UseState<Option<String>>,
+) -> Element {
+    let created = use_state(&cx, || None as Option<ShareInfo>);
+    let error = use_state(&cx, || None as Option<String>);
+
+    let onsubmit = move |ev: FormEvent| {
+        let number = |name: &str| {
+            ev.values
+                .get(name)
+                .and_then(|value| value.trim().parse::<u64>().ok())
+                .filter(|value| *value > 0)
+        };
+        let req = CreateShare {
+            path: path.clone(),
+            expires_in_hours: number("expires_in_hours"),
+            max_downloads: number("max_downloads"),
+            password: ev
+                .values
+                .get("password")
+                .filter(|password| !password.is_empty())
+                .cloned(),
+        };
+        let (created, error) = (created.clone(), error.clone());
+        cx.spawn(async move {
+            match create_share(&req).await {
+                Ok(info) => created.set(Some(info)),
+                Err(err) => error.set(Some(err)),
+            }
+        });
+    };
+
+    let body = match created.get() {
+        Some(info) => {
+            let link = format!("{}/s/{}", url_base.trim_end_matches('/'), info.token);
+            let svg = qr_svg(&link);
+            let expires = match &info.expires {
+                Some(expires) => format!("Expires at {}", expires),
+                None => "Never expires".to_string(),
+            };
+            rsx!(
+                input {
+                    class: "share_link",
+                    r#type: "text",
+                    readonly: "true",
+                    value: "{link}",
+                }
+                div { class: "share_qrcode", dangerous_inner_html: "{svg}" }
+                div { class: "text_summary", "{expires}" }
+            )
+        }
+        None => rsx!(
+            form {
+                prevent_default: "onsubmit",
+                onsubmit: onsubmit,
+                label {
+                    "Expires in hours (empty for never) "
+                    input {
+                        r#type: "number",
+                        name: "expires_in_hours",
+                        min: "1",
+                        value: "{DEFAULT_EXPIRES_IN_HOURS}",
+                    }
+                }
+                label {
+                    "Max downloads (empty for unlimited) "
+                    input {
+                        r#type: "number",
+                        name: "max_downloads",
+                        min: "1",
+                    }
+                }
+                label {
+                    "Password (optional) "
+                    input {
+                        r#type: "password",
+                        name: "password",
+                    }
+                }
+                button { "Create Link" }
+            }
+            error.get().as_ref().map(|err| rsx!(
+                div { class: "card_error", "{err}" }
+            ))
+        ),
+    };
+
+    cx.render(rsx! {
+        div {
+            class: "share_dialog",
+            div { class: "share_title", "Share {path}" }
+            body
+            button {
+                prevent_default: "onclick",
+                r#type: "button",
+                onclick: move |_| share_state.set(None),
+                "Close"
+            }
+        }
+    })
+}
+
+/// Lists the share links that are still valid, with buttons to revoke them.
+pub fn SharesPage(cx: Scope) -> Element {
+    let route = dioxus_router::use_route(&cx);
+    let config_fut = use_future(&cx, (), |_| fetch_server_config());
+    let url_base = get_url_base(route.url(), config_fut.value());
+    let url_base = url_base.as_str();
+    let home = with_base_path("/");
+    let fut = use_future(&cx, (), |_| fetch_shares());
+    let error = use_state(&cx, || None as Option<String>);
+
+    let update_state = use_state(&cx, || false);
+    if *update_state.get() {
+        update_state.set(false);
+        fut.restart();
+    }
+
+    let revoke = move |token: String| {
+        let (error, update_state) = (error.clone(), update_state.clone());
+        cx.spawn(async move {
+            let result = Request::delete(&with_base_path(&format!("/api/shares/{}", token)))
+                .send()
+                .await
+                .map_err(|e| e.to_string());
+            let result = match result {
+                Ok(resp) => match resp.json::<JsonResponse>().await {
+                    Ok(JsonResponse::Failed { msg }) => Err(msg.unwrap_or_default()),
+                    _ => Ok(()),
+                },
+                Err(err) => Err(err),
+            };
+            match result {
+                Ok(()) => update_state.set(true),
+                Err(err) => error.set(Some(err)),
+            }
+        });
+    };
+
+    let body = match fut.value() {
+        Some(Ok(shares)) if shares.is_empty() => rsx!(p {
+            class: "raw_message",
+            "There are no share links."
+        }),
+        Some(Ok(shares)) => rsx!(table {
+            thead {
+                tr {
+                    td { "Path" }
+                    td { "Link" }
+                    td { "Created" }
+                    td { "Expires" }
+                    td { "Downloads" }
+                    td { "Password" }
+                    td { "Operation" }
+                }
+            }
+            shares.iter().map(|share| {
+                let link = format!("{}/s/{}", url_base, share.token);
+                let dir = with_base_path(&share.path);
+                let expires = share.expires.clone().unwrap_or_else(|| "Never".to_string());
+                let downloads = match share.max_downloads {
+                    Some(max) => format!("{}/{}", share.downloads, max),
+                    None => share.downloads.to_string(),
+                };
+                let password = if share.has_password { "Yes" } else { "No" };
+                let token = share.token.clone();
+                rsx!(tr {
+                    key: "{share.token}",
+                    td { a { href: "{dir}", "{share.path}" } }
+                    td { a { href: "{link}", "{link}" } }
+                    td { "{share.created}" }
+                    td { "{expires}" }
+                    td { "{downloads}" }
+                    td { "{password}" }
+                    td {
+                        button {
+                            style: "color: red;",
+                            prevent_default: "onclick",
+                            r#type: "button",
+                            onclick: move |_| revoke(token.clone()),
+                            "Revoke"
+                        }
+                    }
+                })
+            })
+        }),
+        Some(Err(err)) => rsx!(p {
+            class: "raw_message",
+            "Error: {err}"
+        }),
+        None => rsx!(p {
+            class: "raw_message",
+            "Loading..."
+        }),
+    };
+
+    cx.render(rsx! {
+        div {
+            class: "title",
+            a { href: "{home}", "◄ " }
+            "Share Links"
+        }
+        error.get().as_ref().map(|err| rsx!(
+            div { class: "card_error", "{err}" }
+        ))
+        body
+        DropBoxList { url_base: url_base.to_string() }
+    })
+}
+
+async fn create_share(req: &CreateShare) -> Result<ShareInfo, String> {
+    let text = Request::post(&with_base_path("/api/shares"))
+        .json(req)
+        .map_err(|e| e.to_string())?
+        .send()
+        .await
+        .map_err(|e| e.to_string())?
+        .text()
+        .await
+        .map_err(|e| e.to_string())?;
+    match serde_json::from_str::<ShareInfo>(&text) {
+        Ok(info) => Ok(info),
+        Err(_) => match serde_json::from_str::<JsonResponse>(&text) {
+            Ok(JsonResponse::Failed { msg: Some(msg) }) => Err(msg),
+            _ => Err("Not Available!".to_string()),
+        },
+    }
+}
+
+async fn fetch_shares() -> Result<Vec<ShareInfo>, String> {
+    Request::get(&with_base_path("/api/shares"))
+        .send()
+        .await
+        .map_err(|e| e.to_string())?
+        .json::<Vec<ShareInfo>>()
+        .await
+        .map_err(|e| e.to_string())
+}
@@ -0,0 +1,309 @@
+use crate::js::render_text;
+use crate::{format_bytes, with_base_path, EDIT_ROUTE};
+use common::{JsonResponse, TextChunk};
+use dioxus::prelude::*;
+use gloo_net::http::Request;
+use gloo_timers::future::TimeoutFuture;
+
+const FOLLOW_INTERVAL_MS: u32 = 2000;
+/// Oldest lines are dropped when following a log grows the view past this.
+const MAX_BUFFER: usize = 4 * 1024 * 1024;
+
+struct TextView {
+    encoding: String,
+    size: u64,
+    end: u64,
+    text: String,
+    /// The view doesn't start at the beginning of the file, so the line
+    /// numbers are relative and not linkable
+    partial: bool,
+}
+
+impl TextView {
+    fn new(chunk: TextChunk, partial: bool) -> Self {
+        TextView {
+            encoding: chunk.encoding,
+            size: chunk.size,
+            end: chunk.end,
+            text: chunk.text,
+            partial,
+        }
+    }
+
+    fn append(&mut self, chunk: TextChunk) {
+        self.text.push_str(&chunk.text);
+        self.size = chunk.size;
+        self.end = chunk.end;
+
+        if self.text.len() > MAX_BUFFER {
+            let mut cut = self.text.len() - MAX_BUFFER;
+            while !self.text.is_char_boundary(cut) {
+                cut += 1;
+            }
+            let cut = self.text[cut..]
+                .find('\n')
+                .map(|pos| cut + pos + 1)
+                .unwrap_or(cut);
+            self.text.drain(..cut);
+            self.partial = true;
+        }
+    }
+
+    fn render(&self, path: &str, scroll_to_end: bool) {
+        render_text(
+            "text_code",
+            &self.text,
+            language(path).unwrap_or(""),
+            !self.partial,
+            scroll_to_end,
+        );
+    }
+}
+
+/// Shows a text file with line numbers and syntax highlighting, paging
+/// through large files chunk by chunk, with a "tail -f" like follow mode for
+/// log files that keep growing.
+#[inline_props]
+pub fn TextViewer(cx: Scope, path: String) -> Element {
+    let view = use_ref(&cx, || None as Option<TextView>);
+    let error = use_state(&cx, || None as Option<String>);
+    // id of the running follow loop, so that a loop stopped and restarted
+    // within one interval doesn't keep running next to the new one
+    let follow = use_state(&cx, || None as Option<u32>);
+    let follow_count = use_ref(&cx, || 0_u32);
+
+    let (load_view, load_error) = (view.clone(), error.clone());
+    use_future(&cx, (path,), move |(path,)| async move {
+        match fetch_chunk(&path, "offset=0").await {
+            Ok(chunk) => {
+                let text_view = TextView::new(chunk, false);
+                text_view.render(&path, false);
+                load_view.set(Some(text_view));
+            }
+            Err(err) => load_error.set(Some(err)),
+        }
+    });
+
+    let (follow_view, follow_error, follow_path) = (view.clone(), error.clone(), path.clone());
+    let follow_state = follow.clone();
+    use_future(&cx, (follow.get(),), move |(id,)| async move {
+        let id = match id {
+            Some(id) => id,
+            None => return,
+        };
+        while *follow_state.current() == Some(id) {
+            TimeoutFuture::new(FOLLOW_INTERVAL_MS).await;
+            let (end, encoding) = match &*follow_view.read() {
+                Some(view) => (view.end, view.encoding.clone()),
+                None => return,
+            };
+
+            let query = format!("offset={}&encoding={}", end, encoding);
+            let chunk = match fetch_chunk(&follow_path, &query).await {
+                Ok(chunk) => chunk,
+                Err(err) => {
+                    follow_error.set(Some(err));
+                    return;
+                }
+            };
+            if chunk.size < end {
+                // truncated or rotated, start over from its end
+                if let Ok(chunk) = fetch_chunk(&follow_path, "tail=true").await {
+                    follow_view.set(Some(TextView::new(chunk, true)));
+                }
+            } else if !chunk.text.is_empty() {
+                if let Some(view) = follow_view.write().as_mut() {
+                    view.append(chunk);
+                }
+            } else {
+                continue;
+            }
+            if let Some(view) = &*follow_view.read() {
+                view.render(&follow_path, true);
+            }
+        }
+    });
+
+    let dir = match path.rfind('/') {
+        Some(idx) if idx > 0 => &path[..idx],
+        _ => "/",
+    };
+    let dir = with_base_path(dir);
+    let raw = with_base_path(&format!("/api/static{}", path));
+    let edit = with_base_path(&format!("{}{}", EDIT_ROUTE, path));
+
+    let summary = view.read().as_ref().map(|view| {
+        let status = if view.end < view.size {
+            format!("{} of {}", format_bytes(view.end), format_bytes(view.size))
+        } else {
+            format_bytes(view.size)
+        };
+        (
+            format!("{}, {}", view.encoding, status),
+            view.end < view.size,
+        )
+    });
+    let follow_label = if follow.get().is_some() {
+        "Stop Following"
+    } else {
+        "Follow"
+    };
+
+    let load_more = move |_| {
+        let (view, error, path) = (view.clone(), error.clone(), path.clone());
+        cx.spawn(async move {
+            let (end, encoding) = match &*view.read() {
+                Some(view) => (view.end, view.encoding.clone()),
+                None => return,
+            };
+            let query = format!("offset={}&encoding={}", end, encoding);
+            match fetch_chunk(&path, &query).await {
+                Ok(chunk) => {
+                    if let Some(view) = view.write().as_mut() {
+                        view.append(chunk);
+                        view.render(&path, false);
+                    }
+                }
+                Err(err) => error.set(Some(err)),
+            }
+        });
+    };
+
+    let jump_to_end = move |_| {
+        let (view, error, path) = (view.clone(), error.clone(), path.clone());
+        cx.spawn(async move {
+            match fetch_chunk(&path, "tail=true").await {
+                Ok(chunk) => {
+                    let partial = chunk.offset > 0;
+                    let text_view = TextView::new(chunk, partial);
+                    text_view.render(&path, true);
+                    view.set(Some(text_view));
+                }
+                Err(err) => error.set(Some(err)),
+            }
+        });
+    };
+
+    let toggle_follow = move |_| {
+        if follow.get().is_some() {
+            follow.set(None);
+        } else {
+            *follow_count.write() += 1;
+            follow.set(Some(*follow_count.read()));
+        }
+    };
+
+    cx.render(rsx! {
+        div {
+            class: "title",
+            a { href: "{dir}", "◄ " }
+            "{path}"
+        }
+
+        error.get().as_ref().map(|err| rsx!(
+            p {
+                class: "raw_message",
+                "Error: {err}"
+            }
+        ))
+
+        summary.map(|(summary, has_more)| rsx!(
+            div {
+                class: "text_toolbar",
+                span { class: "text_summary", "{summary}" }
+                a { href: "{raw}", "Raw" }
+                a { href: "{edit}", "Edit" }
+                has_more.then(|| rsx!(
+                    button {
+                        prevent_default: "onclick",
+                        r#type: "button",
+                        onclick: load_more,
+                        "Load More"
+                    }
+                    button {
+                        prevent_default: "onclick",
+                        r#type: "button",
+                        onclick: jump_to_end,
+                        "Jump to End"
+                    }
+                ))
+                button {
+                    prevent_default: "onclick",
+                    r#type: "button",
+                    onclick: toggle_follow,
+                    "{follow_label}"
+                }
+            }
+        )),
+
+        (error.get().is_none() && view.read().is_none()).then(|| rsx!(
+            p {
+                class: "raw_message",
+                "Loading..."
+            }
+        ))
+
+        // filled in by render_text, dioxus doesn't manage the content
+        div {
+            class: "text_view",
+            pre { id: "text_code_lines", class: "text_lines" }
+            pre { code { id: "text_code" } }
+        }
+    })
+}
+
+async fn fetch_chunk(path: &str, query: &str) -> Result<TextChunk, String> {
+    let text = Request::get(&with_base_path(&format!("/api/text{}?{}", path, query)))
+        .send()
+        .await
+        .map_err(|e| e.to_string())?
+        .text()
+        .await
+        .map_err(|e| e.to_string())?;
+    match serde_json::from_str::<TextChunk>(&text) {
+        Ok(chunk) => Ok(chunk),
+        Err(_) => match serde_json::from_str::<JsonResponse>(&text) {
+            Ok(JsonResponse::Failed { msg: Some(msg) }) => Err(msg),
+            _ => Err("Not Available!".to_string()),
+        },
+    }
+}
+
+/// highlight.js language of a file, by its extension.
+fn language(path: &str) -> Option<&'static str> {
+    let file_name = path.rsplit('/').next().unwrap_or(path);
+    if file_name == "Makefile" {
+        return Some("makefile");
+    } else if file_name == "Dockerfile" {
+        return Some("dockerfile");
+    }
+
+    let ext = file_name.rsplit_once('.')?.1.to_ascii_lowercase();
+    Some(match ext.as_str() {
+        "rs" => "rust",
+        "go" => "go",
+        "c" | "h" => "c",
+        "cc" | "cpp" | "hpp" => "cpp",
+        "java" => "java",
+        "kt" => "kotlin",
+        "swift" => "swift",
+        "py" => "python",
+        "rb" => "ruby",
+        "php" => "php",
+        "pl" => "perl",
+        "lua" => "lua",
+        "js" | "jsx" => "javascript",
+        "ts" | "tsx" => "typescript",
+        "json" => "json",
+        "html" | "htm" | "xml" => "xml",
+        "css" => "css",
+        "md" | "markdown" => "markdown",
+        "yaml" | "yml" => "yaml",
+        "toml" | "ini" | "conf" | "cfg" => "ini",
+        "gradle" => "gradle",
+        "sh" | "bash" | "zsh" | "fish" => "bash",
+        "sql" => "sql",
+        "diff" | "patch" => "diff",
+        _ => return None,
+    })
+}
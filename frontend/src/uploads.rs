@@ -0,0 +1,169 @@
+use crate::format_bytes;
+use crate::js::{cancel_upload, cancel_uploads, clear_uploads, init_uploader, set_upload_limit};
+use common::ServerConfig;
+use dioxus::prelude::*;
+use serde::Deserialize;
+use wasm_bindgen::closure::Closure;
+
+#[derive(Deserialize, Clone, Copy, PartialEq)]
+enum UploadStatus {
+    Queued,
+    Uploading,
+    Done,
+    Failed,
+    Cancelled,
+}
+
+#[derive(Deserialize, Clone, PartialEq)]
+struct UploadItem {
+    id: u32,
+    /// Path relative to the directory it is uploaded to
+    name: String,
+    size: u64,
+    loaded: u64,
+    status: UploadStatus,
+    error: Option<String>,
+}
+
+/// State of all uploads, sent by the uploader in `js.rs` whenever it changes.
+#[derive(Deserialize)]
+struct UploadUpdate {
+    items: Vec<UploadItem>,
+    /// The last queued upload just ended
+    finished: bool,
+}
+
+#[derive(Props)]
+pub struct UploadsProps<'a> {
+    // not known until the config has been loaded
+    #[props(!optional)]
+    server_config: Option<&'a ServerConfig>,
+    update_state: &'a UseState<bool>,
+}
+
+/// Progress of the uploads started by dropping files onto the listing or
+/// picking them in the upload card. The uploads themselves run in JS, so they
+/// survive navigating between directories. The listing is refreshed when
+/// they are done. Files over the upload limits of the server are rejected
+/// without sending them.
+pub fn Uploads<'a>(cx: Scope<'a, UploadsProps<'a>>) -> Element {
+    let (server_config, update_state) = (cx.props.server_config, cx.props.update_state);
+    let uploads = use_state(&cx, Vec::<UploadItem>::new);
+    // dropping the callback on unmount makes the uploader stop calling it
+    let _on_update = use_ref(&cx, || {
+        let (uploads, update_state) = (uploads.clone(), (*update_state).clone());
+        let on_update = Closure::wrap(Box::new(move |json: String| {
+            if let Ok(update) = serde_json::from_str::<UploadUpdate>(&json) {
+                uploads.set(update.items);
+                if update.finished {
+                    update_state.set(true);
+                }
+            }
+        }) as Box<dyn FnMut(String)>);
+        init_uploader(&on_update);
+        on_update
+    });
+
+    // each file is uploaded in a request of its own
+    let max_size = server_config
+        .and_then(|config| {
+            let limits = &config.upload_limits;
+            [limits.max_file_size, limits.max_request_size]
+                .into_iter()
+                .flatten()
+                .min()
+        })
+        .unwrap_or(0);
+    set_upload_limit(max_size as f64);
+
+    let items = uploads.get();
+    if items.is_empty() {
+        return None;
+    }
+
+    let active = items
+        .iter()
+        .filter(|item| matches!(item.status, UploadStatus::Queued | UploadStatus::Uploading))
+        .count();
+    let done = items
+        .iter()
+        .filter(|item| item.status == UploadStatus::Done)
+        .count();
+    let total_size: u64 = items
+        .iter()
+        .filter(|item| item.status != UploadStatus::Cancelled)
+        .map(|item| item.size)
+        .sum();
+    let total_loaded: u64 = items
+        .iter()
+        .filter(|item| item.status != UploadStatus::Cancelled)
+        .map(|item| item.loaded.min(item.size))
+        .sum();
+    let summary = format!(
+        "{}/{} file(s) uploaded, {} of {}",
+        done,
+        items.len(),
+        format_bytes(total_loaded),
+        format_bytes(total_size)
+    );
+
+    cx.render(rsx! {
+        div {
+            class: "uploads",
+            div {
+                class: "upload_summary",
+                progress {
+                    value: "{total_loaded}",
+                    max: "{total_size}",
+                }
+                span { "{summary}" }
+                (active > 0).then(|| rsx!(button {
+                    prevent_default: "onclick",
+                    r#type: "button",
+                    onclick: move |_| cancel_uploads(),
+                    "Cancel All"
+                })),
+                (active < items.len()).then(|| rsx!(button {
+                    prevent_default: "onclick",
+                    r#type: "button",
+                    onclick: move |_| clear_uploads(),
+                    "Clear"
+                }))
+            }
+
+            items.iter().map(|item| {
+                let id = item.id;
+                let status = match (item.status, &item.error) {
+                    (UploadStatus::Queued, _) => "Queued".to_string(),
+                    (UploadStatus::Uploading, _) => format!(
+                        "{} of {}",
+                        format_bytes(item.loaded.min(item.size)),
+                        format_bytes(item.size)
+                    ),
+                    (UploadStatus::Done, _) => "Done".to_string(),
+                    (UploadStatus::Failed, Some(err)) => format!("Failed: {}", err),
+                    (UploadStatus::Failed, None) => "Failed".to_string(),
+                    (UploadStatus::Cancelled, _) => "Cancelled".to_string(),
+                };
+                let cancellable =
+                    matches!(item.status, UploadStatus::Queued | UploadStatus::Uploading);
+                rsx!(div {
+                    key: "{id}",
+                    class: "upload_row",
+                    span { class: "upload_name", title: "{item.name}", "{item.name}" }
+                    progress {
+                        value: "{item.loaded}",
+                        max: "{item.size}",
+                    }
+                    span { class: "upload_status", "{status}" }
+                    cancellable.then(|| rsx!(button {
+                        prevent_default: "onclick",
+                        r#type: "button",
+                        onclick: move |_| cancel_upload(id),
+                        "Cancel"
+                    }))
+                })
+            })
+        }
+    })
+}